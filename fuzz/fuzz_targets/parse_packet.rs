@@ -0,0 +1,43 @@
+//! Parse arbitrary bytes as a response and interpret whatever parses, none of which may panic
+//! however the bytes are crafted. Run with `cargo fuzz run parse_packet`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nippy::protocol::{
+    parse_timestamp, ConstPackedSizeBytes, ExtensionField, Packet, RawExtensionField, ReadBytes,
+    TimestampSelector,
+};
+use nippy::Instant;
+
+const SELECTORS: [TimestampSelector; 3] =
+    [TimestampSelector::Transmit, TimestampSelector::Receive, TimestampSelector::Reference];
+
+fuzz_target!(|data: &[u8]| {
+    for &selector in &SELECTORS {
+        let _ = parse_timestamp(data, selector);
+    }
+    let packet = match (&data[..]).read_bytes::<Packet>() {
+        Ok(packet) => packet,
+        Err(_) => return,
+    };
+    let extensions = &data[Packet::PACKED_SIZE_BYTES.min(data.len())..];
+    if let Ok(fields) = RawExtensionField::parse_all(extensions) {
+        for field in &fields {
+            let _ = ExtensionField::decode(field);
+        }
+    }
+
+    // The timestamps are interpreted relative to pivots as far apart as instants allow.
+    let pivots = [Instant::now(), Instant::new(i64::MIN, -999_999_999), Instant::new(i64::MAX, 0)];
+    for &pivot in &pivots {
+        for &selector in &SELECTORS {
+            let _ = packet.timestamp(selector).to_instant_near(pivot);
+        }
+        let _ = packet.reference_age(pivot);
+        let _ = packet.leap_second_date(pivot);
+    }
+    let _ = Instant::from(packet.root_delay);
+    let _ = packet.run_sanity_tests(packet.origin_timestamp);
+    let _ = (packet.hops(), packet.precision_secs(), packet.header());
+});
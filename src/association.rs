@@ -0,0 +1,45 @@
+//! The reachability of a server over the requests made to it, as tracked by an NTP association.
+
+/// The reachability register of a server, as described in section 13 of RFC 5905.
+///
+/// Each request shifts the 8-bit register left by one, setting the lowest bit if the request was
+/// answered by an acceptable response, so that its bits record the outcome of the last eight
+/// requests with the most recent lowest. A server whose register is zero has answered none of
+/// them, telling a dead server from a flaky one.
+///
+/// `NtpClient` keeps an association for each server it sends requests to, returned by
+/// `NtpClient::association`. This is unrelated to the associations of a server listed by
+/// `control::list_associations`.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Association {
+    reach: u8,
+}
+
+impl Association {
+    /// Create a new **Association** with an empty register, as for a server never queried.
+    pub fn new() -> Self {
+        Association::default()
+    }
+
+    /// Record the outcome of a request: `true` if it was answered by an acceptable response and
+    /// `false` if it timed out or the response was rejected.
+    pub fn update(&mut self, answered: bool) {
+        self.reach = self.reach << 1 | answered as u8;
+    }
+
+    /// The reachability register, whose lowest bit is set if the most recent request was
+    /// answered.
+    pub fn reach(&self) -> u8 {
+        self.reach
+    }
+
+    /// Whether or not any of the last eight requests was answered.
+    pub fn reachable(&self) -> bool {
+        self.reach != 0
+    }
+
+    /// How many of the last eight requests were answered.
+    pub fn reach_count(&self) -> u32 {
+        self.reach.count_ones()
+    }
+}
@@ -0,0 +1,295 @@
+//! The results of querying several servers, or one server several times, at once.
+
+use crate::protocol::KissOfDeath;
+use crate::{Error, NtpMeasurement, SignedDuration};
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The outcome of `NtpClient::request_many`: the servers that responded with a valid measurement,
+/// and those that did not along with the reason, e.g. a timeout or a kiss-o'-death.
+#[derive(Debug, Default)]
+pub struct BatchResult {
+    /// The measurements of the servers that responded successfully.
+    pub ok: Vec<(SocketAddr, NtpMeasurement)>,
+    /// The errors of the servers that did not.
+    pub failed: Vec<(SocketAddr, Error)>,
+}
+
+impl BatchResult {
+    /// The successful measurement with the lowest `NtpMeasurement::quality_score`, if any.
+    pub fn best(&self) -> Option<&(SocketAddr, NtpMeasurement)> {
+        self.ok.iter().min_by(|(_, a), (_, b)| a.quality_score().total_cmp(&b.quality_score()))
+    }
+
+    /// The successful measurement preferred by `policy`, if any.
+    ///
+    /// `SelectionPolicy::BestQualityScore` selects the same measurement as `best`.
+    pub fn select(&self, policy: SelectionPolicy) -> Option<&(SocketAddr, NtpMeasurement)> {
+        match policy {
+            SelectionPolicy::LowestDelay => {
+                self.ok.iter().min_by_key(|(_, measurement)| measurement.delay())
+            }
+            SelectionPolicy::LowestStratum { max_delay } => self
+                .ok
+                .iter()
+                .filter(|(_, measurement)| {
+                    measurement.delay() <= SignedDuration::from(max_delay)
+                })
+                .min_by_key(|(_, measurement)| (measurement.packet.stratum, measurement.delay())),
+            SelectionPolicy::BestQualityScore => self.best(),
+        }
+    }
+
+    /// The offset combining all successful measurements, if any.
+    ///
+    /// As in the combine algorithm of RFC 5905, the offsets are averaged with each weighted by
+    /// the reciprocal of its `quality_score`, so that the most accurate servers contribute most.
+    pub fn combined(&self) -> Option<SignedDuration> {
+        weighted_offset(self.ok.iter().map(|(_, measurement)| measurement))
+    }
+
+    /// Select the servers whose time agrees and combine their offsets, explaining the verdict
+    /// reached on each server.
+    ///
+    /// Servers that failed are reported with their error, and those of a stratum above
+    /// `max_stratum` are excluded. Of the rest, the selection algorithm of RFC 5905 takes each
+    /// server's time to lie within its `quality_score` of its offset and finds the largest set
+    /// of servers whose intervals overlap. Those are the truechimers, whose offsets are combined
+    /// as by `combined`, while the others are falsetickers. Should no majority of the servers
+    /// agree, every server is a falseticker and there is no combined offset.
+    pub fn combine_with_report(&self, max_stratum: u8) -> CombineReport {
+        let mut servers = Vec::with_capacity(self.ok.len() + self.failed.len());
+        let mut candidates = Vec::new();
+        for (addr, measurement) in &self.ok {
+            if measurement.packet.stratum.0 > max_stratum {
+                servers.push((*addr, Verdict::HighStratum(measurement.packet.stratum.0)));
+            } else {
+                candidates.push((*addr, measurement));
+            }
+        }
+        let intervals: Vec<(f64, f64)> = candidates
+            .iter()
+            .map(|(_, measurement)| {
+                let offset = measurement.offset().as_nanos() as f64;
+                let distance = measurement.quality_score() * 1e9;
+                (offset - distance, offset + distance)
+            })
+            .collect();
+        let agreed = intersection(&intervals);
+        let mut truechimers = Vec::new();
+        for ((addr, measurement), &(low, high)) in candidates.iter().zip(&intervals) {
+            match agreed {
+                Some((lo, hi)) if low <= hi && high >= lo => {
+                    servers.push((*addr, Verdict::Truechimer(measurement.offset())));
+                    truechimers.push(*measurement);
+                }
+                _ => servers.push((*addr, Verdict::Falseticker(measurement.offset()))),
+            }
+        }
+        for (addr, err) in &self.failed {
+            let verdict = match *err {
+                Error::KissOfDeath(code) => Verdict::KissOfDeath(code),
+                ref err => Verdict::Failed(err.to_string()),
+            };
+            servers.push((*addr, verdict));
+        }
+        let offset = weighted_offset(truechimers.into_iter());
+        CombineReport { servers, offset }
+    }
+}
+
+/// How `BatchResult::select` and `NtpClient::best_measurement` choose among the servers that
+/// responded.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SelectionPolicy {
+    /// The measurement with the lowest round-trip delay, the least affected by the network.
+    #[default]
+    LowestDelay,
+    /// The measurement from the server of the lowest stratum, i.e. the closest to a reference
+    /// clock, among those whose delay is at most `max_delay`. Ties go to the lowest delay.
+    LowestStratum { max_delay: Duration },
+    /// The measurement with the lowest `NtpMeasurement::quality_score`, as chosen by
+    /// `BatchResult::best`.
+    BestQualityScore,
+}
+
+/// The verdict reached on a server by `BatchResult::combine_with_report`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verdict {
+    /// The server agrees with the majority, and its offset is part of the combined offset.
+    Truechimer(SignedDuration),
+    /// The server's offset disagrees with the majority.
+    Falseticker(SignedDuration),
+    /// The server's stratum, given, is above the acceptable maximum.
+    HighStratum(u8),
+    /// The server responded with a kiss-o'-death.
+    KissOfDeath(KissOfDeath),
+    /// The request failed for the given reason.
+    Failed(String),
+}
+
+/// The outcome of `BatchResult::combine_with_report`: the verdict on each server along with the
+/// combined offset of the truechimers.
+///
+/// The `Display` implementation explains the result with a line per server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CombineReport {
+    /// Every server queried with its verdict: first the servers that responded, in the order of
+    /// `BatchResult::ok`, then those that failed.
+    pub servers: Vec<(SocketAddr, Verdict)>,
+    /// The combined offset of the truechimers, if any.
+    pub offset: Option<SignedDuration>,
+}
+
+/// The outcome of `NtpClient::compare`: the offset of each of two servers, how far apart their
+/// clocks are and which of them agrees better with the local clock.
+///
+/// The `Display` implementation summarizes the comparison in a few lines.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ComparisonReport {
+    /// The first server and its offset.
+    pub a: (SocketAddr, SignedDuration),
+    /// The second server and its offset.
+    pub b: (SocketAddr, SignedDuration),
+    /// How far the clock of the first server is ahead of that of the second, i.e. the offset of
+    /// the first less the offset of the second.
+    pub difference: SignedDuration,
+    /// The server whose offset is the smaller in magnitude, which is the first when they are
+    /// equal.
+    pub closer: SocketAddr,
+}
+
+impl ComparisonReport {
+    pub(crate) fn new(a: (SocketAddr, SignedDuration), b: (SocketAddr, SignedDuration)) -> Self {
+        let closer = if b.1.as_nanos().abs() < a.1.as_nanos().abs() { b.0 } else { a.0 };
+        ComparisonReport { a, b, difference: a.1 - b.1, closer }
+    }
+}
+
+/// The outcome of `NtpClient::burst_report`: the chosen sample along with how many samples were
+/// received and how many of those were rejected as outliers.
+#[derive(Copy, Clone, Debug)]
+pub struct BurstReport {
+    /// The sample with the lowest delay among those that were not outliers.
+    pub best: NtpMeasurement,
+    /// The number of requests that were answered with a valid response.
+    pub samples: usize,
+    /// The number of valid samples whose offset was rejected as an outlier.
+    pub outliers: usize,
+}
+
+/// The outcome of `NtpClient::flood`: how many requests were sent and answered, and the latency
+/// of the answers.
+///
+/// Requires the `loadtest` feature.
+#[cfg(feature = "loadtest")]
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct FloodReport {
+    /// The number of requests sent.
+    pub sent: usize,
+    /// The number of requests answered by a response echoing their transmit timestamp.
+    pub received: usize,
+    /// The number of datagrams received that answered none of the requests, e.g. duplicated
+    /// or mangled responses.
+    pub unmatched: usize,
+    /// The lowest latency of a response, from sending the request to receiving the response, or
+    /// `None` if no request was answered.
+    pub min_latency: Option<Duration>,
+    /// The mean latency of the responses, or `None` if no request was answered.
+    pub mean_latency: Option<Duration>,
+    /// The highest latency of a response, or `None` if no request was answered.
+    pub max_latency: Option<Duration>,
+}
+
+/// The outcome of `NtpClient::fast_sync`: the most accurate measurement taken and whether the
+/// clock is far enough off that it should be stepped rather than slewed.
+#[derive(Copy, Clone, Debug)]
+pub struct FastSyncReport {
+    /// The refined measurement of the burst, or the initial measurement if no burst was needed.
+    pub measurement: NtpMeasurement,
+    /// Whether the offset of the initial measurement exceeded `STEP_THRESHOLD`.
+    pub step: bool,
+    /// Whether a burst was sent to refine the initial measurement.
+    pub refined: bool,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Verdict::Truechimer(offset) => {
+                write!(f, "truechimer, offset {}s", offset.as_secs_f64())
+            }
+            Verdict::Falseticker(offset) => {
+                write!(f, "falseticker, offset {}s", offset.as_secs_f64())
+            }
+            Verdict::HighStratum(stratum) => write!(f, "rejected, stratum {}", stratum),
+            Verdict::KissOfDeath(code) => write!(f, "rejected, kiss-o'-death {:?}", code),
+            Verdict::Failed(ref reason) => write!(f, "failed, {}", reason),
+        }
+    }
+}
+
+impl fmt::Display for ComparisonReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}: offset {}s", self.a.0, self.a.1.as_secs_f64())?;
+        writeln!(f, "{}: offset {}s", self.b.0, self.b.1.as_secs_f64())?;
+        writeln!(f, "the servers differ by {}s", self.difference.as_secs_f64())?;
+        write!(f, "{} agrees better with the local clock", self.closer)
+    }
+}
+
+impl fmt::Display for CombineReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (addr, verdict) in &self.servers {
+            writeln!(f, "{}: {}", addr, verdict)?;
+        }
+        match self.offset {
+            Some(offset) => write!(f, "combined offset {}s", offset.as_secs_f64()),
+            None => write!(f, "no majority of servers agree"),
+        }
+    }
+}
+
+// Average the offsets of the measurements, each weighted by the reciprocal of its quality score.
+fn weighted_offset<'a, I>(measurements: I) -> Option<SignedDuration>
+where
+    I: Iterator<Item = &'a NtpMeasurement>,
+{
+    let (mut sum, mut total_weight) = (0.0, 0.0);
+    for measurement in measurements {
+        let weight = 1.0 / measurement.quality_score().max(f64::MIN_POSITIVE);
+        sum += measurement.offset().as_nanos() as f64 * weight;
+        total_weight += weight;
+    }
+    if total_weight == 0.0 {
+        return None;
+    }
+    Some(SignedDuration::from_nanos((sum / total_weight).round() as i128))
+}
+
+// Marzullo's algorithm: the first interval in which the largest number of the given intervals
+// overlap, provided they are a majority.
+fn intersection(intervals: &[(f64, f64)]) -> Option<(f64, f64)> {
+    // Lower endpoints sort before upper endpoints at the same point, so that intervals which
+    // only touch are taken to overlap.
+    let mut endpoints: Vec<(f64, i32)> = Vec::with_capacity(intervals.len() * 2);
+    for &(low, high) in intervals {
+        endpoints.push((low, -1));
+        endpoints.push((high, 1));
+    }
+    endpoints.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    let (mut count, mut best, mut agreed) = (0, 0, None);
+    for (i, &(point, kind)) in endpoints.iter().enumerate() {
+        count -= kind;
+        if count > best {
+            best = count;
+            agreed = Some((point, endpoints[i + 1].0));
+        }
+    }
+    if best as usize * 2 <= intervals.len() {
+        return None;
+    }
+    agreed
+}
@@ -0,0 +1,2263 @@
+//! A configurable client for querying ntp servers.
+
+use crate::protocol::{
+    self, ConstPackedSizeBytes, LogSeconds, Packet, PrimarySource, RawExtensionField, ReadBytes,
+    ReferenceIdentifier, Stratum, TimestampFormat, Version, WriteBytes,
+    UNIQUE_IDENTIFIER_FIELD_TYPE,
+};
+use crate::config::NtpClientConfig;
+use crate::history::{MeasurementRecord, OffsetStats};
+use crate::{
+    Association, BatchResult, BurstReport, Clock, ComparisonReport, Error, FastSyncReport, Instant,
+    NtpMeasurement, SelectionPolicy, ServerAddr, SignedDuration, SubsecPrecision, SystemClock,
+    STEP_THRESHOLD,
+};
+#[cfg(feature = "loadtest")]
+use crate::batch::FloodReport;
+#[cfg(feature = "ntpv5")]
+use crate::protocol::PacketV5;
+#[cfg(feature = "proxy")]
+use crate::config::{Socks5Credentials, Socks5ProxyConfig};
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::task::Poll;
+use std::time::Duration;
+use std::{fmt, io};
+
+#[cfg(feature = "async-std")]
+use async_std::io::{ReadExt, WriteExt};
+#[cfg(feature = "async-std")]
+use async_std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+#[cfg(feature = "tokio")]
+use tokio::net::{TcpStream, ToSocketAddrs, UdpSocket};
+
+/// A client for querying ntp servers that validates each response against its configuration.
+///
+/// The client is configured using builder-style methods:
+///
+/// ```
+/// extern crate nippy;
+///
+/// use std::time::Duration;
+///
+/// fn main() {
+///     let client = nippy::NtpClient::new().max_reference_age(Duration::from_secs(3600));
+/// }
+/// ```
+///
+/// ## Concurrency
+///
+/// **NtpClient** is `Clone`, `Send` and `Sync`, and may be shared between tasks, e.g. in an
+/// `Arc`, to query servers concurrently. Each request binds its own socket, so concurrent
+/// requests never receive each other's responses. The only state shared between requests, and
+/// between clones of the client, is the record of previous exchanges kept for interleaved mode,
+/// of the last response accepted from each server, of when each server may next be queried
+/// under `min_query_interval`, of each server's `association` and of the `history_capacity` most
+/// recent measurements, each guarded by a mutex that is never held across an `.await`.
+///
+/// Clones additionally share the request sent by `send_request`, so that any of them may poll for
+/// its response using `try_recv`.
+#[derive(Clone, Debug, Default)]
+pub struct NtpClient {
+    config: NtpClientConfig,
+    resolver: Option<Resolver>,
+    clock: ClockSource,
+    rng: RngSource,
+    // The previous exchange with each server, shared between clones of the client.
+    previous: Arc<Mutex<HashMap<SocketAddr, PreviousExchange>>>,
+    // The transmit timestamp of the last response accepted from each server, shared between
+    // clones.
+    accepted: Arc<Mutex<HashMap<SocketAddr, TimestampFormat>>>,
+    // The earliest time at which the next request may be sent to each server, if
+    // `min_query_interval` is set, shared between clones.
+    next_query: Arc<Mutex<HashMap<SocketAddr, std::time::Instant>>>,
+    // The reachability of each server requests have been sent to, shared between clones.
+    associations: Arc<Mutex<HashMap<SocketAddr, Association>>>,
+    // The socket UDP requests are sent from, if given by `from_socket`, rather than one bound
+    // for each request.
+    udp_socket: Option<Arc<std::net::UdpSocket>>,
+    // The most recent measurements accepted, if `history_capacity` is nonzero, shared between
+    // clones.
+    history: Arc<Mutex<VecDeque<MeasurementRecord>>>,
+    // The request sent by `send_request` awaiting a response, shared between clones.
+    pending: Arc<Mutex<Option<PendingRequest>>>,
+}
+
+/// The default size of the buffer responses are received into, large enough for the extension
+/// fields of NTS and other authenticated responses.
+pub const DEFAULT_RECV_BUFFER_SIZE_BYTES: usize = 1024;
+
+// The size of the random value of a Unique Identifier extension field; RFC 8915 requires at
+// least 32 bytes.
+const UNIQUE_ID_SIZE_BYTES: usize = 32;
+
+// Local times preceding 1 January 1971 are taken to be those of a clock that was never set, such
+// as that of a device without a battery-backed real-time clock which counts from the unix epoch
+// at boot.
+const LOCAL_CLOCK_UNSET_BEFORE_SECS: i64 = 365 * 24 * 60 * 60;
+
+// The most measurements the history allocates room for before it fills, so that a capacity read
+// from a configuration file cannot exhaust memory before any measurement is taken.
+const MAX_PREALLOCATED_HISTORY: usize = 1024;
+
+// How long `flood` awaits responses after sending its last request, should the client have no
+// timeout.
+#[cfg(feature = "loadtest")]
+const FLOOD_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Offsets beyond which `NtpClient::sanity_check_local` takes the local clock to be wrong rather
+/// than merely drifted: a year.
+pub const IMPLAUSIBLE_LOCAL_OFFSET: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// The default multiple of the jitter by which the offset of a `NtpClient::burst` sample may
+/// deviate from the median before it is rejected as an outlier.
+pub const DEFAULT_OUTLIER_SIGMA: f64 = 3.0;
+
+/// The default bound on the spread of the offsets of the samples `NtpClient::sample_until_stable`
+/// takes to be consistent.
+pub const DEFAULT_STABLE_JITTER: Duration = Duration::from_millis(5);
+
+/// The number of requests in the burst by which `NtpClient::fast_sync` refines an offset.
+pub const FAST_SYNC_BURST_COUNT: usize = 3;
+
+/// The spacing of the requests sent by `NtpClient::fast_sync`.
+pub const FAST_SYNC_SPACING: Duration = Duration::from_secs(2);
+
+/// The default number of requests `NtpClient::request_many` keeps in flight at once.
+pub const DEFAULT_MAX_CONCURRENCY: usize = 16;
+
+/// Literal addresses of public anycast time services, used by `get_unix_ntp_time` should
+/// `pool.ntp.org` fail to resolve: two of Cloudflare's `time.cloudflare.com` followed by one of
+/// Google's `time.google.com`, which smears leap seconds.
+///
+/// The addresses are correct at the time of release but are not guaranteed to remain so, and
+/// are only meant to bootstrap a device whose DNS is not yet available.
+pub const DEFAULT_FALLBACK_ADDRS: [SocketAddr; 3] = [
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(162, 159, 200, 1), protocol::NTP_PORT)),
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(162, 159, 200, 123), protocol::NTP_PORT)),
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(216, 239, 35, 0), protocol::NTP_PORT)),
+];
+
+/// The largest UDP payload that fits in a single IPv4 datagram, beyond which a receive buffer
+/// cannot usefully grow.
+pub const MAX_RECV_BUFFER_SIZE_BYTES: usize = protocol::MAX_PACKET_SIZE;
+
+type ResolveFuture = Pin<Box<dyn Future<Output = Vec<SocketAddr>> + Send>>;
+
+// A user supplied replacement for the system resolver.
+#[derive(Clone)]
+struct Resolver(Arc<dyn Fn(&str) -> ResolveFuture + Send + Sync>);
+
+// The clock the client reads local time from.
+#[derive(Clone)]
+struct ClockSource(Arc<dyn Clock>);
+
+// The source of the random values sent with requests, or the operating system's if `None`.
+#[derive(Clone, Default)]
+struct RngSource(Option<Arc<Mutex<dyn RngCore + Send>>>);
+
+// How requests are carried to the server.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) enum Transport {
+    #[default]
+    Udp,
+    // A TCP connection per request, on which each packet is preceded by its length as a 4 byte
+    // big-endian integer.
+    Tcp,
+    // UDP relayed through a SOCKS5 proxy.
+    #[cfg(feature = "proxy")]
+    Socks5(Socks5ProxyConfig),
+}
+
+// How the sockets requests are sent from are set up.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SocketOptions {
+    pub(crate) transport: Transport,
+    // The differentiated services code point outgoing packets are marked with, if any.
+    pub(crate) dscp: Option<u8>,
+    // The socket UDP requests are sent from, if any, rather than one bound for each request.
+    pub(crate) udp_socket: Option<Arc<std::net::UdpSocket>>,
+}
+
+// The timestamps of the previous exchange with a server needed for interleaved mode.
+#[derive(Copy, Clone, Debug)]
+struct PreviousExchange {
+    sent: Instant,
+    received: Instant,
+    round_trip: Duration,
+    // The local receive time exactly as sent in the receive field of the next request.
+    local_receive: TimestampFormat,
+    server_receive: TimestampFormat,
+}
+
+impl PreviousExchange {
+    fn of(measurement: &NtpMeasurement) -> PreviousExchange {
+        PreviousExchange {
+            sent: measurement.sent,
+            received: measurement.received,
+            round_trip: measurement.round_trip,
+            local_receive: TimestampFormat::from_instant_checked(measurement.received)
+                .unwrap_or_default(),
+            server_receive: measurement.packet.receive_timestamp,
+        }
+    }
+}
+
+// A request sent without awaiting its response.
+#[derive(Debug)]
+struct PendingRequest {
+    sock: std::net::UdpSocket,
+    transmit_timestamp: TimestampFormat,
+}
+
+impl NtpClient {
+    /// Create a new **NtpClient** accepting any well-formed response.
+    pub fn new() -> Self {
+        NtpClient::default()
+    }
+
+    /// Create a new **NtpClient** sending its UDP requests from the given socket rather than
+    /// from a socket bound for each request, for setting socket options, e.g. `SO_REUSEPORT` or
+    /// the buffer sizes, that the client does not expose.
+    ///
+    /// The client and its clones share ownership of the socket, which is closed once the last of
+    /// them is dropped; `socket` gives access to it meanwhile. It is put in non-blocking mode and
+    /// must not be connected, as requests are sent to each server with `send_to`. Datagrams
+    /// arriving from any address other than the server's are discarded while awaiting a
+    /// response, but concurrent requests from the shared socket may receive each other's
+    /// responses and fail, so concurrent requests should use a client per socket.
+    /// `send_request`, and requests over TCP or a proxy, use sockets of their own.
+    pub fn from_socket(sock: std::net::UdpSocket) -> Self {
+        NtpClient { udp_socket: Some(Arc::new(sock)), ..NtpClient::default() }
+    }
+
+    /// Create a new **NtpClient** with the given configuration, as if each of its options had been
+    /// set by the builder method of the same name.
+    ///
+    /// Values the builder methods would have clamped or masked, as a configuration read from a
+    /// file may hold, are normalized in the same way, so that `config` returns the configuration
+    /// the builder methods would have built.
+    pub fn from_config(mut config: NtpClientConfig) -> Self {
+        if config.require_reference.is_some() {
+            config.require_primary = true;
+        }
+        config.delay_asymmetry = config.delay_asymmetry.max(-1.0);
+        #[cfg(feature = "proxy")]
+        {
+            if config.socks5_proxy.is_some() {
+                config.use_tcp = false;
+            }
+        }
+        config.dscp = config.dscp.map(|dscp| dscp & 0x3f);
+        let min_size = protocol::Packet::PACKED_SIZE_BYTES;
+        config.recv_buffer_size =
+            config.recv_buffer_size.map(|size| size.clamp(min_size, MAX_RECV_BUFFER_SIZE_BYTES));
+        config.max_concurrency = config.max_concurrency.map(|max| max.max(1));
+        config.outlier_sigma = config.outlier_sigma.map(|sigma| sigma.max(0.0));
+        let history = empty_history(config.history_capacity);
+        NtpClient { config, history: Arc::new(Mutex::new(history)), ..NtpClient::default() }
+    }
+
+    /// The configuration of the client, which `from_config` builds an equivalent client from.
+    pub fn config(&self) -> &NtpClientConfig {
+        &self.config
+    }
+
+    /// The socket given to `from_socket`, if any.
+    pub fn socket(&self) -> Option<&std::net::UdpSocket> {
+        self.udp_socket.as_deref()
+    }
+
+    /// Give up waiting for a response after `timeout`, returning `Error::Timeout`.
+    ///
+    /// A burst is bounded by the timeout as a whole rather than for each of its requests.
+    ///
+    /// By default the client waits indefinitely, which is rarely desirable as UDP datagrams may
+    /// be lost.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = Some(timeout);
+        self
+    }
+
+    /// Reject responses from servers that last synchronized their own clock longer than `age`
+    /// ago, as such servers may be coasting on an undisciplined clock.
+    pub fn max_reference_age(mut self, age: Duration) -> Self {
+        self.config.max_reference_age = Some(age);
+        self
+    }
+
+    /// Reject responses whose reference timestamp is zero, as sent by servers that have never
+    /// synchronized their own clock, with `Error::InvalidPacket`.
+    ///
+    /// NTPv5 headers carry no reference timestamp, so this rejects NTPv5 measurements.
+    pub fn require_synchronized(mut self, require: bool) -> Self {
+        self.config.require_synchronized = require;
+        self
+    }
+
+    /// Reject responses from any server other than a primary (stratum 1) server, i.e. one
+    /// synchronized directly to a reference clock.
+    pub fn require_primary(mut self, require: bool) -> Self {
+        self.config.require_primary = require;
+        self
+    }
+
+    /// Reject responses from any server other than a primary server synchronized to the given
+    /// reference clock, e.g. `PrimarySource::Gps`.
+    ///
+    /// This implies `require_primary(true)`, as only primary servers identify their reference
+    /// clock.
+    pub fn require_reference(mut self, source: PrimarySource) -> Self {
+        self.config.require_primary = true;
+        self.config.require_reference = Some(source);
+        self
+    }
+
+    /// Reject responses from servers in orphan mode given the subnet's orphan stratum, as such
+    /// servers agree on a common time that is not traceable to any reference clock.
+    ///
+    /// See `Packet::is_orphan`.
+    pub fn reject_orphan(mut self, orphan_stratum: u8) -> Self {
+        self.config.reject_orphan = Some(orphan_stratum);
+        self
+    }
+
+    /// Reject responses from servers whose clock precision is coarser than `secs` seconds, e.g.
+    /// `1e-3` to reject servers with worse than millisecond precision.
+    ///
+    /// See `Packet::precision_secs`.
+    pub fn max_acceptable_precision(mut self, secs: f64) -> Self {
+        self.config.max_acceptable_precision = Some(secs);
+        self
+    }
+
+    /// Request interleaved mode from servers that support it.
+    ///
+    /// In interleaved mode each request carries the timestamps of the previous exchange with the
+    /// same server. A supporting server then responds with the more accurate (e.g. hardware)
+    /// transmit timestamp of its *previous* response in place of a software timestamp of the
+    /// current one, and the returned measurement describes the previous exchange.
+    ///
+    /// Support is detected per response by its origin timestamp: an interleaved response echoes
+    /// the receive timestamp of the request while a basic response echoes its transmit
+    /// timestamp. The first exchange with a server is always basic.
+    pub fn interleaved(mut self, interleaved: bool) -> Self {
+        self.config.interleaved = interleaved;
+        self
+    }
+
+    /// The poll exponent advertised in requests, i.e. the log2 of the interval in seconds at which
+    /// the caller queries the server. Defaults to `0`.
+    ///
+    /// Servers may use the advertised interval when deciding whether a client is querying too
+    /// often, so it should match the interval actually used.
+    pub fn poll(mut self, poll: i8) -> Self {
+        self.config.poll = poll;
+        self
+    }
+
+    /// The precision exponent advertised in requests, i.e. the log2 of the resolution in seconds
+    /// of the local clock.
+    ///
+    /// Defaults to the result of `Instant::estimate_resolution`, which is estimated once per
+    /// process on the first request.
+    pub fn precision(mut self, precision: i8) -> Self {
+        self.config.precision = Some(precision);
+        self
+    }
+
+    /// The known asymmetry of the network path to the server, the fraction by which the forward
+    /// delay exceeds the return delay, recorded in each measurement's `delay_asymmetry` and
+    /// corrected for by its `offset`. Defaults to `0.0`, a symmetric path.
+    ///
+    /// An unknown asymmetry biases the offset by up to half the delay, so on links known to be
+    /// asymmetric, e.g. ADSL, this is needed for sub-millisecond accuracy. Values below `-1.0`,
+    /// which would imply a negative forward delay, are clamped.
+    pub fn delay_asymmetry(mut self, fraction: f64) -> Self {
+        self.config.delay_asymmetry = fraction.max(-1.0);
+        self
+    }
+
+    /// Accept responses carrying extension fields following the 48 byte header.
+    ///
+    /// Without authentication configured the client has no use for extension fields, and a
+    /// server that sends them anyway is either misconfigured or an attacker probing for parser
+    /// bugs. Such responses are therefore rejected with `Error::InvalidPacket` by default. When
+    /// allowed, the extension fields are ignored and only the header is interpreted.
+    pub fn allow_unexpected_extensions(mut self, allow: bool) -> Self {
+        self.config.allow_unexpected_extensions = allow;
+        self
+    }
+
+    /// Send requests to each server at most once every `interval`, delaying any request that
+    /// would follow the previous request to the same server sooner, rather than failing it.
+    ///
+    /// The NTP pool asks clients to query each server no more than once a minute or so once
+    /// synchronized, and rate limits or sends a kiss-o'-death to those that query too often, so
+    /// long-running clients should set an interval of their polling period. The limit applies
+    /// to every request to the server, from this client and its clones, including those of
+    /// `burst`, whose spacing it therefore stretches, and `send_request`, but not to `flood`.
+    /// The delay is not counted towards the `timeout`. Disabled by default.
+    pub fn min_query_interval(mut self, interval: Duration) -> Self {
+        self.config.min_query_interval = Some(interval);
+        self
+    }
+
+    /// Ignore trailing zero bytes following the header and any extension fields, as some
+    /// hardware appliances pad every response to a fixed size, rather than rejecting them as
+    /// malformed or unexpected extension fields.
+    ///
+    /// As no extension field is shorter than its 4 byte header or has a length of zero, zeros
+    /// where the next field would begin cannot be one and are taken to be padding, which is
+    /// logged at debug level.
+    pub fn allow_zero_padding(mut self, allow: bool) -> Self {
+        self.config.allow_zero_padding = allow;
+        self
+    }
+
+    /// Send a random Unique Identifier extension field (RFC 8915) with each request and reject
+    /// responses that do not echo it with `Error::Spoofed`.
+    ///
+    /// Without NTS the identifier is not authenticated, but an off-path attacker cannot know it
+    /// and so can neither forge a response nor replay an earlier one. The server must support the
+    /// extension, as one that does not will not echo it. The echoed field is expected and does not
+    /// count against `allow_unexpected_extensions`.
+    pub fn unique_id(mut self, unique_id: bool) -> Self {
+        self.config.unique_id = unique_id;
+        self
+    }
+
+    /// Request the NTPv5 draft protocol, falling back to NTPv4 for each request to which the
+    /// server responds with an earlier version.
+    ///
+    /// NTPv5 headers carry no reference identifier or reference timestamp, so the packets of
+    /// NTPv5 measurements have neither: `require_reference` and `require_synchronized` reject
+    /// them and `max_reference_age` is not applied. Requires the experimental `ntpv5` feature.
+    #[cfg(feature = "ntpv5")]
+    pub fn ntpv5(mut self, ntpv5: bool) -> Self {
+        self.config.ntpv5 = ntpv5;
+        self
+    }
+
+    /// Send requests over TCP rather than UDP, for networks in which UDP port 123 is blocked but
+    /// a time service is reachable over TCP.
+    ///
+    /// Rather than taking a separate TCP endpoint, TCP reuses the server address passed to
+    /// `request` and the other methods sending requests, port included, so the time service must
+    /// listen for TCP on the same port as for UDP. A connection is made to that address for each
+    /// request, on which the request and the response are each preceded by their length in bytes
+    /// as a 4 byte big-endian integer.
+    /// The connection is established before the request is timestamped, so the time it takes
+    /// is excluded from the exchange, but TCP's acknowledgements and retransmissions make the
+    /// delay of each direction less predictable, so the measured offset is less precise than
+    /// over UDP. `send_request` always uses UDP.
+    pub fn use_tcp(mut self, use_tcp: bool) -> Self {
+        self.config.use_tcp = use_tcp;
+        #[cfg(feature = "proxy")]
+        {
+            self.config.socks5_proxy = None;
+        }
+        self
+    }
+
+    /// Mark requests with the given differentiated services code point, e.g. 48 for CS6 (network
+    /// control), so that routers on a congested link do not queue them behind bulk traffic.
+    ///
+    /// The code point is set in the upper six bits of the IPv4 type of service or IPv6 traffic
+    /// class of the socket before each request is sent; only its lower six bits are used. The
+    /// option is only supported on Linux. Elsewhere, requests fail with an `Unsupported` IO
+    /// error rather than being sent unmarked.
+    pub fn dscp(mut self, dscp: u8) -> Self {
+        self.config.dscp = Some(dscp & 0x3f);
+        self
+    }
+
+    /// The largest spread of offsets, from the lowest to the highest, of the samples that
+    /// `sample_until_stable` takes to agree. Defaults to `DEFAULT_STABLE_JITTER`.
+    pub fn stable_jitter(mut self, jitter: Duration) -> Self {
+        self.config.stable_jitter = Some(jitter);
+        self
+    }
+
+    /// How `best_measurement` chooses among the servers that respond. Defaults to
+    /// `SelectionPolicy::LowestDelay`.
+    pub fn selection_policy(mut self, policy: SelectionPolicy) -> Self {
+        self.config.selection_policy = policy;
+        self
+    }
+
+    /// Relay requests through the SOCKS5 proxy at `addr` using its UDP ASSOCIATE command, for
+    /// networks in which outbound UDP is only permitted through a proxy. `auth` is the username
+    /// and password to authenticate with, if the proxy requires them.
+    ///
+    /// A connection is made to the proxy for each request, and the relay set up on it before the
+    /// request is timestamped so that its time is excluded from the exchange, but the relay adds
+    /// its own processing to the delay and need not add it symmetrically, so the measured offset
+    /// is less precise than when querying the server directly. The later of this and `use_tcp`
+    /// decides how requests are carried, and `send_request` is never relayed. Requires the
+    /// `proxy` feature.
+    #[cfg(feature = "proxy")]
+    pub fn socks5_proxy(mut self, addr: SocketAddr, auth: Option<(&str, &str)>) -> Self {
+        let auth = auth.map(|(username, password)| Socks5Credentials {
+            username: username.to_string(),
+            password: password.to_string(),
+        });
+        self.config.use_tcp = false;
+        self.config.socks5_proxy = Some(Socks5ProxyConfig { addr, auth });
+        self
+    }
+
+    /// The size of the buffer responses are received into, which defaults to
+    /// `DEFAULT_RECV_BUFFER_SIZE_BYTES`.
+    ///
+    /// Responses larger than the buffer are rejected with `Error::InvalidPacket` rather than
+    /// silently truncated. As an NTP response always fits within a single UDP datagram, sizes are
+    /// clamped to between the 48 byte header and `MAX_RECV_BUFFER_SIZE_BYTES`.
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        let size = size.clamp(protocol::Packet::PACKED_SIZE_BYTES, MAX_RECV_BUFFER_SIZE_BYTES);
+        self.config.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// The largest number of requests `request_many` keeps in flight at once, which defaults to
+    /// `DEFAULT_MAX_CONCURRENCY`.
+    ///
+    /// As each request binds its own socket, this bounds the number of file descriptors used when
+    /// querying a long list of servers. A limit of zero is treated as one.
+    pub fn max_concurrency(mut self, max: usize) -> Self {
+        self.config.max_concurrency = Some(max.max(1));
+        self
+    }
+
+    /// Reject the samples of a `burst` whose offset deviates from the median offset by more than
+    /// `sigma` times the jitter. Defaults to `DEFAULT_OUTLIER_SIGMA`; `f64::INFINITY` disables
+    /// the rejection.
+    ///
+    /// The jitter is estimated from the median absolute deviation of the offsets, which, unlike
+    /// their standard deviation, is not inflated by the outliers themselves. A transient spike in
+    /// the delay of one direction shifts the offset of a sample by up to half the spike, and
+    /// such a sample may otherwise be chosen should the other direction happen to be fast.
+    pub fn outlier_sigma(mut self, sigma: f64) -> Self {
+        self.config.outlier_sigma = Some(sigma.max(0.0));
+        self
+    }
+
+    /// Reject measurements whose offset exceeds `IMPLAUSIBLE_LOCAL_OFFSET` with
+    /// `Error::LocalClockImplausible`, rather than reporting a local clock read as, say, 2000
+    /// on a device that should be in 2024 as an ordinary offset. Disabled by default.
+    ///
+    /// Such a clock has evidently been set wrongly, or reset by a failed real-time clock, and
+    /// should be stepped to the server's time rather than slewed. The measured offset is carried
+    /// by the error for doing so.
+    pub fn sanity_check_local(mut self, enabled: bool) -> Self {
+        self.config.sanity_check_local = enabled;
+        self
+    }
+
+    /// Reject measurements whose offset exceeds `max_offset` in either direction with
+    /// `Error::OffsetTooLarge`, guarding against a misconfigured server reporting a time that
+    /// is wildly wrong. `PANIC_THRESHOLD` matches the panic threshold of ntpd.
+    ///
+    /// Unlike `sanity_check_local`, which blames the local clock for an offset of a year or more,
+    /// this catches a bad server when the local clock is known to be roughly right. It is
+    /// disabled by default, as a client whose clock is badly set would otherwise never learn the
+    /// time.
+    pub fn max_offset(mut self, max_offset: Duration) -> Self {
+        self.config.max_offset = Some(max_offset);
+        self
+    }
+
+    /// Resolve the server names given to `request` and every other method taking a server
+    /// address using `resolver` in place of the system resolver.
+    ///
+    /// The resolver is given the name written as `host:port`, e.g. `pool.ntp.org:123`, and
+    /// requests are sent to the first socket address it returns. Socket addresses and literal IP
+    /// addresses are used as given, as described by `ServerAddr`. This allows tests to direct
+    /// queries at fixed addresses, or applications to route resolution through a custom DNS
+    /// client.
+    pub fn resolver<F, Fut>(mut self, resolver: F) -> Self
+    where
+        F: Fn(&str) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Vec<SocketAddr>> + Send + 'static,
+    {
+        let resolver = move |addr: &str| -> ResolveFuture { Box::pin(resolver(addr)) };
+        self.resolver = Some(Resolver(Arc::new(resolver)));
+        self
+    }
+
+    /// Resolve the server name `host`, e.g. `pool.ntp.org:123`, to the socket address requests
+    /// to it are sent to, using the resolver set by `resolver` or, if none is set, the system
+    /// resolver.
+    ///
+    /// Returns `Error::Resolution` if it resolves to no socket address.
+    pub async fn resolve(&self, host: &str) -> Result<SocketAddr, Error> {
+        self.resolve_addr(host).await
+    }
+
+    /// Query `addrs` in turn, until one responds, should a server name fail to resolve, by the
+    /// client's `resolver` or the system resolver, e.g. on a device that boots before DNS is
+    /// available.
+    ///
+    /// The fallbacks stand in for the server given to `request`, `request_into`, `burst`,
+    /// `burst_report`, `fast_sync`, `sample_until_stable` and either of those given to `compare`,
+    /// and the methods built on them. They are not used by `flood`, as public services must not be
+    /// flooded, nor by `send_request`, which cannot tell whether a fallback responds.
+    ///
+    /// Literal addresses go stale as services are renumbered, so the fallbacks are a bootstrap
+    /// aid rather than a substitute for configuring servers by name. `DEFAULT_FALLBACK_ADDRS`
+    /// lists some public services.
+    pub fn fallback_addrs<I: IntoIterator<Item = SocketAddr>>(mut self, addrs: I) -> Self {
+        self.config.fallback_addrs = addrs.into_iter().collect();
+        self
+    }
+
+    /// Read the local time from `clock` rather than the **SystemClock**.
+    ///
+    /// The clock is shared between clones of the client, so it is held in an `Arc` rather than a
+    /// `Box`.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = ClockSource(Arc::new(clock));
+        self
+    }
+
+    /// Generate the random values sent with requests, i.e. the `unique_id` extension field, the
+    /// NTPv5 client cookie and the first nonce of a `flood`, from `rng` rather than the operating
+    /// system's random number generator.
+    ///
+    /// This allows tests to be reproducible, or an application to supply its own entropy source.
+    /// As the values guard against spoofed responses, `rng` should be cryptographically secure
+    /// outside of tests. The generator is shared between clones of the client.
+    pub fn rng<R: RngCore + Send + 'static>(mut self, rng: R) -> Self {
+        self.rng = RngSource(Some(Arc::new(Mutex::new(rng))));
+        self
+    }
+
+    /// Remember the last `capacity` measurements accepted, from any server, for `stats` to
+    /// summarize. Disabled, with a capacity of zero, by default.
+    ///
+    /// Each measurement is kept as its 32 byte `MeasurementRecord`. The history is shared
+    /// between clones of the client made after this is set, and begins empty.
+    pub fn history_capacity(mut self, capacity: usize) -> Self {
+        self.config.history_capacity = capacity;
+        self.history = Arc::new(Mutex::new(empty_history(capacity)));
+        self
+    }
+
+    /// Statistics over the measurements remembered by `history_capacity`, oldest to newest,
+    /// whose `count` is zero if there are none.
+    pub fn stats(&self) -> OffsetStats {
+        OffsetStats::from_records(&*self.history.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// The reachability of `server` over the requests sent to it by the client and its clones,
+    /// or `None` if none have been.
+    ///
+    /// Each request to the server, from `request` or any method built on it, updates the
+    /// association once it is answered by an acceptable response, times out or fails.
+    pub fn association(&self, server: SocketAddr) -> Option<Association> {
+        let associations = self.associations.lock().unwrap_or_else(PoisonError::into_inner);
+        associations.get(&server).copied()
+    }
+
+    /// Send an async request to an ntp server and validate the response.
+    ///
+    ///   `addr` can be any valid socket address
+    ///   returns an error if the server cannot be reached or the response is invalid or
+    ///   rejected by the client's configuration.
+    ///
+    /// A response carrying the same transmit timestamp as the last response accepted from the
+    /// server is a duplicate or replay of it and is rejected with `Error::Duplicate`, so that it
+    /// is not counted twice, e.g. by `burst`.
+    pub async fn request<A>(&self, addr: A) -> Result<NtpMeasurement, Error>
+    where
+        A: ServerAddr,
+    {
+        self.request_or_fallback(addr, &mut Vec::new()).await
+    }
+
+    /// Send a request as `request` does, reusing `buf` to send the request and receive the
+    /// response rather than allocating a buffer for each request.
+    ///
+    /// The buffer is cleared and then grown to one byte more than the `recv_buffer_size`, so
+    /// once a buffer has been used it is not reallocated by subsequent requests from the same
+    /// client. When the request completes, successfully or not, `buf` holds the bytes of the
+    /// response if one was received.
+    pub async fn request_into<A>(&self, addr: A, buf: &mut Vec<u8>) -> Result<NtpMeasurement, Error>
+    where
+        A: ServerAddr,
+    {
+        self.request_or_fallback(addr, buf).await
+    }
+
+    /// The current time according to the server, measured by a request as `request` does.
+    ///
+    /// With an `era` of `None`, the server's timestamps are placed in the NTP era nearest the
+    /// local clock, which must therefore be within 68 years of the true time, and the local
+    /// clock is corrected by the measured offset. Should the local clock be further off, e.g.
+    /// reading 1971 in 2040, the era is misjudged and the time is wrong by 136 years. Passing the
+    /// known `era`, 1 for 2036 to 2172, instead takes the server's transmit time in that era,
+    /// advanced by half the delay and by the time since the response arrived, which relies on
+    /// the local clock only for measuring intervals.
+    pub async fn current_time<A>(&self, addr: A, era: Option<i32>) -> Result<Instant, Error>
+    where
+        A: ServerAddr,
+    {
+        let measurement = self.request(addr).await?;
+        let now = self.clock.0.now();
+        match era {
+            None => Ok(measurement.correct(now)),
+            Some(era) => {
+                let transmit = measurement.packet.transmit_timestamp.to_instant_in_era(era);
+                let half_delay = SignedDuration::from_nanos(measurement.delay().as_nanos() / 2);
+                Ok(transmit + half_delay + (now - measurement.received))
+            }
+        }
+    }
+
+    /// Send a request as `request` does, abandoning it with `Error::Cancelled` should `cancel`
+    /// complete first, e.g. on a shutdown signal or a deadline of the caller's.
+    ///
+    /// The request is polled before `cancel`, so a response that has already arrived is not
+    /// discarded. Abandoning the request closes its socket. The client's own `timeout` still
+    /// applies.
+    pub async fn request_cancellable<A, C>(
+        &self,
+        addr: A,
+        cancel: C,
+    ) -> Result<NtpMeasurement, Error>
+    where
+        A: ServerAddr,
+        C: Future<Output = ()>,
+    {
+        match race(self.request(addr), cancel).await {
+            Some(result) => result,
+            None => Err(Error::Cancelled),
+        }
+    }
+
+    // Send a request to `addr`, or to each of the fallback addresses in turn if it cannot be
+    // resolved, returning the first measurement or the last error.
+    async fn request_or_fallback<A>(
+        &self,
+        addr: A,
+        buf: &mut Vec<u8>,
+    ) -> Result<NtpMeasurement, Error>
+    where
+        A: ServerAddr,
+    {
+        let mut result = match self.resolve_addr(addr).await {
+            Ok(server) => return self.request_resolved(server, buf).await,
+            Err(err) => Err(err),
+        };
+        for &fallback in &self.config.fallback_addrs {
+            info!("querying fallback address {}", fallback);
+            result = self.request_resolved(fallback, buf).await;
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    // Run `query` against the address `addr` resolves to or, should it fail to resolve, against
+    // each of the fallback addresses in turn, returning the first success or the last error.
+    async fn with_fallback<A, T, F, Fut>(&self, addr: A, mut query: F) -> Result<T, Error>
+    where
+        A: ServerAddr,
+        F: FnMut(SocketAddr) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut result = match self.resolve_addr(addr).await {
+            Ok(server) => return query(server).await,
+            Err(err) => Err(err),
+        };
+        for &fallback in &self.config.fallback_addrs {
+            info!("querying fallback address {}", fallback);
+            result = query(fallback).await;
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    // Send a request to a server whose address has already been resolved, using `buf` to send
+    // and receive, and record its outcome in the server's association.
+    async fn request_resolved(
+        &self,
+        server: SocketAddr,
+        buf: &mut Vec<u8>,
+    ) -> Result<NtpMeasurement, Error> {
+        self.local_time()?;
+        self.await_query_slot(server).await;
+        let result = self.exchange_and_validate(server, buf).await;
+        self.associations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(server)
+            .or_default()
+            .update(result.is_ok());
+        result
+    }
+
+    // Exchange a request and response with the server and validate the response.
+    async fn exchange_and_validate(
+        &self,
+        server: SocketAddr,
+        buf: &mut Vec<u8>,
+    ) -> Result<NtpMeasurement, Error> {
+        let previous = if self.config.interleaved {
+            self.previous_exchange(server)
+        } else {
+            None
+        };
+        let (origin, receive) = match previous {
+            Some(previous) => (previous.server_receive, previous.local_receive),
+            None => Default::default(),
+        };
+        let request = Packet {
+            poll: LogSeconds(self.config.poll),
+            precision: LogSeconds(self.config.precision.unwrap_or_else(local_precision)),
+            ..request_packet(origin, receive, TimestampFormat::default())
+        };
+        let unique_id = if self.config.unique_id {
+            let mut value = vec![0; UNIQUE_ID_SIZE_BYTES];
+            self.rng.fill_bytes(&mut value);
+            Some(RawExtensionField { field_type: UNIQUE_IDENTIFIER_FIELD_TYPE, value })
+        } else {
+            None
+        };
+        let mut extensions = Vec::new();
+        if let Some(ref field) = unique_id {
+            extensions.write_bytes(field)?;
+        }
+        let buffer_size = self.buffer_size();
+        let exchange = self.exchange(server, request, &extensions, buf, buffer_size);
+        let response = match self.config.timeout {
+            Some(timeout) => match with_timeout(timeout, exchange).await {
+                Some(response) => response.map_err(response_error)?,
+                None => return Err(Error::Timeout),
+            },
+            None => exchange.await.map_err(response_error)?,
+        };
+        if response.truncated {
+            let err_msg = format!("response exceeds the {} byte receive buffer", buffer_size);
+            return Err(Error::InvalidPacket(err_msg));
+        }
+        if is_reflection(&response.measurement) {
+            return Err(Error::Reflected);
+        }
+        // Earlier versions share the layout of the header, but later ones need not.
+        let version = response.measurement.packet.version;
+        if version > self.requested_version(&request) {
+            return Err(Error::UnsupportedVersion(version.number()));
+        }
+        let mut extensions = &response.extensions[..];
+        if self.config.allow_zero_padding {
+            let padding = zero_padding(extensions);
+            if padding > 0 {
+                debug!("ignoring {} bytes of zero padding from {}", padding, server);
+                extensions = &extensions[..extensions.len() - padding];
+            }
+        }
+        let mut unexpected = extensions.len();
+        if let Some(ref unique_id) = unique_id {
+            let fields = RawExtensionField::parse_all(extensions).map_err(|err| {
+                Error::InvalidPacket(format!("malformed extension fields: {}", err))
+            })?;
+            if !fields.contains(unique_id) {
+                return Err(Error::Spoofed);
+            }
+            unexpected -= unique_id.packed_size_bytes();
+        }
+        if unexpected > 0 && !self.config.allow_unexpected_extensions {
+            let err_msg =
+                format!("response carries {} bytes of unexpected extension fields", unexpected);
+            return Err(Error::InvalidPacket(err_msg));
+        }
+        let mut measurement = response.measurement;
+        let transmit_timestamp = measurement.packet.transmit_timestamp;
+        if self.last_accepted(server) == Some(transmit_timestamp) {
+            debug!("discarding duplicate response from {}", server);
+            return Err(Error::Duplicate);
+        }
+        let exchange = PreviousExchange::of(&measurement);
+        if self.config.interleaved {
+            measurement = self.interleave(server, previous, measurement);
+        }
+        measurement.delay_asymmetry = self.config.delay_asymmetry;
+        self.validate(&measurement)?;
+        // Only a response that was accepted may be the basis of the next interleaved exchange.
+        if self.config.interleaved {
+            self.previous
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(server, exchange);
+        }
+        self.accepted
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(server, transmit_timestamp);
+        if self.config.history_capacity > 0 {
+            let mut history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+            if history.len() == self.config.history_capacity {
+                history.pop_front();
+            }
+            history.push_back(measurement.record());
+        }
+        if let Some(direction) = measurement.packet.pending_leap() {
+            info!("leap second announced by server: {:?}", direction);
+        }
+        Ok(measurement)
+    }
+
+    /// Send a burst of `count` requests to an ntp server, `spacing` apart, and return the
+    /// measurement with the lowest round-trip delay.
+    ///
+    /// The sample with the lowest delay is the least affected by queuing in the network and
+    /// therefore yields the most accurate offset. Each request is validated, and requests that
+    /// fail are skipped, unless the server responds with a kiss-o'-death in which case the burst
+    /// is abandoned and `Error::KissOfDeath` returned. If no request succeeds the error of the
+    /// last request is returned.
+    ///
+    /// The client's timeout bounds the burst as a whole, including the spacing between requests
+    /// and any wait for `min_query_interval`. Should it pass before the burst completes, the best
+    /// of the samples collected so far is returned, or `Error::Timeout` if there are none.
+    ///
+    /// Samples whose offset is an outlier, as configured by `outlier_sigma`, are discarded
+    /// before the lowest delay sample is chosen.
+    ///
+    /// A `count` of zero is an `InvalidInput` error.
+    pub async fn burst<A>(
+        &self,
+        addr: A,
+        count: usize,
+        spacing: Duration,
+    ) -> Result<NtpMeasurement, Error>
+    where
+        A: ServerAddr,
+    {
+        Ok(self.burst_report(addr, count, spacing).await?.best)
+    }
+
+    /// Send a burst of requests as `burst` does, also reporting how many samples were accepted
+    /// and how many of those were rejected as outliers.
+    pub async fn burst_report<A>(
+        &self,
+        addr: A,
+        count: usize,
+        spacing: Duration,
+    ) -> Result<BurstReport, Error>
+    where
+        A: ServerAddr,
+    {
+        self.with_fallback(addr, move |server| self.burst_resolved(server, count, spacing)).await
+    }
+
+    // Send a burst of requests as `burst_report` does to a server whose address has already been
+    // resolved.
+    async fn burst_resolved(
+        &self,
+        server: SocketAddr,
+        count: usize,
+        spacing: Duration,
+    ) -> Result<BurstReport, Error> {
+        let mut buf = Vec::new();
+        let mut samples = Vec::with_capacity(count);
+        let mut last_err = None;
+        let collect = async {
+            for i in 0..count {
+                if i > 0 {
+                    sleep(spacing).await;
+                }
+                match self.request_resolved(server, &mut buf).await {
+                    Ok(measurement) => samples.push(measurement),
+                    Err(err @ Error::KissOfDeath(_)) => return Err(err),
+                    Err(err) => {
+                        debug!("burst request {} to {} failed: {}", i, server, err);
+                        last_err = Some(err);
+                    }
+                }
+            }
+            Ok(())
+        };
+        let completed = match self.config.timeout {
+            Some(timeout) => with_timeout(timeout, collect).await,
+            None => Some(collect.await),
+        };
+        match completed {
+            Some(result) => result?,
+            None => {
+                debug!("burst to {} timed out after {} samples", server, samples.len());
+                last_err = Some(Error::Timeout);
+            }
+        }
+        let accepted = samples.len();
+        let sigma = self.config.outlier_sigma.unwrap_or(DEFAULT_OUTLIER_SIGMA);
+        let inliers = reject_outliers(samples, sigma);
+        let outliers = accepted - inliers.len();
+        if outliers > 0 {
+            debug!("rejected {} of {} samples from {} as outliers", outliers, accepted, server);
+        }
+        let best = inliers.into_iter().min_by_key(|measurement| measurement.delay());
+        match (best, last_err) {
+            (Some(best), _) => Ok(BurstReport { best, samples: accepted, outliers }),
+            (None, Some(err)) => Err(err),
+            (None, None) => {
+                let err_msg = "burst count must be at least one";
+                Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg).into())
+            }
+        }
+    }
+
+    /// Send requests as `protocol::build_client_batch` builds them to a server at `rate` requests
+    /// a second for `duration`, tallying the responses and their latency, for load testing a
+    /// server under development.
+    ///
+    /// The requests are paced evenly from a single socket connected to the server, each built
+    /// just before it is sent rather than all of them up front, and responses are matched to
+    /// them by their origin timestamps but not otherwise validated. After the last request
+    /// responses are awaited for the client's `timeout`, or one second should it have none,
+    /// unless all have arrived. A `rate` of zero is an `InvalidInput` error.
+    ///
+    /// Only servers of one's own should be flooded, as public servers rate limit or block
+    /// clients that send more than a request every few seconds. Requires the `loadtest`
+    /// feature.
+    #[cfg(feature = "loadtest")]
+    pub async fn flood<A>(
+        &self,
+        addr: A,
+        rate: u32,
+        duration: Duration,
+    ) -> Result<FloodReport, Error>
+    where
+        A: ServerAddr,
+    {
+        if rate == 0 {
+            let err_msg = "flood rate must be at least one request a second";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg).into());
+        }
+        let server = self.resolve_addr(addr).await?;
+        let local_addr = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let sock = UdpSocket::bind(local_addr).await?;
+        if let Some(dscp) = self.config.dscp {
+            set_dscp(&sock, server.is_ipv6(), dscp)?;
+        }
+        sock.connect(server).await?;
+
+        let count = (duration.as_secs_f64() * rate as f64).ceil() as usize;
+        let size = self.buffer_size();
+        let mut buf = vec![0u8; size];
+        let mut tally = FloodTally::default();
+        let first_nonce = self.rng.next_u64();
+        let start = std::time::Instant::now();
+        for i in 0..count {
+            let due = start + Duration::from_secs_f64(i as f64 / rate as f64);
+            tally.receive_until(&sock, due, &mut buf, false).await?;
+            let nonce = first_nonce.wrapping_add(i as u64);
+            sock.send(&protocol::build_client_with_nonce(nonce)).await?;
+            tally.outstanding.insert(nonce.to_be_bytes(), std::time::Instant::now());
+        }
+        let drain = self.config.timeout.unwrap_or(FLOOD_DRAIN_TIMEOUT);
+        tally.receive_until(&sock, std::time::Instant::now() + drain, &mut buf, true).await?;
+
+        let received = tally.latencies.len();
+        let total: Duration = tally.latencies.iter().sum();
+        Ok(FloodReport {
+            sent: count,
+            received,
+            unmatched: tally.unmatched,
+            min_latency: tally.latencies.iter().min().copied(),
+            mean_latency: (received > 0).then(|| total / received as u32),
+            max_latency: tally.latencies.iter().max().copied(),
+        })
+    }
+
+    /// Sample a server until `min_samples` consecutive samples agree, returning the one of those
+    /// with the lowest delay, so that a single unlucky sample is never acted on.
+    ///
+    /// Samples agree when the spread of their offsets, from the lowest to the highest, is within
+    /// `stable_jitter`. Requests are sent one after another, with no spacing, up to
+    /// `max_attempts` in total, and requests that fail count towards the attempts but break the
+    /// run of consecutive samples. Should the attempts run out first `Error::NotStable` is
+    /// returned, or the error of the last request if none succeeded. A kiss-o'-death abandons the
+    /// sampling at once, and a `min_samples` of zero is an `InvalidInput` error.
+    pub async fn sample_until_stable<A>(
+        &self,
+        addr: A,
+        min_samples: usize,
+        max_attempts: usize,
+    ) -> Result<NtpMeasurement, Error>
+    where
+        A: ServerAddr,
+    {
+        if min_samples == 0 {
+            let err_msg = "at least one sample is needed to be stable";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg).into());
+        }
+        let sample = move |server| self.sample_resolved(server, min_samples, max_attempts);
+        self.with_fallback(addr, sample).await
+    }
+
+    // Sample a server as `sample_until_stable` does whose address has already been resolved.
+    async fn sample_resolved(
+        &self,
+        server: SocketAddr,
+        min_samples: usize,
+        max_attempts: usize,
+    ) -> Result<NtpMeasurement, Error> {
+        let jitter = self.config.stable_jitter.unwrap_or(DEFAULT_STABLE_JITTER).as_nanos() as i128;
+        let mut buf = Vec::new();
+        let mut run: VecDeque<NtpMeasurement> = VecDeque::with_capacity(min_samples);
+        let (mut succeeded, mut last_err) = (false, None);
+        for attempt in 0..max_attempts {
+            match self.request_resolved(server, &mut buf).await {
+                Ok(measurement) => {
+                    succeeded = true;
+                    if run.len() == min_samples {
+                        run.pop_front();
+                    }
+                    run.push_back(measurement);
+                }
+                Err(err @ Error::KissOfDeath(_)) => return Err(err),
+                Err(err) => {
+                    debug!("sample {} from {} failed: {}", attempt, server, err);
+                    run.clear();
+                    last_err = Some(err);
+                    continue;
+                }
+            }
+            if run.len() < min_samples {
+                continue;
+            }
+            let offsets = run.iter().map(|measurement| measurement.offset().as_nanos());
+            let (low, high) = offsets.fold((i128::MAX, i128::MIN), |(low, high), offset| {
+                (low.min(offset), high.max(offset))
+            });
+            if high - low <= jitter {
+                debug!("offset of {} stable after {} samples", server, attempt + 1);
+                if let Some(best) = run.iter().min_by_key(|measurement| measurement.delay()) {
+                    return Ok(*best);
+                }
+            }
+        }
+        match last_err {
+            Some(err) if !succeeded => Err(err),
+            _ => Err(Error::NotStable(min_samples)),
+        }
+    }
+
+    /// Measure the offset to a server quickly at startup: a single request gives a coarse offset,
+    /// and should it exceed `STEP_THRESHOLD` a short burst follows to refine it.
+    ///
+    /// A large initial offset means the clock should be stepped, and as the correction is then
+    /// applied in one go it is worth measuring precisely. The burst of `FAST_SYNC_BURST_COUNT`
+    /// requests, spaced `FAST_SYNC_SPACING` apart as ntpd's `iburst` is, takes a few seconds. If
+    /// the burst fails the initial measurement is returned, unless the server responds with a
+    /// kiss-o'-death.
+    pub async fn fast_sync<A>(&self, addr: A) -> Result<FastSyncReport, Error>
+    where
+        A: ServerAddr,
+    {
+        self.with_fallback(addr, move |server| self.fast_sync_resolved(server)).await
+    }
+
+    // Measure the offset as `fast_sync` does to a server whose address has already been resolved.
+    async fn fast_sync_resolved(&self, server: SocketAddr) -> Result<FastSyncReport, Error> {
+        let coarse = self.request_resolved(server, &mut Vec::new()).await?;
+        let step = coarse.offset().unsigned_abs() > STEP_THRESHOLD;
+        if !step {
+            return Ok(FastSyncReport { measurement: coarse, step, refined: false });
+        }
+        sleep(FAST_SYNC_SPACING).await;
+        match self.burst_resolved(server, FAST_SYNC_BURST_COUNT, FAST_SYNC_SPACING).await {
+            Ok(fine) => Ok(FastSyncReport { measurement: fine.best, step, refined: true }),
+            Err(err @ Error::KissOfDeath(_)) => Err(err),
+            Err(err) => {
+                debug!("fast sync burst to {} failed: {}", server, err);
+                Ok(FastSyncReport { measurement: coarse, step, refined: false })
+            }
+        }
+    }
+
+    /// Send requests to each of the given servers concurrently, collecting the measurements of
+    /// those that respond successfully and the errors of those that do not.
+    ///
+    /// At most `max_concurrency` requests are in flight at once, each further request being sent
+    /// as an earlier one completes. Configure a `timeout` so that an unresponsive server cannot
+    /// delay the result indefinitely.
+    pub async fn request_many<I>(&self, servers: I) -> BatchResult
+    where
+        I: IntoIterator<Item = SocketAddr>,
+    {
+        let servers: Vec<SocketAddr> = servers.into_iter().collect();
+        let mut bufs = vec![Vec::new(); servers.len()];
+        let requests = servers
+            .iter()
+            .zip(bufs.iter_mut())
+            .map(|(&server, buf)| self.request_resolved(server, buf));
+        let max_concurrency = self.config.max_concurrency.unwrap_or(DEFAULT_MAX_CONCURRENCY);
+        let results = join_all(requests.collect(), max_concurrency).await;
+        let mut batch = BatchResult::default();
+        for (server, result) in servers.into_iter().zip(results) {
+            match result {
+                Ok(measurement) => batch.ok.push((server, measurement)),
+                Err(err) => batch.failed.push((server, err)),
+            }
+        }
+        batch
+    }
+
+    /// Query the given servers concurrently as `request_many` does, and return the measurement
+    /// chosen by the client's `selection_policy` along with the address of its server.
+    ///
+    /// Should none of the servers respond successfully the error of the first is returned.
+    /// Should servers respond but none be acceptable to the policy, i.e. none be within the
+    /// maximum delay of `SelectionPolicy::LowestStratum`, `Error::InvalidPacket` is returned.
+    pub async fn best_measurement<I>(
+        &self,
+        servers: I,
+    ) -> Result<(SocketAddr, NtpMeasurement), Error>
+    where
+        I: IntoIterator<Item = SocketAddr>,
+    {
+        let batch = self.request_many(servers).await;
+        if let Some(&selected) = batch.select(self.config.selection_policy) {
+            return Ok(selected);
+        }
+        if !batch.ok.is_empty() {
+            let policy = self.config.selection_policy;
+            let err_msg = format!("no response is acceptable to {:?}", policy);
+            return Err(Error::InvalidPacket(err_msg));
+        }
+        match batch.failed.into_iter().next() {
+            Some((_, err)) => Err(err),
+            None => {
+                let err_msg = "no servers to select a measurement from";
+                Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg).into())
+            }
+        }
+    }
+
+    /// Query two servers concurrently and compare their offsets, to see how far apart their
+    /// clocks are and which of them agrees better with the local clock.
+    ///
+    /// If either request fails its error is returned, that of the first server should both fail.
+    pub async fn compare<A, B>(&self, server_a: A, server_b: B) -> Result<ComparisonReport, Error>
+    where
+        A: ServerAddr,
+        B: ServerAddr,
+    {
+        let server_b = &server_b;
+        let compare = move |a| async move {
+            self.with_fallback(server_b, move |b| self.compare_resolved(a, b)).await
+        };
+        self.with_fallback(server_a, compare).await
+    }
+
+    // Compare two servers as `compare` does whose addresses have already been resolved.
+    async fn compare_resolved(
+        &self,
+        a: SocketAddr,
+        b: SocketAddr,
+    ) -> Result<ComparisonReport, Error> {
+        let (mut buf_a, mut buf_b) = (Vec::new(), Vec::new());
+        let requests =
+            vec![self.request_resolved(a, &mut buf_a), self.request_resolved(b, &mut buf_b)];
+        let measurements = join_all(requests, 2).await.into_iter().collect::<Result<Vec<_>, _>>()?;
+        Ok(ComparisonReport::new((a, measurements[0].offset()), (b, measurements[1].offset())))
+    }
+
+    /// Send a request to an ntp server without waiting for its response, which may then be
+    /// polled for using `try_recv`. This allows the exchange to be driven by an event loop of the
+    /// caller's own.
+    ///
+    /// Returns the transmit timestamp of the request, which a genuine response echoes as its
+    /// origin timestamp. Only the latest request is tracked: sending another request abandons the
+    /// previous one.
+    pub async fn send_request<A>(&self, addr: A) -> Result<TimestampFormat, Error>
+    where
+        A: ServerAddr,
+    {
+        let server = self.resolve_addr(addr).await?;
+        self.await_query_slot(server).await;
+        let transmit_timestamp = TimestampFormat::from_instant_checked(self.local_time()?)?;
+        let zero = TimestampFormat::default();
+        let mut bytes = [0u8; Packet::PACKED_SIZE_BYTES];
+        let request = Packet {
+            poll: LogSeconds(self.config.poll),
+            precision: LogSeconds(self.config.precision.unwrap_or_else(local_precision)),
+            ..request_packet(zero, zero, transmit_timestamp)
+        };
+        (&mut bytes[..]).write_bytes(request)?;
+
+        let local_addr = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let sock = std::net::UdpSocket::bind(local_addr)?;
+        sock.set_nonblocking(true)?;
+        if let Some(dscp) = self.config.dscp {
+            set_dscp(&sock, server.is_ipv6(), dscp)?;
+        }
+        // Connecting filters out datagrams from any address other than the server's.
+        sock.connect(server)?;
+        sock.send(&bytes)?;
+
+        let pending = PendingRequest { sock, transmit_timestamp };
+        *self.pending.lock().unwrap_or_else(PoisonError::into_inner) = Some(pending);
+        Ok(transmit_timestamp)
+    }
+
+    /// Check for a response to the request sent by `send_request` without blocking.
+    ///
+    /// Returns `Ok(None)` if no response has arrived yet. Datagrams that are malformed or not a
+    /// response to the request, i.e. whose origin timestamp does not match, are discarded. The
+    /// response is returned as received and is not validated against the client's configuration.
+    ///
+    /// Returns a `NotConnected` IO error if there is no request awaiting a response.
+    pub fn try_recv(&self) -> Result<Option<Packet>, Error> {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        let request = match *pending {
+            Some(ref request) => request,
+            None => {
+                let err_msg = "no request is awaiting a response";
+                return Err(io::Error::new(io::ErrorKind::NotConnected, err_msg).into());
+            }
+        };
+        let size = self.buffer_size();
+        let mut buf = vec![0u8; size];
+        loop {
+            let len = match request.sock.recv(&mut buf[..]) {
+                Ok(len) => len,
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            if len < Packet::PACKED_SIZE_BYTES {
+                debug!("discarding {} byte datagram", len);
+                continue;
+            }
+            let packet: Packet = match (&buf[..len]).read_bytes() {
+                Ok(packet) => packet,
+                Err(err) => {
+                    debug!("discarding malformed datagram: {}", err);
+                    continue;
+                }
+            };
+            if packet.origin_timestamp != request.transmit_timestamp {
+                debug!("discarding response to another request");
+                continue;
+            }
+            *pending = None;
+            return Ok(Some(packet));
+        }
+    }
+
+    // The size of the buffer responses are received into.
+    fn buffer_size(&self) -> usize {
+        self.config.recv_buffer_size.unwrap_or(DEFAULT_RECV_BUFFER_SIZE_BYTES)
+    }
+
+    // How the sockets requests are sent from are set up.
+    fn socket_options(&self) -> SocketOptions {
+        let transport = if self.config.use_tcp { Transport::Tcp } else { Transport::Udp };
+        #[cfg(feature = "proxy")]
+        let transport = match self.config.socks5_proxy {
+            Some(ref proxy) => Transport::Socks5(proxy.clone()),
+            None => transport,
+        };
+        SocketOptions { transport, dscp: self.config.dscp, udp_socket: self.udp_socket.clone() }
+    }
+
+    // Resolve the server address with the client's resolver if it is a name and one is set, or
+    // with the system resolver otherwise.
+    async fn resolve_addr<A>(&self, addr: A) -> Result<SocketAddr, Error>
+    where
+        A: ServerAddr,
+    {
+        match (&self.resolver, addr.server_name()) {
+            (Some(Resolver(resolver)), Some(name)) => match resolver(&name).await.first() {
+                Some(&server) => Ok(server),
+                None => {
+                    debug!("resolver returned no address for {}", name);
+                    Err(Error::Resolution(name))
+                }
+            },
+            _ => resolve_system(addr).await,
+        }
+    }
+
+    // Exchange `request` with the server, or an equivalent NTPv5 request if configured.
+    async fn exchange(
+        &self,
+        server: SocketAddr,
+        request: Packet,
+        extensions: &[u8],
+        buf: &mut Vec<u8>,
+        buffer_size: usize,
+    ) -> io::Result<Response> {
+        let (socket, clock) = (&self.socket_options(), &*self.clock.0);
+        #[cfg(feature = "ntpv5")]
+        {
+            if self.config.ntpv5 {
+                let v5_request = PacketV5 {
+                    poll: request.poll,
+                    precision: request.precision,
+                    ..PacketV5::request(self.rng.next_u64())
+                };
+                let response =
+                    exchange_v5(server, v5_request, extensions, buf, buffer_size, socket, clock);
+                if let Some(response) = response.await? {
+                    return Ok(response);
+                }
+                debug!("{} does not support ntpv5, falling back to ntpv4", server);
+            }
+        }
+        exchange(server, request, extensions, buf, buffer_size, socket, clock).await
+    }
+
+    // The latest version a response to `request` may have, which is NTPv5 if it was requested.
+    fn requested_version(&self, request: &Packet) -> Version {
+        #[cfg(feature = "ntpv5")]
+        {
+            if self.config.ntpv5 {
+                return Version::V5;
+            }
+        }
+        request.version
+    }
+
+    // Read the local clock, failing if it has evidently never been set.
+    fn local_time(&self) -> Result<Instant, Error> {
+        let now = self.clock.0.now();
+        if now.secs() < LOCAL_CLOCK_UNSET_BEFORE_SECS {
+            return Err(Error::LocalClockUnset(now));
+        }
+        Ok(now)
+    }
+
+    // Wait until a request may be sent to the server under `min_query_interval`, reserving the
+    // time at which it is sent so that concurrent requests are spaced too.
+    async fn await_query_slot(&self, server: SocketAddr) {
+        let interval = match self.config.min_query_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        let wait = {
+            let mut next_query = self.next_query.lock().unwrap_or_else(PoisonError::into_inner);
+            let now = std::time::Instant::now();
+            let slot = next_query.get(&server).map_or(now, |&next| next.max(now));
+            next_query.insert(server, slot + interval);
+            slot - now
+        };
+        if wait > Duration::from_secs(0) {
+            debug!("delaying request to {} by {:?} to respect the query interval", server, wait);
+            sleep(wait).await;
+        }
+    }
+
+    fn previous_exchange(&self, server: SocketAddr) -> Option<PreviousExchange> {
+        let previous = self.previous.lock().unwrap_or_else(PoisonError::into_inner);
+        previous.get(&server).copied()
+    }
+
+    fn last_accepted(&self, server: SocketAddr) -> Option<TimestampFormat> {
+        let accepted = self.accepted.lock().unwrap_or_else(PoisonError::into_inner);
+        accepted.get(&server).copied()
+    }
+
+    // If the server responded in interleaved mode, combine the measurement with the previous
+    // exchange that its transmit timestamp belongs to.
+    fn interleave(
+        &self,
+        server: SocketAddr,
+        previous: Option<PreviousExchange>,
+        current: NtpMeasurement,
+    ) -> NtpMeasurement {
+        match previous {
+            Some(previous)
+                if previous.local_receive != TimestampFormat::default()
+                    && current.packet.origin_timestamp == previous.local_receive =>
+            {
+                debug!("interleaved response from {}", server);
+                let mut packet = current.packet;
+                packet.origin_timestamp = TimestampFormat::from_instant_checked(previous.sent)
+                    .unwrap_or_default();
+                packet.receive_timestamp = previous.server_receive;
+                NtpMeasurement {
+                    packet,
+                    sent: previous.sent,
+                    received: previous.received,
+                    round_trip: previous.round_trip,
+                    interleaved: true,
+                    delay_asymmetry: current.delay_asymmetry,
+                }
+            }
+            _ => current,
+        }
+    }
+
+    // Check a measurement against the configured limits.
+    fn validate(&self, measurement: &NtpMeasurement) -> Result<(), Error> {
+        let packet = &measurement.packet;
+        if let ReferenceIdentifier::KissOfDeath(code) = packet.reference_id {
+            return Err(Error::KissOfDeath(code));
+        }
+        if packet.stratum == Stratum::UNSPECIFIED {
+            return Err(Error::Unsynchronized);
+        }
+        let our_transmit = TimestampFormat::from_instant_checked(measurement.sent)?;
+        let report = packet.run_sanity_tests(our_transmit);
+        if !report.passed() {
+            let err_msg = format!("response failed sanity tests: {:?}", report);
+            return Err(Error::InvalidPacket(err_msg));
+        }
+        let never_synchronized = packet.reference_timestamp == TimestampFormat::default();
+        if self.config.require_synchronized && never_synchronized {
+            let err_msg = "server has never synchronized, its reference timestamp is zero";
+            return Err(Error::InvalidPacket(err_msg.to_string()));
+        }
+        if self.config.require_primary && packet.stratum != Stratum::PRIMARY {
+            let err_msg = format!("stratum {} server is not a primary server", packet.stratum.0);
+            return Err(Error::InvalidPacket(err_msg));
+        }
+        if let Some(orphan_stratum) = self.config.reject_orphan {
+            if packet.is_orphan(orphan_stratum) {
+                let err_msg = format!("stratum {} server is in orphan mode", packet.stratum.0);
+                return Err(Error::InvalidPacket(err_msg));
+            }
+        }
+        if let Some(max_precision) = self.config.max_acceptable_precision {
+            if packet.precision_secs() > max_precision {
+                let err_msg = format!(
+                    "server precision of {}s is coarser than {}s",
+                    packet.precision_secs(),
+                    max_precision
+                );
+                return Err(Error::InvalidPacket(err_msg));
+            }
+        }
+        if let Some(source) = self.config.require_reference {
+            if packet.reference_id != ReferenceIdentifier::PrimarySource(source) {
+                let err_msg = format!(
+                    "server reference {:?} is not the required {:?}",
+                    packet.reference_id, source
+                );
+                return Err(Error::InvalidPacket(err_msg));
+            }
+        }
+        if let Some(max_age) = self.config.max_reference_age {
+            if let Some(age) = measurement.packet.reference_age(measurement.received) {
+                if age > max_age {
+                    return Err(Error::StaleReference(age));
+                }
+            }
+        }
+        if self.config.sanity_check_local {
+            let offset = measurement.offset();
+            if offset.unsigned_abs() > IMPLAUSIBLE_LOCAL_OFFSET {
+                return Err(Error::LocalClockImplausible(offset));
+            }
+        }
+        if let Some(max_offset) = self.config.max_offset {
+            let offset = measurement.offset();
+            if offset.unsigned_abs() > max_offset {
+                return Err(Error::OffsetTooLarge(offset));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for ClockSource {
+    fn default() -> Self {
+        ClockSource(Arc::new(SystemClock))
+    }
+}
+
+impl fmt::Debug for ClockSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ClockSource")
+    }
+}
+
+impl RngSource {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        match self.0 {
+            Some(ref rng) => rng.lock().unwrap_or_else(PoisonError::into_inner).fill_bytes(dest),
+            None => OsRng.fill_bytes(dest),
+        }
+    }
+
+    #[cfg(any(feature = "ntpv5", feature = "loadtest"))]
+    fn next_u64(&self) -> u64 {
+        match self.0 {
+            Some(ref rng) => rng.lock().unwrap_or_else(PoisonError::into_inner).next_u64(),
+            None => OsRng.next_u64(),
+        }
+    }
+}
+
+impl fmt::Debug for RngSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RngSource")
+    }
+}
+
+impl fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Resolver")
+    }
+}
+
+// Resolve the address of a server to the socket addresses it refers to.
+#[cfg(feature = "async-std")]
+async fn resolve<A: ToSocketAddrs>(addr: A) -> io::Result<Vec<SocketAddr>> {
+    Ok(addr.to_socket_addrs().await?.collect())
+}
+
+// Resolve the address of a server to the socket addresses it refers to.
+#[cfg(feature = "tokio")]
+async fn resolve<A: ToSocketAddrs>(addr: A) -> io::Result<Vec<SocketAddr>> {
+    Ok(tokio::net::lookup_host(addr).await?.collect())
+}
+
+// Await `future`, or return `None` if it does not complete within `timeout`.
+#[cfg(feature = "async-std")]
+pub(crate) async fn with_timeout<F: Future>(timeout: Duration, future: F) -> Option<F::Output> {
+    async_std::future::timeout(timeout, future).await.ok()
+}
+
+// Await `future`, or return `None` if it does not complete within `timeout`.
+#[cfg(feature = "tokio")]
+pub(crate) async fn with_timeout<F: Future>(timeout: Duration, future: F) -> Option<F::Output> {
+    tokio::time::timeout(timeout, future).await.ok()
+}
+
+// Await `future`, or return `None` if `cancel` completes first.
+async fn race<F: Future, C: Future<Output = ()>>(future: F, cancel: C) -> Option<F::Output> {
+    let (mut future, mut cancel) = (std::pin::pin!(future), std::pin::pin!(cancel));
+    std::future::poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        cancel.as_mut().poll(cx).map(|()| None)
+    })
+    .await
+}
+
+// Await all of the futures concurrently, returning their outputs in order.
+//
+// No more than `limit` futures are polled at once. As futures do nothing until polled, the
+// remainder are only started as earlier ones complete.
+async fn join_all<F: Future>(futures: Vec<F>, limit: usize) -> Vec<F::Output> {
+    let mut futures: Vec<Pin<Box<F>>> = futures.into_iter().map(Box::pin).collect();
+    let mut outputs: Vec<Option<F::Output>> = futures.iter().map(|_| None).collect();
+    std::future::poll_fn(|cx| {
+        // The earliest incomplete futures are those in flight, as futures are started in order.
+        let mut in_flight = 0;
+        for (future, output) in futures.iter_mut().zip(outputs.iter_mut()) {
+            if output.is_none() {
+                if in_flight == limit {
+                    break;
+                }
+                match future.as_mut().poll(cx) {
+                    Poll::Ready(value) => *output = Some(value),
+                    Poll::Pending => in_flight += 1,
+                }
+            }
+        }
+        if in_flight > 0 {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    })
+    .await;
+    outputs.into_iter().flatten().collect()
+}
+
+#[cfg(feature = "async-std")]
+async fn sleep(duration: Duration) {
+    async_std::task::sleep(duration).await
+}
+
+#[cfg(feature = "tokio")]
+async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await
+}
+
+// The requests sent by `NtpClient::flood` awaiting responses, keyed by their transmit
+// timestamps, and the latencies of those answered.
+#[cfg(feature = "loadtest")]
+#[derive(Default)]
+struct FloodTally {
+    outstanding: HashMap<[u8; 8], std::time::Instant>,
+    latencies: Vec<Duration>,
+    unmatched: usize,
+}
+
+#[cfg(feature = "loadtest")]
+impl FloodTally {
+    // Tally the responses arriving on `sock` until `deadline`, or, when `drain` is set, until
+    // every request has been answered.
+    async fn receive_until(
+        &mut self,
+        sock: &UdpSocket,
+        deadline: std::time::Instant,
+        buf: &mut [u8],
+        drain: bool,
+    ) -> io::Result<()> {
+        loop {
+            let now = std::time::Instant::now();
+            if now >= deadline || (drain && self.outstanding.is_empty()) {
+                return Ok(());
+            }
+            let len = match with_timeout(deadline - now, sock.recv(buf)).await {
+                Some(Ok(len)) => len,
+                // An ICMP error provoked by an earlier request.
+                Some(Err(ref err)) if err.kind() == io::ErrorKind::ConnectionRefused => continue,
+                Some(Err(err)) => return Err(err),
+                None => return Ok(()),
+            };
+            let received = std::time::Instant::now();
+            let sent = if len >= protocol::Packet::PACKED_SIZE_BYTES {
+                let mut origin = [0u8; 8];
+                origin.copy_from_slice(&buf[24..32]);
+                self.outstanding.remove(&origin)
+            } else {
+                None
+            };
+            match sent {
+                Some(sent) => self.latencies.push(received - sent),
+                None => self.unmatched += 1,
+            }
+        }
+    }
+}
+
+// The number of trailing zero bytes of the extension fields of a response beginning where a
+// field would, which cannot be a field as its length would be zero.
+fn zero_padding(extensions: &[u8]) -> usize {
+    let mut offset = 0;
+    while offset < extensions.len() {
+        let rest = &extensions[offset..];
+        if rest.iter().all(|&byte| byte == 0) {
+            return rest.len();
+        }
+        let len = match rest.get(2..4) {
+            Some(len) => u16::from_be_bytes([len[0], len[1]]) as usize,
+            None => return 0,
+        };
+        if len < 4 {
+            return 0;
+        }
+        offset += len;
+    }
+    0
+}
+
+// Bytes displayed as lowercase hexadecimal, formatted only if the message logging them is
+// enabled.
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+// Trace the four timestamps of an exchange, before any interleaving, and the offset and delay
+// they give.
+fn trace_measurement(measurement: &NtpMeasurement) {
+    if !log_enabled!(log::Level::Trace) {
+        return;
+    }
+    let rfc3339 = |t: Instant| t.format_rfc3339(SubsecPrecision::Nanos);
+    let near = |t: TimestampFormat| rfc3339(t.to_instant_near(measurement.sent));
+    let packet = &measurement.packet;
+    trace!(
+        "timestamps: t1 {}, t2 {}, t3 {}, t4 {}",
+        rfc3339(measurement.sent),
+        near(packet.receive_timestamp),
+        near(packet.transmit_timestamp),
+        rfc3339(measurement.received)
+    );
+    trace!(
+        "offset {}s, delay {}s",
+        measurement.offset().as_secs_f64(),
+        measurement.delay().as_secs_f64()
+    );
+}
+
+// The estimated precision of the system clock, measured on first use.
+fn local_precision() -> i8 {
+    static PRECISION: OnceLock<i8> = OnceLock::new();
+    *PRECISION.get_or_init(Instant::estimate_resolution)
+}
+
+// Discard the samples whose offset deviates from their median by more than `sigma` times the
+// jitter, estimated as the median absolute deviation scaled to the standard deviation of normally
+// distributed offsets. As in RFC 5905 the jitter is no less than the precision of the server's
+// clock. Fewer than three samples cannot outvote one another and are all kept.
+fn reject_outliers(samples: Vec<NtpMeasurement>, sigma: f64) -> Vec<NtpMeasurement> {
+    if samples.len() < 3 || sigma.is_infinite() {
+        return samples;
+    }
+    let offsets: Vec<f64> = samples.iter().map(|s| s.offset().as_nanos() as f64).collect();
+    let median_offset = median(offsets.clone());
+    let deviations: Vec<f64> = offsets.iter().map(|o| (o - median_offset).abs()).collect();
+    let precision = samples.iter().map(|s| s.packet.precision_secs() * 1e9).fold(0.0, f64::max);
+    let threshold = sigma * (1.4826 * median(deviations.clone())).max(precision);
+    samples
+        .into_iter()
+        .zip(deviations)
+        .filter(|&(_, deviation)| deviation <= threshold)
+        .map(|(sample, _)| sample)
+        .collect()
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+// Create a packet for requesting from an NTP server as a client.
+//
+// `origin_timestamp` and `receive_timestamp` are usually zero and only set in interleaved mode.
+pub(crate) fn request_packet(
+    origin_timestamp: TimestampFormat,
+    receive_timestamp: TimestampFormat,
+    transmit_timestamp: TimestampFormat,
+) -> Packet {
+    let leap_indicator = protocol::LeapIndicator::default();
+    let version = protocol::Version::V4;
+    let mode = protocol::Mode::Client;
+    let poll = LogSeconds::default();
+    let precision = LogSeconds::default();
+    let root_delay = protocol::ShortFormat::default();
+    let root_dispersion = protocol::ShortFormat::default();
+    let stratum = protocol::Stratum::UNSPECIFIED;
+    let src = protocol::PrimarySource::Null;
+    let reference_id = protocol::ReferenceIdentifier::PrimarySource(src);
+    let reference_timestamp = protocol::TimestampFormat::default();
+    Packet {
+        leap_indicator,
+        version,
+        mode,
+        stratum,
+        poll,
+        precision,
+        root_delay,
+        root_dispersion,
+        reference_id,
+        reference_timestamp,
+        origin_timestamp,
+        receive_timestamp,
+        transmit_timestamp,
+    }
+}
+
+// A measurement together with any bytes that followed the header of the response.
+pub(crate) struct Response {
+    pub(crate) measurement: NtpMeasurement,
+    pub(crate) extensions: Vec<u8>,
+    // Whether the response was larger than the receive buffer and its extensions truncated.
+    pub(crate) truncated: bool,
+}
+
+impl Response {
+    // The response of `len` bytes received into `buf`, which is truncated to the part that fit
+    // within the receive buffer.
+    fn new(
+        measurement: NtpMeasurement,
+        buf: &mut Vec<u8>,
+        len: usize,
+        recv_buffer_size: usize,
+    ) -> Self {
+        let truncated = len > recv_buffer_size;
+        buf.truncate(len.min(recv_buffer_size));
+        let extensions = buf[protocol::Packet::PACKED_SIZE_BYTES..].to_vec();
+        Response { measurement, extensions, truncated }
+    }
+}
+
+// An empty history of measurements with room for `capacity` of them, of which at most
+// `MAX_PREALLOCATED_HISTORY` are allocated up front.
+fn empty_history(capacity: usize) -> VecDeque<MeasurementRecord> {
+    VecDeque::with_capacity(capacity.min(MAX_PREALLOCATED_HISTORY))
+}
+
+// Send a single client request to the server and read its response.
+//
+// The transmit timestamp of `request` is set from `clock` as it is sent, once the connection to
+// the server is set up.
+pub(crate) async fn exchange<A: ToSocketAddrs>(
+    addr: A,
+    request: Packet,
+    extensions: &[u8],
+    buf: &mut Vec<u8>,
+    recv_buffer_size: usize,
+    socket: &SocketOptions,
+    clock: &dyn Clock,
+) -> io::Result<Response> {
+    let mut connection = Connection::open(addr, socket).await?;
+    let sent = clock.now();
+    let sent_monotonic = std::time::Instant::now();
+
+    let transmit_timestamp = protocol::TimestampFormat::from_instant_checked(sent)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let packet = Packet { transmit_timestamp, ..request };
+
+    // Write the packet, followed by any extension fields, to the buffer.
+    buf.clear();
+    buf.write_bytes(packet)?;
+    buf.extend_from_slice(extensions);
+    trace!(
+        "request: version {}, mode {:?}, poll {:?}, precision {:?}, origin {}, transmit {}",
+        packet.version.number(),
+        packet.mode,
+        packet.poll,
+        packet.precision,
+        packet.origin_timestamp,
+        packet.transmit_timestamp
+    );
+    trace!("sent {} bytes: {}", buf.len(), Hex(buf));
+
+    let res = connection.send_and_receive(buf, recv_buffer_size).await?;
+    let round_trip = sent_monotonic.elapsed();
+    let received = clock.now();
+    trace!("received {} bytes: {}", res, Hex(&buf[..res]));
+
+    // Read the received packet from the response.
+    let packet = (&buf[..res]).read_bytes()?;
+    let measurement = NtpMeasurement {
+        packet,
+        sent,
+        received,
+        round_trip,
+        interleaved: false,
+        delay_asymmetry: 0.0,
+    };
+    trace_measurement(&measurement);
+    Ok(Response::new(measurement, buf, res, recv_buffer_size))
+}
+
+// Send an NTPv5 request to the server and read its response, or return `None` if the server
+// responded with an earlier version.
+//
+// The response is translated into an NTPv4 packet for the measurement. As NTPv5 has no origin
+// timestamp, the transmit timestamp of the request is taken as the origin if the response echoes
+// the client cookie, and zero otherwise so that the response fails the sanity tests.
+#[cfg(feature = "ntpv5")]
+async fn exchange_v5(
+    addr: SocketAddr,
+    request: PacketV5,
+    extensions: &[u8],
+    buf: &mut Vec<u8>,
+    recv_buffer_size: usize,
+    socket: &SocketOptions,
+    clock: &dyn Clock,
+) -> io::Result<Option<Response>> {
+    let mut connection = Connection::open(addr, socket).await?;
+    let sent = clock.now();
+    let sent_monotonic = std::time::Instant::now();
+
+    buf.clear();
+    buf.write_bytes(request)?;
+    buf.extend_from_slice(extensions);
+    trace!(
+        "request: version {}, mode {:?}, poll {:?}, precision {:?}, client cookie {:016x}",
+        request.version.number(),
+        request.mode,
+        request.poll,
+        request.precision,
+        request.client_cookie
+    );
+    trace!("sent {} bytes: {}", buf.len(), Hex(buf));
+
+    let res = connection.send_and_receive(buf, recv_buffer_size).await?;
+    let round_trip = sent_monotonic.elapsed();
+    let received = clock.now();
+    trace!("received {} bytes: {}", res, Hex(&buf[..res]));
+
+    if buf[0] >> 3 & 0b111 != 5 {
+        return Ok(None);
+    }
+    let response: PacketV5 = (&buf[..res]).read_bytes()?;
+    let origin_timestamp = if response.client_cookie == request.client_cookie {
+        protocol::TimestampFormat::from_instant_checked(sent).unwrap_or_default()
+    } else {
+        protocol::TimestampFormat::default()
+    };
+    let time32_to_short = |t: u32| protocol::ShortFormat {
+        seconds: (t >> 28) as u16,
+        fraction: (t >> 12) as u16,
+    };
+    let packet = Packet {
+        leap_indicator: response.leap_indicator,
+        version: response.version,
+        mode: response.mode,
+        stratum: response.stratum,
+        poll: response.poll,
+        precision: response.precision,
+        root_delay: time32_to_short(response.root_delay),
+        root_dispersion: time32_to_short(response.root_dispersion),
+        reference_id: ReferenceIdentifier::Unknown([0; 4]),
+        reference_timestamp: protocol::TimestampFormat::default(),
+        origin_timestamp,
+        receive_timestamp: response.receive_timestamp,
+        transmit_timestamp: response.transmit_timestamp,
+    };
+    let measurement = NtpMeasurement {
+        packet,
+        sent,
+        received,
+        round_trip,
+        interleaved: false,
+        delay_asymmetry: 0.0,
+    };
+    trace_measurement(&measurement);
+    Ok(Some(Response::new(measurement, buf, res, recv_buffer_size)))
+}
+
+// A socket set up to carry a single exchange with a server.
+//
+// Over TCP the connection takes a round trip of its own, and through a proxy several, so the
+// socket is set up before the request is timestamped and its setup excluded from the exchange.
+enum Connection {
+    // A UDP socket connected to the server.
+    Udp(UdpSocket),
+    // The socket given to `NtpClient::from_socket`, which is left unconnected, and the server.
+    Shared(UdpSocket, SocketAddr),
+    // A TCP connection to the server, on which each packet is preceded by its length.
+    Tcp(TcpStream),
+    // An association with the proxy requests are relayed through.
+    #[cfg(feature = "proxy")]
+    Socks5(crate::proxy::Relay),
+}
+
+impl Connection {
+    // Set up a socket to the server `addr` refers to as `socket` describes.
+    async fn open<A: ToSocketAddrs>(addr: A, socket: &SocketOptions) -> io::Result<Connection> {
+        let dscp = socket.dscp;
+        let connection = match socket.transport {
+            Transport::Udp => match socket.udp_socket {
+                Some(ref sock) => {
+                    let server = first_addr(&resolve(addr).await?)?;
+                    if let Some(dscp) = dscp {
+                        set_dscp(&**sock, server.is_ipv6(), dscp)?;
+                    }
+                    // The clone shares the socket, but is registered with the runtime only for
+                    // this request.
+                    sock.set_nonblocking(true)?;
+                    Connection::Shared(async_udp_socket(sock.try_clone()?)?, server)
+                }
+                None => Connection::Udp(connect_udp(addr, dscp).await?),
+            },
+            Transport::Tcp => {
+                let stream = TcpStream::connect(addr).await?;
+                stream.set_nodelay(true)?;
+                if let Some(dscp) = dscp {
+                    set_dscp(&stream, stream.peer_addr()?.is_ipv6(), dscp)?;
+                }
+                Connection::Tcp(stream)
+            }
+            #[cfg(feature = "proxy")]
+            Transport::Socks5(ref proxy) => {
+                let server = resolve_first(addr).await?;
+                Connection::Socks5(crate::proxy::associate(proxy, server, dscp).await?)
+            }
+        };
+        Ok(connection)
+    }
+
+    // Send the request held in `buf` to the server and receive its response into `buf`,
+    // returning the length of the response, which is at least that of a packet header.
+    async fn send_and_receive(
+        &mut self,
+        buf: &mut Vec<u8>,
+        recv_buffer_size: usize,
+    ) -> io::Result<usize> {
+        let res = match *self {
+            Connection::Udp(ref sock) => {
+                let sz = sock.send(buf).await?;
+                debug!("{:?}", sock.local_addr());
+                debug!("sent: {}", sz);
+                // One spare byte reveals whether the response was truncated to fit the buffer.
+                buf.resize(recv_buffer_size + 1, 0);
+                let res = sock.recv(&mut buf[..]).await?;
+                debug!("{:?}", &buf[..res]);
+                res
+            }
+            Connection::Shared(ref sock, server) => {
+                let sz = sock.send_to(buf, server).await?;
+                debug!("sent: {}", sz);
+                buf.resize(recv_buffer_size + 1, 0);
+                loop {
+                    let (res, from) = sock.recv_from(&mut buf[..]).await?;
+                    if from == server {
+                        break res;
+                    }
+                    debug!("discarding datagram from {}", from);
+                }
+            }
+            Connection::Tcp(ref mut stream) => {
+                return send_and_receive_tcp(stream, buf, recv_buffer_size).await;
+            }
+            #[cfg(feature = "proxy")]
+            Connection::Socks5(ref relay) => relay.send_and_receive(buf, recv_buffer_size).await?,
+        };
+        debug!("recv: {:?}", res);
+        if res < protocol::Packet::PACKED_SIZE_BYTES {
+            let err_msg = "response is shorter than an ntp packet";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        }
+        Ok(res)
+    }
+}
+
+// Bind a UDP socket and connect it to the server `addr` refers to.
+//
+// Only the first address of a pool name is queried, so the socket is always connected to it. The
+// kernel then discards datagrams from any other source rather than handing them to `recv`, and
+// looks up the route once at `connect`. The exchange still takes one system call in each
+// direction, but an unconnected `send_to` repeats the route lookup for every datagram and
+// `recv_from` copies out a source address only for it to be compared in user space, so that the
+// connected path saves that work in the kernel on each call as well as the filtering.
+async fn connect_udp<A: ToSocketAddrs>(addr: A, dscp: Option<u8>) -> io::Result<UdpSocket> {
+    let server = first_addr(&resolve(addr).await?)?;
+    let local_addr = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let sock = UdpSocket::bind(local_addr).await?;
+    if let Some(dscp) = dscp {
+        set_dscp(&sock, server.is_ipv6(), dscp)?;
+    }
+    sock.connect(server).await?;
+    Ok(sock)
+}
+
+#[cfg(feature = "async-std")]
+fn async_udp_socket(sock: std::net::UdpSocket) -> io::Result<UdpSocket> {
+    Ok(UdpSocket::from(sock))
+}
+
+#[cfg(feature = "tokio")]
+fn async_udp_socket(sock: std::net::UdpSocket) -> io::Result<UdpSocket> {
+    UdpSocket::from_std(sock)
+}
+
+// The error of an exchange, in which responses that cannot be parsed are invalid packets rather
+// than failures of IO.
+fn response_error(err: io::Error) -> Error {
+    if err.kind() == io::ErrorKind::InvalidData {
+        Error::InvalidPacket(format!("malformed response: {}", err))
+    } else {
+        err.into()
+    }
+}
+
+// Whether the response is our own request sent back to us, as by a misconfigured middlebox or a
+// server reflecting datagrams: it is in client mode, or carries the transmit timestamp of the
+// request as its own receive or transmit timestamp.
+fn is_reflection(measurement: &NtpMeasurement) -> bool {
+    let packet = &measurement.packet;
+    let ours = TimestampFormat::from_instant_checked(measurement.sent).unwrap_or_default();
+    packet.mode == protocol::Mode::Client
+        || packet.transmit_timestamp == ours
+        || packet.receive_timestamp == ours
+}
+
+// The first of the addresses a server resolved to, failing if there were none.
+fn first_addr(addrs: &[SocketAddr]) -> io::Result<SocketAddr> {
+    match addrs.first() {
+        Some(&server) => Ok(server),
+        None => {
+            let err_msg = "server address did not resolve to any socket address";
+            Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg))
+        }
+    }
+}
+
+// Resolve a server address with the system resolver, which for an address that fails to resolve
+// gives an error describing it.
+pub(crate) async fn resolve_system<A: ToSocketAddrs>(addr: A) -> Result<SocketAddr, Error> {
+    resolve_first(addr).await.map_err(|err| {
+        debug!("failed to resolve server address: {}", err);
+        Error::Resolution(err.to_string())
+    })
+}
+
+// Resolve the address of a server to the first socket address it refers to.
+pub(crate) async fn resolve_first<A: ToSocketAddrs>(addr: A) -> io::Result<SocketAddr> {
+    first_addr(&resolve(addr).await?)
+}
+
+// Mark the packets sent from the socket with the given differentiated services code point, held
+// in the upper six bits of the IPv4 type of service or IPv6 traffic class.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_dscp<S>(sock: &S, ipv6: bool, dscp: u8) -> io::Result<()>
+where
+    S: std::os::unix::io::AsRawFd,
+{
+    let (level, name) = if ipv6 {
+        (libc::IPPROTO_IPV6, libc::IPV6_TCLASS)
+    } else {
+        (libc::IPPROTO_IP, libc::IP_TOS)
+    };
+    let value = (dscp as libc::c_int) << 2;
+    let res = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_dscp<S>(_sock: &S, _ipv6: bool, _dscp: u8) -> io::Result<()> {
+    let err_msg = "setting the dscp of requests is only supported on linux";
+    Err(io::Error::new(io::ErrorKind::Unsupported, err_msg))
+}
+
+// Exchange the request held in `buf` over a TCP connection, on which each packet is preceded by
+// its length. A response exceeding the buffer is read only as far as the one spare byte, as over
+// UDP, and the rest discarded with the connection.
+async fn send_and_receive_tcp(
+    stream: &mut TcpStream,
+    buf: &mut Vec<u8>,
+    recv_buffer_size: usize,
+) -> io::Result<usize> {
+    stream.write_all(&(buf.len() as u32).to_be_bytes()).await?;
+    stream.write_all(buf).await?;
+    debug!("sent: {}", buf.len());
+
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    debug!("recv: {:?}", len);
+    if len < protocol::Packet::PACKED_SIZE_BYTES {
+        let err_msg = "response is shorter than an ntp packet";
+        return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+    }
+    let res = len.min(recv_buffer_size + 1);
+    buf.resize(res, 0);
+    stream.read_exact(&mut buf[..]).await?;
+    Ok(res)
+}
@@ -0,0 +1,23 @@
+//! The source of local time used when measuring an exchange.
+
+use crate::Instant;
+
+/// A source of the local wall clock time.
+///
+/// **NtpClient** reads the local time from a **Clock** when sending each request and receiving
+/// each response. The default is the **SystemClock**, while tests may substitute a clock
+/// returning fixed or scripted times in order to make assertions about the measured offset.
+pub trait Clock: Send + Sync {
+    /// The current time according to this clock.
+    fn now(&self) -> Instant;
+}
+
+/// The operating system's wall clock, as read by `Instant::now`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
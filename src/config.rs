@@ -0,0 +1,121 @@
+//! The configuration of an `NtpClient`, which may be persisted and reloaded.
+
+use crate::protocol::PrimarySource;
+use crate::SelectionPolicy;
+#[cfg(feature = "proxy")]
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Every option of an `NtpClient` that is plain data, as set by its builder methods.
+///
+/// A configuration defined once, e.g. read from a TOML or JSON file with the `serde` feature, is
+/// applied with `NtpClient::from_config` rather than by calling each builder method in turn, and
+/// that of a client is returned by `NtpClient::config`. Each field is documented by the builder
+/// method of the same name. With the `serde` feature, fields missing from the serialized form
+/// take their default values.
+///
+/// The resolver, clock, random number generator and socket of a client are not data, and are
+/// set on the client built from the configuration.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct NtpClientConfig {
+    /// See `NtpClient::timeout`.
+    pub timeout: Option<Duration>,
+    /// See `NtpClient::max_reference_age`.
+    pub max_reference_age: Option<Duration>,
+    /// See `NtpClient::require_synchronized`.
+    pub require_synchronized: bool,
+    /// See `NtpClient::require_primary`.
+    pub require_primary: bool,
+    /// See `NtpClient::require_reference`, which also sets `require_primary`.
+    pub require_reference: Option<PrimarySource>,
+    /// See `NtpClient::reject_orphan`.
+    pub reject_orphan: Option<u8>,
+    /// See `NtpClient::max_acceptable_precision`.
+    pub max_acceptable_precision: Option<f64>,
+    /// See `NtpClient::interleaved`.
+    pub interleaved: bool,
+    /// See `NtpClient::poll`.
+    pub poll: i8,
+    /// See `NtpClient::precision`.
+    pub precision: Option<i8>,
+    /// See `NtpClient::delay_asymmetry`.
+    pub delay_asymmetry: f64,
+    /// See `NtpClient::allow_unexpected_extensions`.
+    pub allow_unexpected_extensions: bool,
+    /// See `NtpClient::allow_zero_padding`.
+    pub allow_zero_padding: bool,
+    /// See `NtpClient::unique_id`.
+    pub unique_id: bool,
+    /// See `NtpClient::ntpv5`.
+    #[cfg(feature = "ntpv5")]
+    pub ntpv5: bool,
+    /// See `NtpClient::use_tcp`. Ignored if `socks5_proxy` is set.
+    pub use_tcp: bool,
+    /// See `NtpClient::dscp`.
+    pub dscp: Option<u8>,
+    /// See `NtpClient::socks5_proxy`.
+    #[cfg(feature = "proxy")]
+    pub socks5_proxy: Option<Socks5ProxyConfig>,
+    /// See `NtpClient::min_query_interval`.
+    pub min_query_interval: Option<Duration>,
+    /// See `NtpClient::recv_buffer_size`.
+    pub recv_buffer_size: Option<usize>,
+    /// See `NtpClient::max_concurrency`.
+    pub max_concurrency: Option<usize>,
+    /// See `NtpClient::outlier_sigma`.
+    pub outlier_sigma: Option<f64>,
+    /// See `NtpClient::sanity_check_local`.
+    pub sanity_check_local: bool,
+    /// See `NtpClient::max_offset`.
+    pub max_offset: Option<Duration>,
+    /// See `NtpClient::stable_jitter`.
+    pub stable_jitter: Option<Duration>,
+    /// See `NtpClient::selection_policy`.
+    pub selection_policy: SelectionPolicy,
+    /// See `NtpClient::fallback_addrs`.
+    pub fallback_addrs: Vec<SocketAddr>,
+    /// See `NtpClient::history_capacity`.
+    pub history_capacity: usize,
+}
+
+/// The SOCKS5 proxy requests are relayed through, as set by `NtpClient::socks5_proxy`.
+///
+/// Requires the `proxy` feature.
+#[cfg(feature = "proxy")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Socks5ProxyConfig {
+    /// The address of the proxy.
+    pub addr: SocketAddr,
+    /// The credentials to authenticate to the proxy with, if it requires them.
+    pub auth: Option<Socks5Credentials>,
+}
+
+/// A username and password to authenticate to a SOCKS5 proxy with.
+///
+/// The password is kept out of the `Debug` output and, with the `serde` feature, out of the
+/// serialized form, so that logging or saving a configuration does not leak it. It is read when
+/// deserializing, so a configuration file written by hand may hold it.
+#[cfg(feature = "proxy")]
+#[derive(Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Socks5Credentials {
+    /// The username, which is serialized.
+    pub username: String,
+    /// The password, which is never serialized.
+    #[cfg_attr(feature = "serde", serde(skip_serializing, default))]
+    pub password: String,
+}
+
+#[cfg(feature = "proxy")]
+impl fmt::Debug for Socks5Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Socks5Credentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
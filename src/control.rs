@@ -0,0 +1,539 @@
+//! NTP control messages (mode 6), as used by monitoring tools such as `ntpq`.
+//!
+//! Control messages share the first header byte with ordinary NTP packets but are otherwise an
+//! entirely separate format, used to read and write the variables of a server's system and peer
+//! associations. Large responses are split across several datagrams which must be reassembled by
+//! their data offset before the payload can be interpreted.
+//!
+//! Documentation is largely derived from IETF RFC 9327 (originally RFC 1305 Appendix B).
+
+use crate::protocol::{self, ReadBytes, ReadFromBytes, WriteBytes, WriteToBytes};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, BE};
+use conv::TryFrom;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Duration;
+
+#[cfg(feature = "async-std")]
+use async_std::net::{ToSocketAddrs, UdpSocket};
+#[cfg(feature = "tokio")]
+use tokio::net::{ToSocketAddrs, UdpSocket};
+
+/// The size of the control message header in bytes.
+pub const HEADER_SIZE_BYTES: usize = 12;
+
+/// The maximum number of data octets carried by a single control message.
+pub const MAX_DATA_SIZE_BYTES: usize = 468;
+
+/// The association ID used to address the server's system variables rather than a peer.
+pub const SYSTEM_ASSOCIATION_ID: u16 = 0;
+
+/// How long `read_variables` and `list_associations` wait for each response before giving up.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Large enough for a full fragment followed by padding and an optional MAC.
+const RECV_BUFFER_SIZE_BYTES: usize = 1024;
+
+// Sequence numbers used to match responses to requests.
+static SEQUENCE: AtomicU16 = AtomicU16::new(1);
+
+custom_derive! {
+    /// A 5-bit integer identifying the control operation.
+    #[repr(u8)]
+    #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, TryFrom(u8))]
+    pub enum OpCode {
+        /// Read the status word of the system or of every peer association.
+        ReadStatus = 1,
+        /// Read the variables of the system or of a peer association.
+        ReadVariables = 2,
+        /// Write the variables of the system or of a peer association.
+        WriteVariables = 3,
+        /// Read the variables of a reference clock.
+        ReadClockVariables = 4,
+        /// Write the variables of a reference clock.
+        WriteClockVariables = 5,
+        /// Set a trap address.
+        SetTrapAddress = 6,
+        /// A trap response.
+        TrapResponse = 7,
+    }
+}
+
+/// **Control Message** - A single mode 6 request or response fragment.
+///
+/// ### Layout
+///
+/// ```ignore
+///  0                   1                   2                   3
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |LI | VN  |Mode |R|E|M| OpCode  |       Sequence Number         |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |            Status             |       Association ID          |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |            Offset             |            Count              |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                                                               |
+/// .                                                               .
+/// .                       Data (468 octets max)                   .
+/// .                                                               .
+/// |                               |        Padding (optional)     |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+///
+/// The `Count` field is not stored explicitly as it is always the length of `data`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ControlMessage {
+    pub leap_indicator: protocol::LeapIndicator,
+    pub version: protocol::Version,
+    /// Set by the server on responses.
+    pub response: bool,
+    /// Set by the server when the request could not be fulfilled. The error code is carried in
+    /// the high byte of `status`.
+    pub error: bool,
+    /// Set when further fragments of the response follow.
+    pub more: bool,
+    pub op_code: OpCode,
+    /// Chosen by the client and echoed by the server so responses can be matched to requests.
+    pub sequence: u16,
+    /// The system or peer status word, depending on the association.
+    pub status: u16,
+    pub association_id: u16,
+    /// The offset of this fragment's data within the reassembled payload.
+    pub offset: u16,
+    pub data: Vec<u8>,
+}
+
+impl ControlMessage {
+    /// Create a request for the given operation on the given association.
+    pub fn request(op_code: OpCode, sequence: u16, association_id: u16) -> Self {
+        ControlMessage {
+            leap_indicator: protocol::LeapIndicator::NoWarning,
+            version: protocol::Version::V2,
+            response: false,
+            error: false,
+            more: false,
+            op_code,
+            sequence,
+            status: 0,
+            association_id,
+            offset: 0,
+            data: Vec::new(),
+        }
+    }
+
+    /// The error code reported by the server, if the error bit is set.
+    pub fn error_code(&self) -> Option<u8> {
+        if self.error {
+            Some((self.status >> 8) as u8)
+        } else {
+            None
+        }
+    }
+}
+
+custom_derive! {
+    /// A 3-bit integer describing the outcome of the clock selection algorithm for a peer.
+    #[repr(u8)]
+    #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, TryFrom(u8))]
+    pub enum Selection {
+        /// Discarded as not valid (TEST10-TEST13).
+        Reject = 0,
+        /// Discarded by the intersection algorithm.
+        Falseticker = 1,
+        /// Discarded by the table overflow (not used).
+        Excess = 2,
+        /// Discarded by the cluster algorithm.
+        Outlier = 3,
+        /// Included by the combine algorithm.
+        Candidate = 4,
+        /// Backup (more than `tos maxclock` sources).
+        Backup = 5,
+        /// The system peer.
+        SystemPeer = 6,
+        /// The PPS (pulse per second) peer.
+        PpsPeer = 7,
+    }
+}
+
+/// The 16-bit status word of a peer association.
+///
+/// ```ignore
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |  Status | Sel | Count | Code  |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct PeerStatus(pub u16);
+
+/// A peer association of a server, as listed by `ntpq -c associations`.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Association {
+    pub id: u16,
+    pub status: PeerStatus,
+    /// The stratum of the peer, if reported by the server.
+    pub stratum: Option<protocol::Stratum>,
+}
+
+impl PeerStatus {
+    /// Whether or not the association was created from the configuration file.
+    pub fn is_configured(&self) -> bool {
+        self.0 & 0x8000 != 0
+    }
+
+    /// Whether or not authentication is enabled for the association.
+    pub fn is_authentication_enabled(&self) -> bool {
+        self.0 & 0x4000 != 0
+    }
+
+    /// Whether or not the association has been authenticated.
+    pub fn is_authentic(&self) -> bool {
+        self.0 & 0x2000 != 0
+    }
+
+    /// Whether or not the peer is reachable.
+    pub fn is_reachable(&self) -> bool {
+        self.0 & 0x1000 != 0
+    }
+
+    /// Whether or not the association is a broadcast association.
+    pub fn is_broadcast(&self) -> bool {
+        self.0 & 0x0800 != 0
+    }
+
+    /// The outcome of the clock selection algorithm for the peer.
+    pub fn selection(&self) -> Selection {
+        // All eight 3-bit values are defined.
+        Selection::try_from((self.0 >> 8) as u8 & 0b111).unwrap_or(Selection::Reject)
+    }
+
+    /// The number of events since the last time the event code changed.
+    pub fn event_count(&self) -> u8 {
+        (self.0 >> 4) as u8 & 0b1111
+    }
+
+    /// The code of the most recent peer event.
+    pub fn event_code(&self) -> u8 {
+        self.0 as u8 & 0b1111
+    }
+}
+
+// Writer implementations.
+
+impl WriteToBytes for ControlMessage {
+    fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
+        if self.data.len() > MAX_DATA_SIZE_BYTES {
+            let err_msg = "control message data too long";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg));
+        }
+        let li_vn_mode = (self.leap_indicator, self.version, protocol::Mode::NtpControlMessage);
+        writer.write_bytes(li_vn_mode)?;
+        let mut rem_op = self.op_code as u8;
+        rem_op |= (self.response as u8) << 7;
+        rem_op |= (self.error as u8) << 6;
+        rem_op |= (self.more as u8) << 5;
+        writer.write_u8(rem_op)?;
+        writer.write_u16::<BE>(self.sequence)?;
+        writer.write_u16::<BE>(self.status)?;
+        writer.write_u16::<BE>(self.association_id)?;
+        writer.write_u16::<BE>(self.offset)?;
+        writer.write_u16::<BE>(self.data.len() as u16)?;
+        writer.write_all(&self.data)?;
+        // Pad the data out to a 32-bit boundary.
+        let padding = (4 - self.data.len() % 4) % 4;
+        writer.write_all(&[0u8; 3][..padding])?;
+        Ok(())
+    }
+}
+
+// Reader implementations.
+
+impl ReadFromBytes for ControlMessage {
+    fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
+        let (leap_indicator, version, mode): protocol::PacketByte1 = reader.read_bytes()?;
+        if mode != protocol::Mode::NtpControlMessage {
+            let err_msg = "not a control message";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        }
+        let rem_op = reader.read_u8()?;
+        let op_code = match OpCode::try_from(rem_op & 0b1_1111).ok() {
+            Some(op_code) => op_code,
+            None => {
+                let err_msg = format!("unknown control opcode {}", rem_op & 0b1_1111);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+            },
+        };
+        let sequence = reader.read_u16::<BE>()?;
+        let status = reader.read_u16::<BE>()?;
+        let association_id = reader.read_u16::<BE>()?;
+        let offset = reader.read_u16::<BE>()?;
+        let count = reader.read_u16::<BE>()? as usize;
+        if count > MAX_DATA_SIZE_BYTES {
+            let err_msg = "control message data too long";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        }
+        let mut data = vec![0u8; count];
+        reader.read_exact(&mut data)?;
+        Ok(ControlMessage {
+            leap_indicator,
+            version,
+            response: rem_op & 0b1000_0000 != 0,
+            error: rem_op & 0b0100_0000 != 0,
+            more: rem_op & 0b0010_0000 != 0,
+            op_code,
+            sequence,
+            status,
+            association_id,
+            offset,
+            data,
+        })
+    }
+}
+
+/// Reassembles the data of a fragmented control response.
+///
+/// Fragments may arrive in any order. The response is complete once the final fragment (the one
+/// without the "more" bit) has been received and the data of all fragments is contiguous.
+#[derive(Clone, Debug, Default)]
+pub struct Reassembly {
+    fragments: Vec<(usize, Vec<u8>)>,
+    end: Option<usize>,
+}
+
+impl Reassembly {
+    /// Create a new, empty **Reassembly**.
+    pub fn new() -> Self {
+        Reassembly::default()
+    }
+
+    /// Add a response fragment.
+    ///
+    /// Returns an error if the fragment reports a server error, overlaps the data received so
+    /// far, ends past the end of the response given by the final fragment or, being the final
+    /// fragment, ends before data already received. A fragment other than the final one must
+    /// carry data.
+    pub fn insert(&mut self, fragment: ControlMessage) -> io::Result<()> {
+        if let Some(code) = fragment.error_code() {
+            let err_msg = format!("control request failed: {}", error_code_description(code));
+            return Err(io::Error::other(err_msg));
+        }
+        let start = fragment.offset as usize;
+        let end = start + fragment.data.len();
+        if fragment.more && fragment.data.is_empty() {
+            let err_msg = "empty control response fragment";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        }
+        let overlaps = self.fragments
+            .iter()
+            .any(|(s, d)| start < s + d.len() && *s < end);
+        if overlaps {
+            let err_msg = "overlapping control response fragments";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        }
+        let beyond_end = match self.end {
+            Some(response_end) => end > response_end,
+            None => false,
+        };
+        let received_end = self.fragments.last().map_or(0, |(s, d)| s + d.len());
+        if beyond_end || (!fragment.more && end < received_end) {
+            let err_msg = "control response fragment beyond the end of the response";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        }
+        if !fragment.more {
+            self.end = Some(end);
+        }
+        // Fragments are kept in order of their offsets.
+        let index = self.fragments.partition_point(|&(s, _)| s < start);
+        self.fragments.insert(index, (start, fragment.data));
+        Ok(())
+    }
+
+    /// Whether or not all fragments of the response have been received.
+    pub fn is_complete(&self) -> bool {
+        let end = match self.end {
+            Some(end) => end,
+            None => return false,
+        };
+        let mut received = 0;
+        for (start, data) in &self.fragments {
+            if *start != received {
+                return false;
+            }
+            received += data.len();
+        }
+        received == end
+    }
+
+    /// The reassembled data, or `None` if fragments are still missing.
+    pub fn into_data(self) -> Option<Vec<u8>> {
+        if !self.is_complete() {
+            return None;
+        }
+        Some(self.fragments.into_iter().flat_map(|(_, d)| d).collect())
+    }
+}
+
+/// Parse the `key=value` payload of a read variables response.
+///
+/// Variables are separated by commas and optional whitespace. Quoted values may contain commas and
+/// have their quotes removed. Variables without a value map to an empty string.
+pub fn parse_variables(data: &[u8]) -> io::Result<HashMap<String, String>> {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text.trim_end_matches('\0'),
+        Err(_) => {
+            let err_msg = "control variables are not valid ascii";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        }
+    };
+    let mut variables = HashMap::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices().chain(Some((text.len(), ','))) {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                let item = text[start..i].trim();
+                start = i + 1;
+                if item.is_empty() {
+                    continue;
+                }
+                let (key, value) = match item.find('=') {
+                    Some(eq) => (item[..eq].trim(), item[eq + 1..].trim()),
+                    None => (item, ""),
+                };
+                let value = value.trim_start_matches('"').trim_end_matches('"');
+                variables.insert(key.to_string(), value.to_string());
+            }
+            _ => (),
+        }
+    }
+    if in_quotes {
+        let err_msg = "unterminated quoted control variable";
+        return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+    }
+    Ok(variables)
+}
+
+/// Send a control request to an ntp server and reassemble the fragments of its response.
+///
+///   `addr` can be any valid socket address
+///   `data` is the request payload, e.g. a comma separated list of variable names to read
+///   `timeout` bounds the wait for the whole response, failing with `TimedOut` once it passes
+///   returns the reassembled response data or an error if the server reports one.
+///
+/// Datagrams from any address other than the server's, and those that are not well-formed
+/// control messages, are discarded while the response is reassembled.
+pub async fn query<A: ToSocketAddrs>(
+    addr: A,
+    op_code: OpCode,
+    association_id: u16,
+    data: &[u8],
+    timeout: Duration,
+) -> io::Result<Vec<u8>> {
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let mut request = ControlMessage::request(op_code, sequence, association_id);
+    request.data = data.to_vec();
+    let mut bytes = Vec::with_capacity(HEADER_SIZE_BYTES);
+    bytes.write_bytes(&request)?;
+
+    let server = crate::client::resolve_first(addr).await?;
+    let local_addr = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let sock = UdpSocket::bind(local_addr).await?;
+    let sz = sock.send_to(&bytes, server).await?;
+    debug!("sent control request: {}", sz);
+
+    let reassemble = async {
+        let mut reassembly = Reassembly::new();
+        let mut buf = [0u8; RECV_BUFFER_SIZE_BYTES];
+        while !reassembly.is_complete() {
+            let (len, src) = sock.recv_from(&mut buf[..]).await?;
+            debug!("recv control fragment from {}: {}", src, len);
+            if src != server {
+                debug!("discarding datagram from {}", src);
+                continue;
+            }
+            let fragment: ControlMessage = match (&buf[..len]).read_bytes() {
+                Ok(fragment) => fragment,
+                Err(err) => {
+                    debug!("discarding malformed control fragment: {}", err);
+                    continue;
+                }
+            };
+            // Ignore anything that isn't a response to this particular request.
+            if !fragment.response || fragment.sequence != sequence || fragment.op_code != op_code {
+                continue;
+            }
+            reassembly.insert(fragment)?;
+        }
+        Ok(reassembly.into_data().unwrap_or_default())
+    };
+    match crate::client::with_timeout(timeout, reassemble).await {
+        Some(result) => result,
+        None => {
+            let err_msg = "timed out waiting for the control response";
+            Err(io::Error::new(io::ErrorKind::TimedOut, err_msg))
+        }
+    }
+}
+
+/// Read the variables of the given association from an ntp server, like `ntpq -c readvar`.
+///
+///   `addr` can be any valid socket address
+///   `association_id` selects a peer, or `SYSTEM_ASSOCIATION_ID` for the system variables
+///   returns the variables by name or an error if the server cannot be reached or rejects the
+///   request.
+///
+pub async fn read_variables<A: ToSocketAddrs>(
+    addr: A,
+    association_id: u16,
+) -> io::Result<HashMap<String, String>> {
+    let data = query(addr, OpCode::ReadVariables, association_id, &[], DEFAULT_TIMEOUT).await?;
+    parse_variables(&data)
+}
+
+/// List the peer associations of an ntp server, like `ntpq -c associations`.
+///
+///   `addr` can be any valid socket address
+///   returns each association along with its status word and stratum, or an error if the server
+///   cannot be reached or rejects the request.
+///
+pub async fn list_associations<A: ToSocketAddrs + Clone>(addr: A) -> io::Result<Vec<Association>> {
+    let op_code = OpCode::ReadStatus;
+    let data = query(addr.clone(), op_code, SYSTEM_ASSOCIATION_ID, &[], DEFAULT_TIMEOUT).await?;
+    if data.len() % 4 != 0 {
+        let err_msg = "invalid association list length";
+        return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+    }
+    let mut associations = Vec::with_capacity(data.len() / 4);
+    let mut reader = &data[..];
+    while !reader.is_empty() {
+        let id = reader.read_u16::<BE>()?;
+        let status = PeerStatus(reader.read_u16::<BE>()?);
+        let variables =
+            query(addr.clone(), OpCode::ReadVariables, id, b"stratum", DEFAULT_TIMEOUT).await?;
+        let stratum = parse_variables(&variables)?
+            .get("stratum")
+            .and_then(|s| s.parse().ok())
+            .map(protocol::Stratum);
+        associations.push(Association { id, status, stratum });
+    }
+    Ok(associations)
+}
+
+// Utility functions.
+
+fn error_code_description(code: u8) -> &'static str {
+    match code {
+        1 => "authentication failure",
+        2 => "invalid message length or format",
+        3 => "invalid opcode",
+        4 => "unknown association identifier",
+        5 => "unknown variable name",
+        6 => "invalid variable value",
+        7 => "administratively prohibited",
+        _ => "unspecified error",
+    }
+}
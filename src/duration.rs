@@ -0,0 +1,193 @@
+//! A signed span of time, as needed for clock offsets.
+
+use crate::{Error, Instant};
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+use std::time::Duration;
+
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+
+/// A span of time that, unlike `std::time::Duration`, may be negative.
+///
+/// Like **Instant**, the duration is described by a `secs` and a `subsec_nanos` component, both
+/// of which are negative for a negative duration.
+///
+/// A clock offset is positive when the local clock is behind the server's clock and negative when
+/// it is ahead. Every offset of the API is a **SignedDuration**, as is the difference of two
+/// **Instant**s, so that the sign of an offset is never carried separately.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SignedDuration {
+    secs: i64,
+    subsec_nanos: i32,
+}
+
+impl SignedDuration {
+    /// A duration of zero.
+    pub const ZERO: Self = SignedDuration { secs: 0, subsec_nanos: 0 };
+
+    /// Create a new **SignedDuration** given its `secs` and `subsec_nanos` components.
+    ///
+    /// To indicate a positive duration, both `secs` and `subsec_nanos` must be positive. To
+    /// indicate a negative duration, both must be negative. Violating these invariants or passing
+    /// a `subsec_nanos` of a second or more will result in a **panic!**; see `try_new`.
+    pub fn new(secs: i64, subsec_nanos: i32) -> Self {
+        match SignedDuration::try_new(secs, subsec_nanos) {
+            Ok(duration) => duration,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Create a new **SignedDuration** as `new` does, returning `Error::InvalidComponents` rather
+    /// than panicking should the components violate its invariants.
+    pub fn try_new(secs: i64, subsec_nanos: i32) -> Result<Self, Error> {
+        let err_msg = if secs > 0 && subsec_nanos < 0 {
+            "invalid duration: secs was positive but subsec_nanos was negative"
+        } else if secs < 0 && subsec_nanos > 0 {
+            "invalid duration: secs was negative but subsec_nanos was positive"
+        } else if subsec_nanos.unsigned_abs() >= NANOS_PER_SEC as u32 {
+            "invalid duration: subsec_nanos was a second or more"
+        } else {
+            return Ok(SignedDuration { secs, subsec_nanos });
+        };
+        Err(Error::InvalidComponents(err_msg.to_string()))
+    }
+
+    /// Create a new **SignedDuration** from a whole number of nanoseconds, saturating at the
+    /// longest durations representable.
+    pub fn from_nanos(nanos: i128) -> Self {
+        let nanos = nanos.clamp(
+            i64::MIN as i128 * NANOS_PER_SEC - (NANOS_PER_SEC - 1),
+            i64::MAX as i128 * NANOS_PER_SEC + (NANOS_PER_SEC - 1),
+        );
+        SignedDuration {
+            secs: (nanos / NANOS_PER_SEC) as i64,
+            subsec_nanos: (nanos % NANOS_PER_SEC) as i32,
+        }
+    }
+
+    /// The "seconds" component of the **SignedDuration**.
+    pub fn secs(&self) -> i64 {
+        self.secs
+    }
+
+    /// The fractional component of the **SignedDuration** in nanoseconds.
+    pub fn subsec_nanos(&self) -> i32 {
+        self.subsec_nanos
+    }
+
+    /// The total number of nanoseconds in the **SignedDuration**.
+    pub fn as_nanos(&self) -> i128 {
+        self.secs as i128 * NANOS_PER_SEC + self.subsec_nanos as i128
+    }
+
+    /// The **SignedDuration** in fractional seconds.
+    pub fn as_secs_f64(&self) -> f64 {
+        self.secs as f64 + self.subsec_nanos as f64 / 1e9
+    }
+
+    /// The **SignedDuration** scaled by `ppm` parts per million, rounded to the nearest
+    /// nanosecond: the drift accumulated over an interval by a clock whose rate is off by `ppm`,
+    /// e.g. 180ms over an hour at 50ppm.
+    ///
+    /// The interval is scaled in nanoseconds rather than through `as_secs_f64`, so that the
+    /// result is precise to the nanosecond for intervals of up to about 100 days.
+    pub fn scaled_by_ppm(&self, ppm: f64) -> Self {
+        SignedDuration::from_nanos((self.as_nanos() as f64 * ppm / 1e6).round() as i128)
+    }
+
+    /// Whether or not the **SignedDuration** is less than zero, as is the offset of a local
+    /// clock ahead of the server's.
+    pub fn is_negative(&self) -> bool {
+        self.secs < 0 || self.subsec_nanos < 0
+    }
+
+    /// The absolute value of the **SignedDuration**.
+    pub fn abs(&self) -> Self {
+        if self.is_negative() {
+            -*self
+        } else {
+            *self
+        }
+    }
+
+    /// The magnitude of the **SignedDuration** as a `std::time::Duration`, e.g. to compare an
+    /// offset against a threshold regardless of its sign.
+    pub fn unsigned_abs(&self) -> Duration {
+        let abs = self.abs();
+        Duration::new(abs.secs as u64, abs.subsec_nanos as u32)
+    }
+}
+
+impl From<Duration> for SignedDuration {
+    /// Durations beyond the roughly 292 billion years representable in `i64` seconds saturate.
+    fn from(d: Duration) -> Self {
+        SignedDuration {
+            secs: d.as_secs().min(i64::MAX as u64) as i64,
+            subsec_nanos: d.subsec_nanos() as i32,
+        }
+    }
+}
+
+// Arithmetic implementations.
+
+impl Neg for SignedDuration {
+    type Output = SignedDuration;
+    fn neg(self) -> Self::Output {
+        SignedDuration { secs: -self.secs, subsec_nanos: -self.subsec_nanos }
+    }
+}
+
+impl Add for SignedDuration {
+    type Output = SignedDuration;
+    fn add(self, d: SignedDuration) -> Self::Output {
+        SignedDuration::from_nanos(self.as_nanos() + d.as_nanos())
+    }
+}
+
+impl Sub for SignedDuration {
+    type Output = SignedDuration;
+    fn sub(self, d: SignedDuration) -> Self::Output {
+        self + -d
+    }
+}
+
+/// Scales the duration by `factor`, rounding to the nearest nanosecond. The duration is a span of
+/// time rather than an **Instant**, which cannot be scaled.
+impl Mul<f64> for SignedDuration {
+    type Output = SignedDuration;
+    fn mul(self, factor: f64) -> Self::Output {
+        SignedDuration::from_nanos((self.as_nanos() as f64 * factor).round() as i128)
+    }
+}
+
+impl AddAssign for SignedDuration {
+    fn add_assign(&mut self, d: SignedDuration) {
+        *self = *self + d;
+    }
+}
+
+impl SubAssign for SignedDuration {
+    fn sub_assign(&mut self, d: SignedDuration) {
+        *self = *self - d;
+    }
+}
+
+impl Sub for Instant {
+    type Output = SignedDuration;
+    fn sub(self, other: Instant) -> Self::Output {
+        SignedDuration::from_nanos(self.as_nanos() - other.as_nanos())
+    }
+}
+
+impl Add<SignedDuration> for Instant {
+    type Output = Instant;
+    fn add(self, d: SignedDuration) -> Self::Output {
+        Instant::from_nanos(self.as_nanos() + d.as_nanos())
+    }
+}
+
+impl Sub<SignedDuration> for Instant {
+    type Output = Instant;
+    fn sub(self, d: SignedDuration) -> Self::Output {
+        self + -d
+    }
+}
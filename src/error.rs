@@ -0,0 +1,123 @@
+//! The error type returned by the fallible operations of this crate.
+
+use crate::protocol::KissOfDeath;
+use crate::{Instant, SignedDuration};
+use std::time::Duration;
+use std::{error, fmt, io};
+
+/// Errors that can occur when building, sending or interpreting NTP packets.
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O error occurred while communicating with the server.
+    Io(io::Error),
+    /// The instant precedes the prime epoch (0 h 1 January 1900 UTC) and cannot be represented by
+    /// the unsigned NTP timestamp formats.
+    BeforePrimeEpoch(Instant),
+    /// The server address could not be resolved to a socket address. Holds the name given to
+    /// the client's resolver, or otherwise the system resolver's description of the failure.
+    Resolution(String),
+    /// No response arrived from the server within the client's timeout.
+    Timeout,
+    /// The server responded with a kiss-o'-death message, asking the client to back off or stop
+    /// querying it.
+    KissOfDeath(KissOfDeath),
+    /// The server responded with stratum 0 (unspecified) but no recognized kiss code, so its
+    /// clock is not synchronized. Unlike a kiss-o'-death, it does not ask the client to back off.
+    Unsynchronized,
+    /// The response is well-formed but was rejected by the client, for the given reason.
+    InvalidPacket(String),
+    /// The server last synchronized its clock longer ago than the client is configured to accept.
+    StaleReference(Duration),
+    /// The response did not echo the unique identifier sent with the request, so it may have been
+    /// forged or replayed.
+    Spoofed,
+    /// The server responded with the given version, which is later than the version of the
+    /// request and whose header may not be laid out as the client expects.
+    UnsupportedVersion(u8),
+    /// The response is the request itself sent back, as by a misconfigured middlebox or a server
+    /// that reflects datagrams: it is in client mode or carries the request's transmit timestamp.
+    Reflected,
+    /// The response carries the same transmit timestamp as the last response accepted from the
+    /// server, so it is a duplicate or a replay of that response (RFC 5905 TEST1).
+    Duplicate,
+    /// The local clock reads the given time preceding 1971, as the clock of a device without a
+    /// battery-backed real-time clock does until it is first set. Timestamps taken from such a
+    /// clock are meaningless; set it from `server_transmit_time` before measuring the offset.
+    LocalClockUnset(Instant),
+    /// The measured offset, given, exceeds `IMPLAUSIBLE_LOCAL_OFFSET`, so the local clock is
+    /// clearly wrong and should be stepped to the server's time rather than slewed. Only
+    /// returned when enabled by `NtpClient::sanity_check_local`.
+    LocalClockImplausible(SignedDuration),
+    /// The measured offset, given, exceeds the bound set by `NtpClient::max_offset`, so the
+    /// server's time is not trusted.
+    OffsetTooLarge(SignedDuration),
+    /// The request was abandoned as the cancellation future passed to
+    /// `NtpClient::request_cancellable` completed first.
+    Cancelled,
+    /// `NtpClient::sample_until_stable` used up its attempts without the given number of
+    /// consecutive samples agreeing to within the client's `stable_jitter`.
+    NotStable(usize),
+    /// The components given to `Instant::try_new` or `SignedDuration::try_new` violate the
+    /// invariants of its type, for the given reason.
+    InvalidComponents(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "{}", err),
+            Error::BeforePrimeEpoch(instant) => write!(
+                f,
+                "instant {}s since the unix epoch precedes the ntp prime epoch",
+                instant.secs()
+            ),
+            Error::Resolution(ref host) => write!(f, "could not resolve ntp server: {}", host),
+            Error::Timeout => write!(f, "timed out waiting for a response"),
+            Error::KissOfDeath(code) => write!(f, "server sent kiss-o'-death {:?}", code),
+            Error::Unsynchronized => write!(f, "server clock is not synchronized"),
+            Error::InvalidPacket(ref reason) => write!(f, "invalid packet: {}", reason),
+            Error::StaleReference(age) => {
+                write!(f, "server last synchronized {}s ago", age.as_secs())
+            }
+            Error::Spoofed => write!(f, "response did not echo the request's unique identifier"),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "server responded with unsupported ntp version {}", version)
+            }
+            Error::Reflected => write!(f, "server sent back the request itself"),
+            Error::Duplicate => write!(f, "response duplicates one already accepted"),
+            Error::LocalClockUnset(now) => write!(
+                f,
+                "local clock has not been set, reading {}s since the unix epoch",
+                now.secs()
+            ),
+            Error::LocalClockImplausible(offset) => write!(
+                f,
+                "local clock is implausibly far from the server's, by {}s",
+                offset.as_secs_f64()
+            ),
+            Error::Cancelled => write!(f, "request was cancelled"),
+            Error::NotStable(min_samples) => {
+                write!(f, "offset did not stabilize over {} consecutive samples", min_samples)
+            }
+            Error::OffsetTooLarge(offset) => {
+                write!(f, "offset of {}s exceeds the maximum accepted", offset.as_secs_f64())
+            }
+            Error::InvalidComponents(ref reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
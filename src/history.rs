@@ -0,0 +1,283 @@
+//! A compact binary format for storing a long history of measurements.
+//!
+//! Each measurement is reduced to a fixed-size `MeasurementRecord` of 32 bytes holding what is
+//! needed to chart a server's offset and delay over time, so that a log of a measurement per
+//! minute grows by under 50 KB a day. Records are simply concatenated, so a log may be appended
+//! to by reopening the file, and a torn final record is detected on reading.
+
+use crate::protocol::{
+    ConstPackedSizeBytes, LeapIndicator, LogSeconds, ReadBytes, ReadFromBytes, Stratum,
+    WriteBytes, WriteToBytes,
+};
+use crate::{Instant, NtpMeasurement, SignedDuration};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, BE};
+use conv::TryFrom;
+use std::io;
+use std::time::Duration;
+
+/// A measurement reduced to a fixed-size binary record.
+///
+/// All fields are big-endian:
+///
+/// ```ignore
+/// +--------+------+------------------------------------------------------+
+/// | Offset | Size | Field                                                |
+/// +--------+------+------------------------------------------------------+
+/// | 0      | 8    | received, signed nanoseconds since the unix epoch    |
+/// | 8      | 8    | offset, signed nanoseconds                           |
+/// | 16     | 8    | delay, signed nanoseconds                            |
+/// | 24     | 1    | stratum                                              |
+/// | 25     | 1    | leap indicator                                       |
+/// | 26     | 1    | flags, bit 0 set if the measurement was interleaved  |
+/// | 27     | 1    | precision, signed log2 seconds                       |
+/// | 28     | 4    | reference identifier                                 |
+/// +--------+------+------------------------------------------------------+
+/// ```
+///
+/// Times and durations beyond the roughly 292 years representable in signed 64-bit nanoseconds
+/// saturate.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct MeasurementRecord {
+    /// Local time at which the response arrived from the server.
+    pub received: Instant,
+    /// The offset of the server's clock relative to the local clock.
+    pub offset: SignedDuration,
+    /// The round-trip network delay of the exchange.
+    pub delay: SignedDuration,
+    pub stratum: Stratum,
+    pub leap_indicator: LeapIndicator,
+    /// Whether or not the server responded in interleaved mode.
+    pub interleaved: bool,
+    /// The precision of the server's clock.
+    pub precision: LogSeconds,
+    /// The reference identifier of the server, as it appears on the wire.
+    pub reference_id: [u8; 4],
+}
+
+// The bit of the flags byte set for interleaved measurements.
+const FLAG_INTERLEAVED: u8 = 0x1;
+
+impl MeasurementRecord {
+    /// Encode the record as its 32 bytes.
+    pub fn to_bytes(&self) -> [u8; MeasurementRecord::PACKED_SIZE_BYTES] {
+        let mut bytes = [0u8; MeasurementRecord::PACKED_SIZE_BYTES];
+        (&mut bytes[..]).write_bytes(*self).expect("a record fits in its packed size");
+        bytes
+    }
+
+    /// Decode a record from its 32 bytes, failing with `InvalidData` should the leap indicator
+    /// hold an unknown value.
+    pub fn from_bytes(bytes: &[u8; MeasurementRecord::PACKED_SIZE_BYTES]) -> io::Result<Self> {
+        (&bytes[..]).read_bytes()
+    }
+}
+
+impl NtpMeasurement {
+    /// The measurement reduced to a `MeasurementRecord`.
+    pub fn record(&self) -> MeasurementRecord {
+        MeasurementRecord {
+            received: self.received,
+            offset: self.offset(),
+            delay: self.delay(),
+            stratum: self.packet.stratum,
+            leap_indicator: self.packet.leap_indicator,
+            interleaved: self.interleaved,
+            precision: self.packet.precision,
+            reference_id: self.packet.reference_id_raw(),
+        }
+    }
+
+    /// The measurement encoded as the 32 bytes of its `MeasurementRecord`, which
+    /// `MeasurementRecord::from_bytes` decodes.
+    pub fn to_bytes(&self) -> [u8; MeasurementRecord::PACKED_SIZE_BYTES] {
+        self.record().to_bytes()
+    }
+}
+
+/// Running statistics over a sequence of measurements, as kept by `NtpClient::history_capacity`.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct OffsetStats {
+    /// The number of measurements, which is zero if there were none.
+    pub count: usize,
+    /// The mean offset of the measurements.
+    pub mean_offset: SignedDuration,
+    /// The root mean square deviation of the offsets from their mean.
+    pub jitter: Duration,
+    /// The largest delay of the measurements.
+    pub max_delay: SignedDuration,
+}
+
+impl OffsetStats {
+    /// Compute the statistics of the given records, e.g. those read back by
+    /// `MeasurementLogReader`.
+    pub fn from_records<'a, I>(records: I) -> OffsetStats
+    where
+        I: IntoIterator<Item = &'a MeasurementRecord>,
+    {
+        let records: Vec<&MeasurementRecord> = records.into_iter().collect();
+        if records.is_empty() {
+            return OffsetStats::default();
+        }
+        let offsets: Vec<f64> = records.iter().map(|r| r.offset.as_nanos() as f64).collect();
+        let n = offsets.len() as f64;
+        let mean = offsets.iter().sum::<f64>() / n;
+        let variance = offsets.iter().map(|offset| (offset - mean).powi(2)).sum::<f64>() / n;
+        OffsetStats {
+            count: records.len(),
+            mean_offset: SignedDuration::from_nanos(mean.round() as i128),
+            jitter: Duration::from_nanos(variance.sqrt().round() as u64),
+            max_delay: records.iter().map(|r| r.delay).max().unwrap_or_default(),
+        }
+    }
+}
+
+/// Appends measurements to a stream as consecutive `MeasurementRecord`s.
+#[derive(Debug)]
+pub struct MeasurementLog<W> {
+    writer: W,
+}
+
+impl<W: io::Write> MeasurementLog<W> {
+    /// Create a **MeasurementLog** writing records to the given stream.
+    pub fn new(writer: W) -> Self {
+        MeasurementLog { writer }
+    }
+
+    /// Append the record of `measurement`.
+    pub fn append(&mut self, measurement: &NtpMeasurement) -> io::Result<()> {
+        self.append_record(&measurement.record())
+    }
+
+    /// Append a record.
+    pub fn append_record(&mut self, record: &MeasurementRecord) -> io::Result<()> {
+        self.writer.write_all(&record.to_bytes())
+    }
+
+    /// Flush the underlying stream.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Unwrap the underlying stream.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// An iterator over the records of a log written by `MeasurementLog`.
+///
+/// A malformed record yields an error without ending the iteration. A trailing record shorter
+/// than 32 bytes, as left by a write interrupted part way, yields an `UnexpectedEof` error and
+/// ends the iteration, as does any error reading from the underlying stream.
+#[derive(Debug)]
+pub struct MeasurementLogReader<R> {
+    reader: R,
+    done: bool,
+}
+
+impl<R: io::Read> MeasurementLogReader<R> {
+    /// Create a **MeasurementLogReader** reading records from the given stream.
+    pub fn new(reader: R) -> Self {
+        MeasurementLogReader { reader, done: false }
+    }
+
+    /// Unwrap the underlying stream.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    // Fill the buffer with the next record, returning the number of bytes read which is less than
+    // a record only at the end of the stream.
+    fn read_record(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut len = 0;
+        while len < buf.len() {
+            match self.reader.read(&mut buf[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl<R: io::Read> Iterator for MeasurementLogReader<R> {
+    type Item = io::Result<MeasurementRecord>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut buf = [0u8; MeasurementRecord::PACKED_SIZE_BYTES];
+        match self.read_record(&mut buf) {
+            Ok(0) => {
+                self.done = true;
+                None
+            }
+            Ok(len) if len < buf.len() => {
+                self.done = true;
+                let err_msg = "trailing record is shorter than a measurement record";
+                Some(Err(io::Error::new(io::ErrorKind::UnexpectedEof, err_msg)))
+            }
+            Ok(_) => Some(MeasurementRecord::from_bytes(&buf)),
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl ConstPackedSizeBytes for MeasurementRecord {
+    const PACKED_SIZE_BYTES: usize = 8 * 3 + 4 + 4;
+}
+
+impl WriteToBytes for MeasurementRecord {
+    fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_i64::<BE>(saturating_nanos(self.received.as_nanos()))?;
+        writer.write_i64::<BE>(saturating_nanos(self.offset.as_nanos()))?;
+        writer.write_i64::<BE>(saturating_nanos(self.delay.as_nanos()))?;
+        writer.write_bytes(self.stratum)?;
+        writer.write_u8(self.leap_indicator as u8)?;
+        writer.write_u8(if self.interleaved { FLAG_INTERLEAVED } else { 0 })?;
+        writer.write_bytes(self.precision)?;
+        writer.write_all(&self.reference_id)?;
+        Ok(())
+    }
+}
+
+impl ReadFromBytes for MeasurementRecord {
+    fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
+        let received = Instant::from_nanos(reader.read_i64::<BE>()? as i128);
+        let offset = SignedDuration::from_nanos(reader.read_i64::<BE>()? as i128);
+        let delay = SignedDuration::from_nanos(reader.read_i64::<BE>()? as i128);
+        let stratum = reader.read_bytes()?;
+        let leap_indicator_u8 = reader.read_u8()?;
+        let leap_indicator = match LeapIndicator::try_from(leap_indicator_u8) {
+            Ok(leap_indicator) => leap_indicator,
+            Err(_) => {
+                let err_msg = format!("unknown leap indicator {}", leap_indicator_u8);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+            }
+        };
+        let interleaved = reader.read_u8()? & FLAG_INTERLEAVED != 0;
+        let precision = reader.read_bytes()?;
+        let mut reference_id = [0u8; 4];
+        reader.read_exact(&mut reference_id)?;
+        Ok(MeasurementRecord {
+            received,
+            offset,
+            delay,
+            stratum,
+            leap_indicator,
+            interleaved,
+            precision,
+            reference_id,
+        })
+    }
+}
+
+fn saturating_nanos(nanos: i128) -> i64 {
+    nanos.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
@@ -6,88 +6,112 @@ extern crate conv;
 #[macro_use]
 extern crate log;
 extern crate byteorder;
+extern crate rand;
+#[cfg(target_os = "linux")]
+extern crate libc;
 
+mod association;
+mod batch;
+mod client;
+mod clock;
+mod config;
+pub mod control;
+mod duration;
+mod error;
+pub mod history;
+mod measurement;
+pub mod multicast;
+pub mod nts;
 pub mod protocol;
+#[cfg(feature = "proxy")]
+mod proxy;
+mod server_addr;
+#[cfg(target_os = "linux")]
+pub mod system;
+
+pub use association::Association;
+pub use batch::{
+    BatchResult, BurstReport, CombineReport, ComparisonReport, FastSyncReport, SelectionPolicy,
+    Verdict,
+};
+#[cfg(feature = "loadtest")]
+pub use batch::FloodReport;
+pub use client::{
+    NtpClient, DEFAULT_FALLBACK_ADDRS, DEFAULT_MAX_CONCURRENCY, DEFAULT_OUTLIER_SIGMA,
+    DEFAULT_RECV_BUFFER_SIZE_BYTES, DEFAULT_STABLE_JITTER, FAST_SYNC_BURST_COUNT,
+    FAST_SYNC_SPACING, IMPLAUSIBLE_LOCAL_OFFSET, MAX_RECV_BUFFER_SIZE_BYTES,
+};
+pub use clock::{Clock, SystemClock};
+pub use config::NtpClientConfig;
+#[cfg(feature = "proxy")]
+pub use config::{Socks5Credentials, Socks5ProxyConfig};
+pub use duration::SignedDuration;
+pub use error::Error;
+pub use server_addr::ServerAddr;
+pub use measurement::{
+    ntp_offset_delay, DisciplineAction, NtpMeasurement, PANIC_THRESHOLD, STEP_THRESHOLD,
+};
 
-use protocol::{ReadBytes, ConstPackedSizeBytes, WriteBytes};
 use std::io;
 
 #[cfg(feature = "async-std")]
-use async_std::net::{ToSocketAddrs, UdpSocket};
+use async_std::net::ToSocketAddrs;
 #[cfg(feature = "tokio")]
-use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::net::ToSocketAddrs;
 
 use anyhow::Result;
 use std::{self, time};
 
-/// Send an async request to an ntp server with a hardcoded 5 second timeout.
+/// Send an async request to an ntp server.
 ///
 ///   `addr` can be any valid socket address
 ///   returns an error if the server cannot be reached or the response is invalid.
 ///
 pub async fn request<A: ToSocketAddrs>(addr: A) -> io::Result<protocol::Packet> {
-    // Create a packet for requesting from an NTP server as a client.
-    let mut packet = {
-        let leap_indicator = protocol::LeapIndicator::default();
-        let version = protocol::Version::V4;
-        let mode = protocol::Mode::Client;
-        let poll = 0;
-        let precision = 0;
-        let root_delay = protocol::ShortFormat::default();
-        let root_dispersion = protocol::ShortFormat::default();
-        let transmit_timestamp = Instant::now().into();
-        let stratum = protocol::Stratum::UNSPECIFIED;
-        let src = protocol::PrimarySource::Null;
-        let reference_id = protocol::ReferenceIdentifier::PrimarySource(src);
-        let reference_timestamp = protocol::TimestampFormat::default();
-        let receive_timestamp = protocol::TimestampFormat::default();
-        let origin_timestamp = protocol::TimestampFormat::default();
-        protocol::Packet {
-            leap_indicator,
-            version,
-            mode,
-            stratum,
-            poll,
-            precision,
-            root_delay,
-            root_dispersion,
-            reference_id,
-            reference_timestamp,
-            origin_timestamp,
-            receive_timestamp,
-            transmit_timestamp,
-        }
-    };
-
-    // Write the packet to a slice of bytes.
-    let mut bytes = [0u8; protocol::Packet::PACKED_SIZE_BYTES];
-    (&mut bytes[..]).write_bytes(&packet)?;
-
-    // Create the socket from which we will send the packet.
-    let sock = UdpSocket::bind("0.0.0.0:0").await?;
-
-    // Send the data.
-    let sz = sock.send_to(&bytes, addr).await?;
-    debug!("{:?}", sock.local_addr());
-    debug!("sent: {}", sz);
-
-    // Receive the response.
-    let res = sock.recv(&mut bytes[..]).await?;
-    debug!("recv: {:?}", res);
-    debug!("{:?}", &bytes[..]);
+    let zero = protocol::TimestampFormat::default();
+    let request = client::request_packet(zero, zero, zero);
+    let buffer_size = client::DEFAULT_RECV_BUFFER_SIZE_BYTES;
+    let mut buf = Vec::new();
+    let (socket, clock) = (&client::SocketOptions::default(), &SystemClock);
+    let response =
+        client::exchange(addr, request, &[], &mut buf, buffer_size, socket, clock).await?;
+    Ok(response.measurement.packet)
+}
 
-    // Read the received packet from the response.
-    packet = (&bytes[..]).read_bytes()?;
-    Ok(packet)
+/// Send an async request to an ntp server and return the time at which the server sent its
+/// response, according to the server's clock.
+///
+/// The returned time is *uncorrected*: it does not account for the time the response took to
+/// arrive, so it is already behind the server's clock by the one-way network delay when it is
+/// returned. Use `NtpClient::request` and `NtpMeasurement::correct` to estimate the current time.
+pub async fn server_transmit_time<A: ToSocketAddrs>(addr: A) -> Result<Instant, Error> {
+    server_time(addr, protocol::TimestampSelector::Transmit).await
 }
 
+/// Send an async request to an ntp server and return the time of its response chosen by
+/// `selector`, according to the server's clock, as `server_transmit_time` does the transmit time.
+///
+/// Like that of `server_transmit_time`, the returned time is uncorrected. It is placed in the NTP
+/// era nearest the local clock, so that it remains correct beyond the 2036 rollover. Returns
+/// `Error::InvalidPacket` for the reference time of a server that has never synchronized, whose
+/// reference timestamp is zero.
+pub async fn server_time<A: ToSocketAddrs>(
+    addr: A,
+    selector: protocol::TimestampSelector,
+) -> Result<Instant, Error> {
+    let packet = request(addr).await?;
+    let timestamp = packet.timestamp(selector);
+    let zero = protocol::TimestampFormat::default();
+    if selector == protocol::TimestampSelector::Reference && timestamp == zero {
+        let err_msg = "server has never synchronized, its reference timestamp is zero";
+        return Err(Error::InvalidPacket(err_msg.to_string()));
+    }
+    Ok(timestamp.to_instant_near(Instant::now()))
+}
 
 /// The number of seconds from 1st January 1900 UTC to the start of the Unix epoch.
 pub const EPOCH_DELTA: i64 = 2_208_988_800;
 
-// The NTP fractional scale.
-const NTP_SCALE: f64 = std::u32::MAX as f64;
-
 /// Describes an instant relative to the `UNIX_EPOCH` - 00:00:00 Coordinated Universal Time (UTC),
 /// Thursay, 1 January 1970 in seconds with the fractional part in nanoseconds.
 ///
@@ -115,7 +139,7 @@ const NTP_SCALE: f64 = std::u32::MAX as f64;
 ///     println!("{}", local_time);
 /// }
 /// ```
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Instant {
     secs: i64,
     subsec_nanos: i32,
@@ -126,15 +150,29 @@ impl Instant {
     ///
     /// To indicate a time following `UNIX_EPOCH`, both `secs` and `subsec_nanos` must be positive.
     /// To indicate a time prior to `UNIX_EPOCH`, both `secs` and `subsec_nanos` must be negative.
-    /// Violating these invariants will result in a **panic!**.
+    /// Either way, `subsec_nanos` must be less than a second in magnitude. Violating these
+    /// invariants will result in a **panic!**. Use `try_new` for components that are not known
+    /// to be valid, e.g. those read from the network or a file.
     pub fn new(secs: i64, subsec_nanos: i32) -> Instant {
-        if secs > 0 && subsec_nanos < 0 {
-            panic!("invalid instant: secs was positive but subsec_nanos was negative");
+        match Instant::try_new(secs, subsec_nanos) {
+            Ok(instant) => instant,
+            Err(err) => panic!("{}", err),
         }
-        if secs < 0 && subsec_nanos > 0 {
-            panic!("invalid instant: secs was negative but subsec_nanos was positive");
-        }
-        Instant { secs, subsec_nanos }
+    }
+
+    /// Create a new **Instant** as `new` does, returning `Error::InvalidComponents` rather than
+    /// panicking should the components violate its invariants.
+    pub fn try_new(secs: i64, subsec_nanos: i32) -> Result<Instant, Error> {
+        let err_msg = if secs > 0 && subsec_nanos < 0 {
+            "invalid instant: secs was positive but subsec_nanos was negative"
+        } else if secs < 0 && subsec_nanos > 0 {
+            "invalid instant: secs was negative but subsec_nanos was positive"
+        } else if subsec_nanos.unsigned_abs() >= 1_000_000_000 {
+            "invalid instant: subsec_nanos was a second or more"
+        } else {
+            return Ok(Instant { secs, subsec_nanos });
+        };
+        Err(Error::InvalidComponents(err_msg.to_string()))
     }
 
     /// Uses `std::time::SystemTime::now` and `std::time::UNIX_EPOCH` to determine the current
@@ -165,6 +203,36 @@ impl Instant {
         }
     }
 
+    /// Estimate the resolution of the clock read by `Instant::now` as a log2 exponent in seconds,
+    /// suitable for the `precision` field of a packet.
+    ///
+    /// The clock is read repeatedly and the smallest nonzero step between consecutive readings is
+    /// rounded to the nearest power of two, e.g. `-24` for a step of 60 nanoseconds. This takes
+    /// up to a few milliseconds on clocks that advance in coarse ticks and so should be done once
+    /// at startup rather than per request. A clock that fails to advance within a second is
+    /// reported as `0`.
+    pub fn estimate_resolution() -> i8 {
+        const STEPS: usize = 16;
+        let start = time::Instant::now();
+        let mut steps = 0;
+        let mut smallest = i128::MAX;
+        let mut previous = Instant::now();
+        while steps < STEPS && start.elapsed() < time::Duration::from_secs(1) {
+            let now = Instant::now();
+            let step = now.as_nanos() - previous.as_nanos();
+            if step > 0 {
+                smallest = smallest.min(step);
+                steps += 1;
+            }
+            previous = now;
+        }
+        if steps == 0 {
+            return 0;
+        }
+        let exponent = (smallest as f64 / 1e9).log2().round();
+        exponent.clamp(i8::MIN as f64, 0.0) as i8
+    }
+
     /// The "seconds" component of the **Instant**.
     pub fn secs(&self) -> i64 {
         self.secs
@@ -174,6 +242,167 @@ impl Instant {
     pub fn subsec_nanos(&self) -> i32 {
         self.subsec_nanos
     }
+
+    /// The **Instant** rounded to the nearest multiple of `precision` since `UNIX_EPOCH`, e.g. to
+    /// the nearest millisecond. Halfway instants round up to the later multiple.
+    ///
+    /// Rounding up to the next second carries into `secs`. Instants preceding `UNIX_EPOCH` are
+    /// rounded in the same direction in time as later ones, so that `-0.0126s` rounds to
+    /// `-0.01s` at a precision of 10ms. A `precision` of zero returns the instant unchanged.
+    pub fn round_to(&self, precision: time::Duration) -> Instant {
+        let precision = precision.as_nanos() as i128;
+        if precision == 0 {
+            return *self;
+        }
+        let nanos = self.as_nanos() + precision / 2;
+        Instant::from_nanos(nanos - nanos.rem_euclid(precision))
+    }
+
+    /// The **Instant** truncated to the latest multiple of `precision` since `UNIX_EPOCH` not
+    /// after it, e.g. to the start of its millisecond.
+    ///
+    /// Instants preceding `UNIX_EPOCH` are truncated towards the earlier multiple, as their
+    /// fraction is by `format_rfc3339`, so that `-0.0126s` truncates to `-0.02s` at a precision
+    /// of 10ms. A `precision` of zero returns the instant unchanged.
+    pub fn truncate_to(&self, precision: time::Duration) -> Instant {
+        let precision = precision.as_nanos() as i128;
+        if precision == 0 {
+            return *self;
+        }
+        let nanos = self.as_nanos();
+        Instant::from_nanos(nanos - nanos.rem_euclid(precision))
+    }
+
+    /// The instant as the 64-bit NTP timestamp sent on the wire: the seconds since the prime
+    /// epoch in the upper 32 bits and the binary fraction of a second in the lower 32 bits.
+    ///
+    /// The fraction is rounded to the nearest 2^-32 seconds, saturating rather than carrying
+    /// into the seconds. The timestamp carries no NTP era, so instants beyond 7 February 2036
+    /// wrap into the next era, and those preceding 1900 into the previous one; see
+    /// `TimestampFormat::from_instant_checked` to detect the latter.
+    pub fn to_ntp64(&self) -> u64 {
+        let (seconds, fraction) = ntp_seconds_and_fraction(*self);
+        (seconds as u32 as u64) << 32 | fraction as u64
+    }
+
+    /// The instant of a 64-bit NTP timestamp as sent on the wire, taken in era 0 (1900 to 2036)
+    /// and rounded to the nearest nanosecond, the inverse of `to_ntp64`.
+    ///
+    /// Use `TimestampFormat::to_instant_near` or `TimestampFormat::to_instant_in_era` to place
+    /// it in another era.
+    pub fn from_ntp64(timestamp: u64) -> Instant {
+        let timestamp = protocol::TimestampFormat {
+            seconds: (timestamp >> 32) as u32,
+            fraction: timestamp as u32,
+        };
+        timestamp.to_instant_in_era(0)
+    }
+
+    // The total number of nanoseconds since the unix epoch.
+    pub(crate) fn as_nanos(&self) -> i128 {
+        self.secs as i128 * 1_000_000_000 + self.subsec_nanos as i128
+    }
+
+    // The instant a whole number of nanoseconds from the unix epoch, saturating at the earliest
+    // and latest instants representable.
+    //
+    // Truncating division leaves both components with the sign of `nanos`.
+    pub(crate) fn from_nanos(nanos: i128) -> Self {
+        let nanos = nanos.clamp(
+            i64::MIN as i128 * 1_000_000_000 - 999_999_999,
+            i64::MAX as i128 * 1_000_000_000 + 999_999_999,
+        );
+        let secs = (nanos / 1_000_000_000) as i64;
+        let subsec_nanos = (nanos % 1_000_000_000) as i32;
+        Instant { secs, subsec_nanos }
+    }
+
+    // The instant `secs` seconds and `subsec_nanos` nanoseconds from the unix epoch, where the
+    // components may have any sign or magnitude. Whole seconds are carried out of (or borrowed
+    // into) `subsec_nanos` so that the result upholds the invariants of `Instant::new`.
+    fn normalized(secs: i64, subsec_nanos: i64) -> Self {
+        Instant::from_nanos(secs as i128 * 1_000_000_000 + subsec_nanos as i128)
+    }
+
+    /// Format the **Instant** as an RFC 3339 UTC timestamp, truncated to the given precision.
+    ///
+    /// Instants preceding `UNIX_EPOCH` are formatted as the correct earlier date and time, with
+    /// a positive fraction counting forward from the preceding second. Years outside `0000` to
+    /// `9999`, which RFC 3339 cannot represent, are written with as many digits as needed and a
+    /// leading `-` for years before `0000`.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// extern crate nippy;
+    ///
+    /// use nippy::{Instant, SubsecPrecision};
+    ///
+    /// fn main() {
+    ///     let instant = Instant::new(1_714_564_800, 123_456_789);
+    ///     let formatted = instant.format_rfc3339(SubsecPrecision::Micros);
+    ///     assert_eq!(formatted, "2024-05-01T12:00:00.123456Z");
+    /// }
+    /// ```
+    pub fn format_rfc3339(&self, precision: SubsecPrecision) -> String {
+        let nanos = self.as_nanos();
+        let secs = nanos.div_euclid(1_000_000_000) as i64;
+        let subsec_nanos = nanos.rem_euclid(1_000_000_000) as u32;
+        let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+        let secs_of_day = secs.rem_euclid(86_400);
+        let (hour, minute, second) = (secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60);
+        let fraction = match precision {
+            SubsecPrecision::Seconds => String::new(),
+            SubsecPrecision::Millis => format!(".{:03}", subsec_nanos / 1_000_000),
+            SubsecPrecision::Micros => format!(".{:06}", subsec_nanos / 1_000),
+            SubsecPrecision::Nanos => format!(".{:09}", subsec_nanos),
+        };
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+            year, month, day, hour, minute, second, fraction
+        )
+    }
+}
+
+/// The number of fractional second digits produced by `Instant::format_rfc3339`.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum SubsecPrecision {
+    /// Whole seconds, e.g. `2024-05-01T12:00:00Z`.
+    Seconds,
+    /// Three digits, e.g. `2024-05-01T12:00:00.123Z`.
+    Millis,
+    /// Six digits, e.g. `2024-05-01T12:00:00.123456Z`.
+    Micros,
+    /// Nine digits, e.g. `2024-05-01T12:00:00.123456789Z`.
+    Nanos,
+}
+
+// The proleptic gregorian (year, month, day) of the given number of days since 1970-01-01.
+//
+// See Howard Hinnant's `civil_from_days` algorithm.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// The number of days since 1970-01-01 of the given proleptic gregorian date, the inverse of
+// `civil_from_days`.
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
 }
 
 // Conversion implementations.
@@ -181,44 +410,258 @@ impl Instant {
 impl From<protocol::ShortFormat> for Instant {
     fn from(t: protocol::ShortFormat) -> Self {
         let secs = t.seconds as i64 - EPOCH_DELTA;
-        let subsec_nanos = (t.fraction as f64 / NTP_SCALE * 1e9) as i32;
-        Instant::new(secs, subsec_nanos)
+        // The 16 bit fraction, rounded to the nearest nanosecond.
+        let subsec_nanos = (t.fraction as i64 * 1_000_000_000 + (1 << 15)) >> 16;
+        Instant::normalized(secs, subsec_nanos)
     }
 }
 
+/// The timestamp in NTP era 0, which ends on 7 February 2036. Use
+/// `TimestampFormat::to_instant_near` for timestamps that may be later.
 impl From<protocol::TimestampFormat> for Instant {
     fn from(t: protocol::TimestampFormat) -> Self {
-        let secs = t.seconds as i64 - EPOCH_DELTA;
-        let subsec_nanos = (t.fraction as f64 / NTP_SCALE * 1e9) as i32;
-        Instant::new(secs, subsec_nanos)
+        t.to_instant_in_era(0)
     }
 }
 
 impl From<Instant> for protocol::ShortFormat {
     fn from(t: Instant) -> Self {
-        let sec = t.secs() + EPOCH_DELTA;
-        let frac = t.subsec_nanos() as f64 * NTP_SCALE / 1e10;
+        let (secs, nanos) = positive_subsec_nanos(t);
         protocol::ShortFormat {
-            seconds: sec as u16,
-            fraction: frac as u16,
+            seconds: secs.saturating_add(EPOCH_DELTA) as u16,
+            fraction: fraction(nanos, 16) as u16,
         }
     }
 }
 
+/// **Deprecated**: instants preceding the prime epoch (1900) silently wrap into garbage
+/// timestamps. Use `TimestampFormat::from_instant_checked` instead.
 impl From<Instant> for protocol::TimestampFormat {
     fn from(t: Instant) -> Self {
-        let sec = t.secs() + EPOCH_DELTA;
-        let frac = t.subsec_nanos() as f64 * NTP_SCALE / 1e10;
+        let (seconds, fraction) = ntp_seconds_and_fraction(t);
         protocol::TimestampFormat {
-            seconds: sec as u32,
-            fraction: frac as u32,
+            seconds: seconds as u32,
+            fraction,
+        }
+    }
+}
+
+impl protocol::TimestampFormat {
+    /// Convert an **Instant** into an NTP timestamp.
+    ///
+    /// Returns `Error::BeforePrimeEpoch` if the instant precedes 1 January 1900, which the
+    /// unsigned timestamp cannot represent. Instants beyond 7 February 2036 wrap into the next
+    /// NTP era, as specified by RFC 5905.
+    pub fn from_instant_checked(t: Instant) -> Result<Self, Error> {
+        let (seconds, fraction) = ntp_seconds_and_fraction(t);
+        if seconds < 0 {
+            return Err(Error::BeforePrimeEpoch(t));
         }
+        Ok(protocol::TimestampFormat {
+            seconds: seconds as u32,
+            fraction,
+        })
+    }
+
+    /// The **Instant** of the timestamp in the given NTP era, rounding the fraction to the
+    /// nearest nanosecond. Era 0 began on 1 January 1900 and era 1 begins on 7 February 2036.
+    pub fn to_instant_in_era(&self, era: i32) -> Instant {
+        self.instant_in_any_era(era as i128)
+    }
+
+    // As `to_instant_in_era`, for an era beyond the range of `i32` as that of an extreme pivot
+    // may be, saturating at the earliest and latest instants representable.
+    fn instant_in_any_era(&self, era: i128) -> Instant {
+        let nanos = (self.fixed_in_era(era) * 1_000_000_000 + (1 << 31)) >> 32;
+        Instant::from_nanos(nanos - EPOCH_DELTA as i128 * 1_000_000_000)
+    }
+
+    // The timestamp in the given era as signed 32.32 fixed-point seconds since the prime epoch,
+    // in which it is exact.
+    fn fixed_in_era(&self, era: i128) -> i128 {
+        let secs = (era << 32) + self.seconds as i128;
+        (secs << 32) + self.fraction as i128
+    }
+
+    /// The **Instant** of the timestamp in the NTP era placing it nearest `pivot`, e.g. the
+    /// local time at which it was received.
+    ///
+    /// As an era spans 136 years, the result is correct provided that `pivot` is within 68
+    /// years of the time the timestamp represents. Should the local clock be further off, as
+    /// that of a device which has lost its time may be, pass the known era to
+    /// `to_instant_in_era` instead.
+    pub fn to_instant_near(&self, pivot: Instant) -> Instant {
+        self.instant_in_any_era(self.era_near(pivot))
+    }
+
+    // As `to_instant_near`, as signed 32.32 fixed-point seconds since the prime epoch.
+    pub(crate) fn fixed_near(&self, pivot: Instant) -> i128 {
+        self.fixed_in_era(self.era_near(pivot))
+    }
+
+    // The era placing the timestamp nearest `pivot`.
+    fn era_near(&self, pivot: Instant) -> i128 {
+        // Computed in 128 bits so that no pivot, however far from the present, overflows.
+        let pivot_secs = pivot.secs() as i128 + EPOCH_DELTA as i128;
+        (pivot_secs - self.seconds as i128 + (1 << 31)).div_euclid(1 << 32)
+    }
+}
+
+/// Interprets the timestamp in the era placing it between 20 January 1968 and 26 February 2104,
+/// as recommended by RFC 4330: timestamps with the most significant bit of the seconds set fall
+/// in era 0, and the remainder in era 1 following 7 February 2036. The fraction is rounded to the
+/// nearest nanosecond.
+///
+/// Requires the `chrono` feature.
+#[cfg(feature = "chrono")]
+impl From<protocol::TimestampFormat> for chrono::DateTime<chrono::Utc> {
+    fn from(t: protocol::TimestampFormat) -> Self {
+        let era = if t.seconds & 0x8000_0000 == 0 { 1 } else { 0 };
+        let secs = (era << 32) + t.seconds as i64 - EPOCH_DELTA;
+        let nanos = (t.fraction as i64 * 1_000_000_000 + (1 << 31)) >> 32;
+        chrono::DateTime::from_timestamp_nanos(secs * 1_000_000_000 + nanos)
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl protocol::TimestampFormat {
+    /// Convert a `chrono::DateTime<Utc>` into an NTP timestamp, rounding to the nearest 2^-32 s.
+    ///
+    /// As with `from_instant_checked`, returns `Error::BeforePrimeEpoch` for dates preceding 1
+    /// January 1900 and wraps later dates into their NTP era. Converting the timestamp back into a
+    /// `DateTime` recovers the date if it lies between 1968 and 2104. A leap second is treated as
+    /// the last nanosecond of the preceding second.
+    ///
+    /// Requires the `chrono` feature.
+    pub fn from_date_time_checked(t: chrono::DateTime<chrono::Utc>) -> Result<Self, Error> {
+        let nanos = t.timestamp_subsec_nanos().min(999_999_999);
+        let instant = Instant::normalized(t.timestamp(), nanos as i64);
+        protocol::TimestampFormat::from_instant_checked(instant)
+    }
+}
+
+// The seconds since the prime epoch and the 32-bit binary fraction of the given instant. The
+// seconds saturate at the extremes of `i64` rather than overflow.
+fn ntp_seconds_and_fraction(t: Instant) -> (i64, u32) {
+    let (secs, nanos) = positive_subsec_nanos(t);
+    (secs.saturating_add(EPOCH_DELTA), fraction(nanos, 32) as u32)
+}
+
+// The seconds and nanoseconds of an instant, borrowing a second for pre-epoch instants so that
+// the nanoseconds are always positive.
+fn positive_subsec_nanos(t: Instant) -> (i64, i64) {
+    if t.subsec_nanos() < 0 {
+        (t.secs().saturating_sub(1), 1_000_000_000 + t.subsec_nanos() as i64)
+    } else {
+        (t.secs(), t.subsec_nanos() as i64)
     }
 }
 
+// The nanoseconds of a second as the nearest fraction of `bits` bits. The last nanoseconds of a
+// second would round up to a whole second, which does not fit, so the fraction saturates instead.
+fn fraction(nanos: i64, bits: u32) -> i64 {
+    let fraction = ((nanos << bits) + 500_000_000) / 1_000_000_000;
+    fraction.min((1 << bits) - 1)
+}
+
+/// Query an ntp server and summarize the measurement in a single line, e.g.
+/// `pool.ntp.org:123: stratum 2, offset -3.2ms, delay 24.1ms`.
+///
+/// This is a convenience for command line and scripting use. Use `NtpClient` to configure the
+/// request or to work with the measurement itself.
+pub async fn quick_report<A>(addr: A) -> Result<String, Error>
+where
+    A: ServerAddr + std::fmt::Display,
+{
+    let server = addr.to_string();
+    let measurement = NtpClient::new().timeout(time::Duration::from_secs(5)).request(addr).await?;
+    Ok(format!(
+        "{}: stratum {}, offset {:.1}ms, delay {:.1}ms",
+        server,
+        measurement.packet.stratum.0,
+        measurement.offset().as_secs_f64() * 1e3,
+        measurement.delay().as_secs_f64() * 1e3,
+    ))
+}
+
+/// Everything learned from a single exchange with a server, with all fields public, as returned
+/// by `report`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TimeReport {
+    /// The address the server's name resolved to.
+    pub server_addr: std::net::SocketAddr,
+    /// The stratum of the server.
+    pub stratum: protocol::Stratum,
+    /// The number of servers between this one and its reference clock, as given by
+    /// `Packet::hops`.
+    pub hops: u8,
+    /// The leap second warning of the server.
+    pub leap: protocol::LeapIndicator,
+    /// The server's reference identifier.
+    pub reference: protocol::ReferenceIdentifier,
+    /// The offset of the server's clock, positive when the local clock is behind.
+    pub offset: SignedDuration,
+    /// The round-trip network delay of the exchange.
+    pub delay: SignedDuration,
+    /// The root distance in seconds, as given by `NtpMeasurement::quality_score`: the estimated
+    /// distance in time to the reference clock, where `hops` is that in servers.
+    pub root_distance: f64,
+    /// The time according to the server when the response was received, i.e. `local_time`
+    /// corrected by the offset.
+    pub ntp_time: Instant,
+    /// The time according to the local clock when the response was received.
+    pub local_time: Instant,
+}
+
+/// Query an ntp server and collect what was learned into a **TimeReport**.
+///
+/// As with `quick_report`, this is a convenience for command line and scripting use, with a
+/// timeout of five seconds.
+pub async fn report<A: ToSocketAddrs>(addr: A) -> Result<TimeReport, Error> {
+    let client = NtpClient::new().timeout(time::Duration::from_secs(5));
+    let server_addr = client::resolve_system(addr).await?;
+    let measurement = client.request(server_addr).await?;
+    Ok(TimeReport {
+        server_addr,
+        stratum: measurement.packet.stratum,
+        hops: measurement.packet.hops(),
+        leap: measurement.packet.leap_indicator,
+        reference: measurement.packet.reference_id,
+        offset: measurement.offset(),
+        delay: measurement.delay(),
+        root_distance: measurement.quality_score(),
+        ntp_time: measurement.correct(measurement.received),
+        local_time: measurement.received,
+    })
+}
+
+/// The current unix time in seconds according to `pool.ntp.org`.
+///
+/// The local clock is corrected by the offset measured against the pool, so that the result
+/// accounts for the network delay of the exchange. Should the pool fail to resolve, the
+/// `DEFAULT_FALLBACK_ADDRS` are queried instead.
+///
+/// The pool's timestamps are placed in the NTP era nearest the local clock, so the result is
+/// correct beyond the 2036 rollover provided that the local clock is within 68 years of the
+/// true time. See `get_unix_ntp_time_detailed` otherwise.
 pub async fn get_unix_ntp_time() -> Result<i64> {
-    let pool_ntp = "pool.ntp.org:123";
-    let response = request(pool_ntp).await?;
-    let timestamp = response.transmit_timestamp;
-    Ok(Instant::from(timestamp).secs())
+    Ok(get_unix_ntp_time_detailed(None).await?.secs())
+}
+
+/// The current time according to `pool.ntp.org`, as `get_unix_ntp_time` but to the nanosecond.
+///
+/// An `era` of `Some` overrides the era the pool's timestamps are placed in, for devices whose
+/// local clock may be decades off, as described by `NtpClient::current_time`.
+pub async fn get_unix_ntp_time_detailed(era: Option<i32>) -> Result<Instant> {
+    let pool_ntp = format!("pool.ntp.org:{}", protocol::NTP_PORT);
+    let client = NtpClient::new().fallback_addrs(DEFAULT_FALLBACK_ADDRS);
+    Ok(client.current_time(pool_ntp, era).await?)
+}
+
+/// The transmit time of a response from `pool.ntp.org` in unix seconds, as returned by
+/// `get_unix_ntp_time` prior to it applying the measured offset.
+#[deprecated(note = "use `server_transmit_time` or `get_unix_ntp_time` instead")]
+pub async fn get_unix_ntp_transmit_time() -> Result<i64> {
+    let pool_ntp = format!("pool.ntp.org:{}", protocol::NTP_PORT);
+    Ok(server_transmit_time(pool_ntp).await?.secs())
 }
@@ -0,0 +1,269 @@
+//! The result of a single request/response exchange with an ntp server.
+
+use crate::protocol::{Packet, TimestampFormat};
+use crate::{Error, Instant, SignedDuration, EPOCH_DELTA};
+use std::time::Duration;
+
+/// Offsets beyond which the clock is considered too far off to be corrected automatically,
+/// matching the panic threshold of ntpd.
+pub const PANIC_THRESHOLD: Duration = Duration::from_secs(1000);
+
+/// Offsets beyond which the clock is better stepped than slewed, matching the step threshold of
+/// ntpd.
+pub const STEP_THRESHOLD: Duration = Duration::from_millis(128);
+
+/// How a measured offset should be applied to the local clock.
+///
+/// Each variant carries the offset, positive when the local clock is behind the server.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum DisciplineAction {
+    /// The offset is small enough to be corrected by gradually adjusting the clock's rate.
+    Slew(SignedDuration),
+    /// The offset is too large to slew in reasonable time and the clock should be set directly.
+    Step(SignedDuration),
+    /// The offset exceeds `PANIC_THRESHOLD`. The clock, or the server, is likely badly wrong and
+    /// an operator should decide what to do rather than correct it automatically.
+    Panic(SignedDuration),
+}
+
+/// A response from an ntp server along with the local times at which the request was sent and the
+/// response was received.
+///
+/// Two local clocks are involved. The wall clock timestamps `sent` and `received` place the
+/// exchange in absolute time and are used for the offset, while the round trip is also measured
+/// on the monotonic clock so that the delay is unaffected should the wall clock be stepped (e.g.
+/// by the operating system's own time synchronization) while the request is in flight.
+#[derive(Copy, Clone, Debug)]
+pub struct NtpMeasurement {
+    /// The response received from the server.
+    pub packet: Packet,
+    /// Local time at which the request departed for the server (T1).
+    pub sent: Instant,
+    /// Local time at which the response arrived from the server (T4).
+    pub received: Instant,
+    /// The time elapsed between sending the request and receiving the response, as measured by
+    /// the local monotonic clock.
+    pub round_trip: Duration,
+    /// Whether or not the server responded in interleaved mode.
+    ///
+    /// The timestamps of an interleaved measurement describe the exchange preceding the one in
+    /// which it was received, completed by the server's more accurate transmit timestamp.
+    pub interleaved: bool,
+    /// The fraction by which the delay of the forward path, from the client to the server,
+    /// exceeds that of the return path, e.g. `0.1` if it is 10% longer. Zero for a symmetric
+    /// path, which is all that can be assumed without knowledge of the network.
+    pub delay_asymmetry: f64,
+}
+
+impl NtpMeasurement {
+    /// Create a measurement from the server's response and the local wall clock times at which
+    /// the request was sent (T1) and the response received (T4), independently of any network IO.
+    ///
+    /// The round trip is taken to be `t4_local - t1_local`. Returns `Error::InvalidPacket` if the
+    /// response was received before the request was sent, or if the server claims to have
+    /// transmitted its response (T3) before receiving the request (T2).
+    pub fn from_exchange(
+        t1_local: Instant,
+        packet: &Packet,
+        t4_local: Instant,
+    ) -> Result<NtpMeasurement, Error> {
+        let round_trip = t4_local - t1_local;
+        if round_trip.is_negative() {
+            let err_msg = "response was received before the request was sent".to_string();
+            return Err(Error::InvalidPacket(err_msg));
+        }
+        // Compare the server's timestamps as instants, so that an exchange spanning the end of
+        // an NTP era is not rejected.
+        let receive = packet.receive_timestamp.to_instant_near(t1_local);
+        let transmit = packet.transmit_timestamp.to_instant_near(t1_local);
+        if transmit < receive {
+            let err_msg = "server transmit timestamp precedes its receive timestamp".to_string();
+            return Err(Error::InvalidPacket(err_msg));
+        }
+        Ok(NtpMeasurement {
+            packet: *packet,
+            sent: t1_local,
+            received: t4_local,
+            round_trip: round_trip.unsigned_abs(),
+            interleaved: false,
+            delay_asymmetry: 0.0,
+        })
+    }
+
+    /// The offset of the server's clock relative to the local clock.
+    ///
+    /// The offset is positive when the local clock is behind the server's clock. It is computed
+    /// from the four timestamps of the exchange as `((T2 - T1) + (T3 - T4)) / 2`, which assumes
+    /// that the network delay is the same in both directions.
+    ///
+    /// The server's timestamps are taken in the NTP era nearest the local time the request was
+    /// sent, as by `TimestampFormat::to_instant_near`, so that the offset remains correct beyond
+    /// the 2036 rollover. The offset is computed as by `ntp_offset_delay`, exactly on the 32.32
+    /// fixed-point timestamps, rounding only the result to the nearest nanosecond.
+    ///
+    /// A known `delay_asymmetry` `a` is corrected for by subtracting half the difference between
+    /// the forward and return delays, `a * delay / (2 * (2 + a))`, by which the symmetric offset
+    /// is biased.
+    pub fn offset(&self) -> SignedDuration {
+        let (t2, t3) = self.server_units();
+        let t4 = instant_units(self.received);
+        let (offset, _) = offset_delay_units(instant_units(self.sent), t2, t3, t4);
+        if self.delay_asymmetry == 0.0 {
+            return offset;
+        }
+        let a = self.delay_asymmetry;
+        let bias = a * self.delay().as_nanos() as f64 / (2.0 * (2.0 + a));
+        offset - SignedDuration::from_nanos(bias.round() as i128)
+    }
+
+    /// The offset of the server's clock as a `chrono::Duration`, which unlike
+    /// `std::time::Duration` may be negative. Offsets beyond the roughly 292 years representable
+    /// in nanoseconds saturate.
+    ///
+    /// Requires the `chrono` feature.
+    #[cfg(feature = "chrono")]
+    pub fn offset_chrono(&self) -> chrono::Duration {
+        let nanos = self.offset().as_nanos().clamp(i64::MIN as i128, i64::MAX as i128);
+        chrono::Duration::nanoseconds(nanos as i64)
+    }
+
+    /// The round-trip network delay of the exchange, excluding the time the server spent
+    /// processing the request, computed as `(T4 - T1) - (T3 - T2)`.
+    ///
+    /// `T4 - T1` is taken from the monotonic `round_trip` rather than the wall clock timestamps.
+    /// The delay is computed as by `ntp_offset_delay`, exactly on the server's timestamps, and so
+    /// is never negative.
+    pub fn delay(&self) -> SignedDuration {
+        let (t2, t3) = self.server_units();
+        let t4 = self.sent + SignedDuration::from(self.round_trip);
+        let (_, delay) = offset_delay_units(instant_units(self.sent), t2, t3, instant_units(t4));
+        SignedDuration::from(delay)
+    }
+
+    /// An estimate of the maximum error of the server's time as seen by the client, in seconds,
+    /// by which measurements from different servers may be ranked. Lower is better.
+    ///
+    /// The score is the root distance of RFC 5905: half the total round-trip delay to the
+    /// reference clock, plus its total dispersion, plus the precision of the server's clock.
+    pub fn quality_score(&self) -> f64 {
+        let packet = &self.packet;
+        let root_delay = packet.root_delay.as_secs_f64() + self.delay().as_secs_f64();
+        root_delay / 2.0 + packet.root_dispersion.as_secs_f64() + packet.precision_secs()
+    }
+
+    /// Advise how the local clock should be disciplined given the measured offset.
+    ///
+    /// Offsets of at most `step_threshold` (`STEP_THRESHOLD` in ntpd) are slewed, larger
+    /// offsets stepped and offsets beyond `PANIC_THRESHOLD` reported as a panic. The crate never
+    /// adjusts the clock itself.
+    pub fn discipline_action(&self, step_threshold: Duration) -> DisciplineAction {
+        let offset = self.offset();
+        let magnitude = offset.unsigned_abs();
+        if magnitude > PANIC_THRESHOLD {
+            DisciplineAction::Panic(offset)
+        } else if magnitude > step_threshold {
+            DisciplineAction::Step(offset)
+        } else {
+            DisciplineAction::Slew(offset)
+        }
+    }
+
+    /// Translate a timestamp taken from the local clock to the server's time by applying the
+    /// measured offset.
+    ///
+    /// This assumes that the offset is approximately constant over the window between `local` and
+    /// the measurement, i.e. that the local clock has not drifted or been adjusted since.
+    pub fn correct(&self, local: Instant) -> Instant {
+        local + self.offset()
+    }
+
+    /// The time at which the server transmitted its response (T3), as it reported it and so
+    /// uncorrected for the delay of the response on its way back.
+    ///
+    /// The timestamp is taken in the NTP era nearest the local time the request was sent, as for
+    /// `offset`.
+    pub fn server_transmit(&self) -> Instant {
+        self.server_instants().1
+    }
+
+    /// The correction applied to `server_transmit` to give the server's time at which the
+    /// response was received, `correct(received)`, so that
+    /// `server_transmit() + correction() == correct(received)`.
+    ///
+    /// For a symmetric path this is half the delay measured on the wall clock, the time the
+    /// response is taken to have spent on its way back, which lets the adjustment made to the
+    /// time the server reported be logged and audited separately from it.
+    pub fn correction(&self) -> SignedDuration {
+        self.correct(self.received) - self.server_transmit()
+    }
+
+    // The server's receive and transmit timestamps as instants in the era nearest the local
+    // time the request was sent, rounded to the nearest nanosecond.
+    fn server_instants(&self) -> (Instant, Instant) {
+        let receive = self.packet.receive_timestamp.to_instant_near(self.sent);
+        let transmit = self.packet.transmit_timestamp.to_instant_near(self.sent);
+        (receive, transmit)
+    }
+
+    // The server's receive and transmit timestamps as `server_instants` places them, exactly in
+    // the units of `offset_delay_units`.
+    fn server_units(&self) -> (i128, i128) {
+        let receive = timestamp_units(self.packet.receive_timestamp, self.sent);
+        let transmit = timestamp_units(self.packet.transmit_timestamp, self.sent);
+        (receive, transmit)
+    }
+
+    /// Translate a batch of local timestamps to the server's time, as `correct` does for one.
+    pub fn correct_all(&self, locals: &[Instant]) -> Vec<Instant> {
+        let offset = self.offset();
+        locals.iter().map(|&local| local + offset).collect()
+    }
+}
+
+/// Compute the clock offset and round-trip delay of an exchange from its four timestamps.
+///
+/// - `t1` is the local time at which the client sent the request (the origin timestamp),
+/// - `t2` the server's time at which it received the request (the receive timestamp),
+/// - `t3` the server's time at which it sent the response (the transmit timestamp), and
+/// - `t4` the local time at which the client received the response.
+///
+/// Returns the offset `((t2 - t1) + (t3 - t4)) / 2`, which is positive when the local clock is
+/// behind the server's clock and negative when it is ahead, and the delay
+/// `(t4 - t1) - (t3 - t2)`, the time spent on the network excluding the server's processing.
+///
+/// The arithmetic is exact in nanoseconds, with a half nanosecond offset rounded up. A delay
+/// that would be negative, as when the server's clock runs fast over the exchange, is zero.
+/// `NtpMeasurement::offset` and `NtpMeasurement::delay` compute theirs in the same way, from
+/// the server's timestamps as they were received rather than rounded to nanoseconds.
+pub fn ntp_offset_delay(
+    t1: Instant,
+    t2: Instant,
+    t3: Instant,
+    t4: Instant,
+) -> (SignedDuration, Duration) {
+    let (t1, t2) = (instant_units(t1), instant_units(t2));
+    offset_delay_units(t1, t2, instant_units(t3), instant_units(t4))
+}
+
+// Offset and delay arithmetic is carried out on times in units of 2^-32 ns since the unix epoch,
+// in which both the nanosecond instants of the local clock and the 32.32 fixed-point timestamps
+// of the server are exact, rounding only the results to the nearest nanosecond. Rounding the
+// server's timestamps to nanoseconds first would add up to a nanosecond of error.
+
+fn instant_units(t: Instant) -> i128 {
+    t.as_nanos() << 32
+}
+
+// The timestamp in the era placing it nearest `pivot`, as `TimestampFormat::to_instant_near`.
+fn timestamp_units(t: TimestampFormat, pivot: Instant) -> i128 {
+    (t.fixed_near(pivot) - ((EPOCH_DELTA as i128) << 32)) * 1_000_000_000
+}
+
+// Saturating, so that instants at the extremes of their range cannot overflow.
+fn offset_delay_units(t1: i128, t2: i128, t3: i128, t4: i128) -> (SignedDuration, Duration) {
+    let offset = t2.saturating_sub(t1).saturating_add(t3.saturating_sub(t4));
+    let offset = offset.saturating_add(1 << 32) >> 33;
+    let delay = t4.saturating_sub(t1).saturating_sub(t3.saturating_sub(t2));
+    let delay = delay.saturating_add(1 << 31) >> 32;
+    (SignedDuration::from_nanos(offset), Duration::from_nanos(delay.max(0) as u64))
+}
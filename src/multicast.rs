@@ -0,0 +1,87 @@
+//! Receiving time from servers that multicast NTP packets over IPv6.
+//!
+//! Rather than answering requests, a server in broadcast mode (mode 5) periodically sends its
+//! packets to a multicast group, from which any number of clients on the network receive them.
+//! IANA assigns the `ff0x::101` groups to NTP, of which `ff02::101` reaches the local link.
+//!
+//! A broadcast packet carries no origin timestamp, so the network delay, and therefore the offset,
+//! can only be estimated by other means, e.g. from an occasional ordinary client exchange with the
+//! same server.
+
+use crate::protocol::{self, Mode, Packet, ReadBytes};
+
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr};
+
+#[cfg(feature = "async-std")]
+use async_std::net::UdpSocket;
+#[cfg(feature = "tokio")]
+use tokio::net::UdpSocket;
+
+/// The multicast group of all NTP servers on the local link, `ff02::101`.
+pub const LINK_LOCAL_GROUP: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x101);
+
+/// The multicast group of all NTP servers within the site, `ff05::101`.
+pub const SITE_LOCAL_GROUP: Ipv6Addr = Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 0, 0x101);
+
+/// A socket that has joined an NTP multicast group, yielding the broadcast packets sent to it.
+///
+/// Dropping the listener leaves the group and closes the socket, releasing its port, so a
+/// long-running task listening for packets is shut down by dropping its listener. Dropping the
+/// future returned by `recv` cancels the pending receive without affecting the listener.
+#[derive(Debug)]
+pub struct MulticastListener {
+    sock: UdpSocket,
+    group: Ipv6Addr,
+    interface: u32,
+}
+
+impl MulticastListener {
+    /// Join `group` on the network interface with index `interface` and listen on `port`.
+    ///
+    /// An `interface` of `0` lets the operating system choose the interface. A `port` of `0`
+    /// binds an ephemeral port, which is only useful for testing as multicast servers send to the
+    /// NTP port.
+    pub async fn bind(port: u16, group: Ipv6Addr, interface: u32) -> io::Result<Self> {
+        let sock = UdpSocket::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port))).await?;
+        sock.join_multicast_v6(&group, interface)?;
+        Ok(MulticastListener { sock, group, interface })
+    }
+
+    /// The local address the listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.sock.local_addr()
+    }
+
+    /// Wait for the next broadcast packet, returning it along with the address of the server
+    /// that sent it.
+    ///
+    /// Datagrams that are not valid NTP packets in broadcast mode are discarded.
+    pub async fn recv(&self) -> io::Result<(Packet, SocketAddr)> {
+        let mut buf = [0u8; crate::DEFAULT_RECV_BUFFER_SIZE_BYTES];
+        loop {
+            let (len, src) = self.sock.recv_from(&mut buf).await?;
+            match (&buf[..len]).read_bytes::<Packet>() {
+                Ok(packet) if packet.mode == Mode::Broadcast => return Ok((packet, src)),
+                Ok(packet) => debug!("discarding {:?} mode packet from {}", packet.mode, src),
+                Err(err) => debug!("discarding invalid packet from {}: {}", src, err),
+            }
+        }
+    }
+}
+
+impl Drop for MulticastListener {
+    fn drop(&mut self) {
+        if let Err(err) = self.sock.leave_multicast_v6(&self.group, self.interface) {
+            debug!("failed to leave multicast group {}: {}", self.group, err);
+        }
+    }
+}
+
+/// Join an NTP multicast group, e.g. `LINK_LOCAL_GROUP`, on the network interface with index
+/// `interface` and listen for broadcast packets on the NTP port.
+///
+/// Binding the NTP port usually requires elevated privileges.
+pub async fn listen_multicast(group: Ipv6Addr, interface: u32) -> io::Result<MulticastListener> {
+    MulticastListener::bind(protocol::NTP_PORT, group, interface).await
+}
@@ -0,0 +1,118 @@
+//! Client-side support for Network Time Security (NTS).
+//!
+//! NTS is described by IETF RFC 8915. A client first performs an NTS Key Establishment (NTS-KE)
+//! exchange over TLS, which yields a pair of AEAD keys and a small pool of opaque cookies. Each
+//! subsequent NTP request carries exactly one of those cookies and each authenticated response
+//! carries a fresh one in return, so that the cookies are never reused across requests.
+//!
+//! `KeyExchange` performs the key establishment, with the TLS backend selected by the default
+//! `nts-rustls` feature, and `CookieJar` keeps the cookie pool populated between key exchanges.
+
+use std::collections::VecDeque;
+
+#[cfg(feature = "nts-rustls")]
+mod ke;
+#[cfg(feature = "nts-rustls")]
+mod rustls_backend;
+
+#[cfg(feature = "nts-rustls")]
+pub use self::ke::{
+    KeyEstablishment, KeyExchange, AEAD_AES_SIV_CMAC_256, DEFAULT_KEY_EXCHANGE_TIMEOUT,
+    NTS_KE_ALPN, NTS_KE_PORT, PROTOCOL_NTPV4,
+};
+
+/// The number of cookies an NTS-KE server is expected to supply to a client.
+///
+/// RFC 8915 recommends that servers provide eight cookies so that a client can survive several
+/// lost responses without running dry.
+pub const DEFAULT_COOKIE_COUNT: usize = 8;
+
+/// The default number of cookies at or below which a new key exchange should be performed.
+pub const DEFAULT_LOW_WATER: usize = 2;
+
+/// A pool of NTS cookies obtained from key establishment.
+///
+/// Cookies are used in the order they were received. A cookie is removed from the jar with `pop`
+/// before each request and the replacement cookie carried by the response is added back into the
+/// jar with `push`. When responses are lost the jar slowly drains; once it falls to the low-water
+/// mark `needs_key_exchange` returns true and the caller should perform a new NTS-KE exchange and
+/// `refill` the jar with the cookies it produces.
+///
+/// ## Example
+///
+/// ```
+/// extern crate nippy;
+///
+/// use nippy::nts::CookieJar;
+///
+/// fn main() {
+///     let mut jar = CookieJar::new(vec![vec![1; 16], vec![2; 16], vec![3; 16]]);
+///     let cookie = jar.pop().unwrap();
+///     assert_eq!(cookie, vec![1; 16]);
+///     jar.push(vec![4; 16]);
+///     assert_eq!(jar.len(), 3);
+/// }
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CookieJar {
+    cookies: VecDeque<Vec<u8>>,
+    low_water: usize,
+}
+
+impl CookieJar {
+    /// Create a new **CookieJar** holding the given cookies from an NTS-KE exchange.
+    ///
+    /// The low-water mark defaults to `DEFAULT_LOW_WATER`.
+    pub fn new(cookies: Vec<Vec<u8>>) -> Self {
+        CookieJar::with_low_water(cookies, DEFAULT_LOW_WATER)
+    }
+
+    /// Create a new **CookieJar** that requests a new key exchange once it holds `low_water` or
+    /// fewer cookies.
+    pub fn with_low_water(cookies: Vec<Vec<u8>>, low_water: usize) -> Self {
+        CookieJar {
+            cookies: cookies.into(),
+            low_water,
+        }
+    }
+
+    /// The number of cookies currently held by the jar.
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    /// Whether or not the jar has run out of cookies entirely.
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+
+    /// The number of cookies at or below which a new key exchange is required.
+    pub fn low_water(&self) -> usize {
+        self.low_water
+    }
+
+    /// Remove the oldest cookie from the jar for use in the next request.
+    ///
+    /// Returns `None` if the jar is empty, in which case a new key exchange must be performed
+    /// before another request can be sent.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        self.cookies.pop_front()
+    }
+
+    /// Add the fresh cookie carried by a response to the jar.
+    pub fn push(&mut self, cookie: Vec<u8>) {
+        self.cookies.push_back(cookie);
+    }
+
+    /// Whether or not the jar has run low enough that a new key exchange should be performed.
+    pub fn needs_key_exchange(&self) -> bool {
+        self.cookies.len() <= self.low_water
+    }
+
+    /// Replace the contents of the jar with the cookies from a new key exchange.
+    ///
+    /// Any remaining cookies are discarded as they are bound to the keys of the previous exchange.
+    pub fn refill(&mut self, cookies: Vec<Vec<u8>>) {
+        self.cookies = cookies.into();
+    }
+}
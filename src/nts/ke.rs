@@ -0,0 +1,350 @@
+//! NTS Key Establishment (NTS-KE), as described in section 4 of RFC 8915.
+//!
+//! The client and server exchange a short sequence of records over TLS 1.3, negotiating the
+//! `ntske/1` application protocol, after which both derive the AEAD keys from the TLS session with
+//! the keying material exporter of RFC 5705. The TLS session is provided through `TlsSession` by
+//! the backend the `nts-rustls` feature selects, so that the exchange is independent of it.
+
+use std::io;
+use std::time::Duration;
+
+#[cfg(feature = "async-std")]
+use async_std::io::{ReadExt, WriteExt};
+#[cfg(feature = "async-std")]
+use async_std::net::TcpStream;
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+#[cfg(feature = "tokio")]
+use tokio::net::TcpStream;
+
+/// The TCP port NTS-KE servers listen on.
+pub const NTS_KE_PORT: u16 = 4460;
+
+/// The ALPN protocol ID every NTS-KE session negotiates.
+pub const NTS_KE_ALPN: &[u8] = b"ntske/1";
+
+/// The NTS next protocol ID of NTPv4, the only protocol requested by `KeyExchange`.
+pub const PROTOCOL_NTPV4: u16 = 0;
+
+/// The ID of the AEAD_AES_SIV_CMAC_256 algorithm, the only algorithm requested by `KeyExchange`.
+pub const AEAD_AES_SIV_CMAC_256: u16 = 15;
+
+/// How long `KeyExchange::establish` waits for the whole exchange by default.
+pub const DEFAULT_KEY_EXCHANGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The exporter label from which both keys are derived.
+const KEY_EXPORTER_LABEL: &[u8] = b"EXPORTER-network-time-security";
+
+// The key length of AEAD_AES_SIV_CMAC_256.
+const KEY_SIZE_BYTES: usize = 32;
+
+// The record types of section 4.1 of RFC 8915, and the bit marking a record as critical.
+const CRITICAL: u16 = 0x8000;
+const RECORD_END_OF_MESSAGE: u16 = 0;
+const RECORD_NEXT_PROTOCOL: u16 = 1;
+const RECORD_ERROR: u16 = 2;
+const RECORD_WARNING: u16 = 3;
+const RECORD_AEAD_ALGORITHM: u16 = 4;
+const RECORD_NEW_COOKIE: u16 = 5;
+const RECORD_SERVER: u16 = 6;
+const RECORD_PORT: u16 = 7;
+
+// Far larger than a response carrying eight cookies, to bound a server that never ends its own.
+const MAX_RESPONSE_SIZE_BYTES: usize = 65536;
+
+const RECV_BUFFER_SIZE_BYTES: usize = 4096;
+
+// The session of the TLS backend selected by a feature.
+#[cfg(feature = "nts-rustls")]
+type Backend = super::rustls_backend::RustlsSession;
+
+// A TLS 1.3 client session offering only the `ntske/1` protocol, driven over a TCP stream by the
+// exchange. Sessions do no I/O of their own: records received from the server are handed to
+// `read_tls` and those waiting to be sent are taken from `write_tls`.
+pub(crate) trait TlsSession: Sized {
+    // Begin a handshake with `host`, whose certificate is verified against the web PKI roots and
+    // the additional DER encoded `roots`.
+    fn connect(host: &str, roots: &[Vec<u8>]) -> io::Result<Self>;
+
+    // Whether or not the handshake is still in progress.
+    fn is_handshaking(&self) -> bool;
+
+    // Append the TLS records waiting to be sent to the server to `out`.
+    fn write_tls(&mut self, out: &mut Vec<u8>) -> io::Result<()>;
+
+    // Process the TLS records received from the server in `data`.
+    fn read_tls(&mut self, data: &[u8]) -> io::Result<()>;
+
+    // Queue application data to be sent to the server.
+    fn write_plaintext(&mut self, data: &[u8]) -> io::Result<()>;
+
+    // Append the application data received from the server so far to `out`.
+    fn read_plaintext(&mut self, out: &mut Vec<u8>) -> io::Result<()>;
+
+    // The application protocol agreed during the handshake, if any.
+    fn alpn_protocol(&self) -> Option<&[u8]>;
+
+    // Export `len` bytes of keying material for `label` and `context`, as described in RFC 5705.
+    fn export_keying_material(
+        &self,
+        label: &[u8],
+        context: &[u8],
+        len: usize,
+    ) -> io::Result<Vec<u8>>;
+}
+
+/// The keys and cookies produced by an NTS-KE exchange.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeyEstablishment {
+    /// The key protecting requests sent from the client to the server.
+    pub c2s_key: Vec<u8>,
+    /// The key protecting responses sent from the server to the client.
+    pub s2c_key: Vec<u8>,
+    /// The cookies supplied by the server, in the order received, e.g. to fill a `CookieJar`.
+    pub cookies: Vec<Vec<u8>>,
+    /// The NTP server to query, if it is not the NTS-KE server itself.
+    pub server: Option<String>,
+    /// The port of the NTP server, if it is not `NTP_PORT`.
+    pub port: Option<u16>,
+}
+
+/// Performs NTS-KE exchanges, requesting NTPv4 with AEAD_AES_SIV_CMAC_256.
+///
+/// The server certificate is verified against the web PKI roots, along with any certificates
+/// given to `add_root_certificate`.
+///
+/// ## Example
+///
+/// ```
+/// extern crate nippy;
+///
+/// use nippy::nts::KeyExchange;
+/// use std::time::Duration;
+///
+/// fn main() {
+///     let key_exchange = KeyExchange::new().timeout(Duration::from_secs(5));
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct KeyExchange {
+    roots: Vec<Vec<u8>>,
+    timeout: Duration,
+}
+
+impl Default for KeyExchange {
+    fn default() -> Self {
+        KeyExchange::new()
+    }
+}
+
+impl KeyExchange {
+    /// Create a new **KeyExchange**, which waits up to `DEFAULT_KEY_EXCHANGE_TIMEOUT`.
+    pub fn new() -> Self {
+        KeyExchange {
+            roots: Vec::new(),
+            timeout: DEFAULT_KEY_EXCHANGE_TIMEOUT,
+        }
+    }
+
+    /// Also trust the DER encoded certificate `der`, e.g. that of a private certificate authority
+    /// or a self-signed server.
+    pub fn add_root_certificate(mut self, der: Vec<u8>) -> Self {
+        self.roots.push(der);
+        self
+    }
+
+    /// Bound the wait for the whole exchange, failing with `TimedOut` once it passes.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Perform an exchange with the NTS-KE server `host`, usually listening on `NTS_KE_PORT`.
+    ///
+    /// `host` is both resolved and checked against the server certificate. Fails with
+    /// `InvalidData` if the server negotiates a protocol other than `ntske/1`, declines NTPv4 or
+    /// AEAD_AES_SIV_CMAC_256, or supplies no cookies, and with `Other` if it reports an error.
+    pub async fn establish(&self, host: &str, port: u16) -> io::Result<KeyEstablishment> {
+        let exchange = exchange::<Backend>(host, port, &self.roots);
+        match crate::client::with_timeout(self.timeout, exchange).await {
+            Some(result) => result,
+            None => {
+                let err_msg = "timed out waiting for the nts-ke exchange";
+                Err(io::Error::new(io::ErrorKind::TimedOut, err_msg))
+            }
+        }
+    }
+}
+
+// The parts of a response that are passed on to the caller.
+struct Response {
+    cookies: Vec<Vec<u8>>,
+    server: Option<String>,
+    port: Option<u16>,
+}
+
+async fn exchange<S: TlsSession>(
+    host: &str,
+    port: u16,
+    roots: &[Vec<u8>],
+) -> io::Result<KeyEstablishment> {
+    let mut session = S::connect(host, roots)?;
+    let mut stream = TcpStream::connect((host, port)).await?;
+    stream.set_nodelay(true)?;
+    while session.is_handshaking() {
+        send(&mut session, &mut stream).await?;
+        if session.is_handshaking() {
+            recv(&mut session, &mut stream).await?;
+        }
+    }
+    if session.alpn_protocol() != Some(NTS_KE_ALPN) {
+        return Err(invalid_data("nts-ke server did not negotiate ntske/1"));
+    }
+
+    session.write_plaintext(&request())?;
+    send(&mut session, &mut stream).await?;
+    let mut data = Vec::new();
+    let response = loop {
+        session.read_plaintext(&mut data)?;
+        if let Some(response) = parse_response(&data)? {
+            break response;
+        }
+        if data.len() > MAX_RESPONSE_SIZE_BYTES {
+            return Err(invalid_data("nts-ke response is too large"));
+        }
+        recv(&mut session, &mut stream).await?;
+    };
+    debug!("nts-ke server supplied {} cookies", response.cookies.len());
+
+    let key = |direction| {
+        session.export_keying_material(KEY_EXPORTER_LABEL, &key_context(direction), KEY_SIZE_BYTES)
+    };
+    Ok(KeyEstablishment {
+        c2s_key: key(0)?,
+        s2c_key: key(1)?,
+        cookies: response.cookies,
+        server: response.server,
+        port: response.port,
+    })
+}
+
+// Send the TLS records waiting in the session to the server.
+async fn send<S: TlsSession>(session: &mut S, stream: &mut TcpStream) -> io::Result<()> {
+    let mut out = Vec::new();
+    session.write_tls(&mut out)?;
+    if !out.is_empty() {
+        stream.write_all(&out).await?;
+    }
+    Ok(())
+}
+
+// Receive TLS records from the server into the session.
+async fn recv<S: TlsSession>(session: &mut S, stream: &mut TcpStream) -> io::Result<()> {
+    let mut buf = [0u8; RECV_BUFFER_SIZE_BYTES];
+    let len = stream.read(&mut buf).await?;
+    if len == 0 {
+        let err_msg = "nts-ke server closed the connection before the exchange completed";
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, err_msg));
+    }
+    session.read_tls(&buf[..len])
+}
+
+// The request for NTPv4 protected by AEAD_AES_SIV_CMAC_256.
+fn request() -> Vec<u8> {
+    let mut request = Vec::new();
+    write_record(&mut request, CRITICAL | RECORD_NEXT_PROTOCOL, &PROTOCOL_NTPV4.to_be_bytes());
+    write_record(&mut request, RECORD_AEAD_ALGORITHM, &AEAD_AES_SIV_CMAC_256.to_be_bytes());
+    write_record(&mut request, CRITICAL | RECORD_END_OF_MESSAGE, &[]);
+    request
+}
+
+fn write_record(out: &mut Vec<u8>, record_type: u16, body: &[u8]) {
+    out.extend_from_slice(&record_type.to_be_bytes());
+    out.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    out.extend_from_slice(body);
+}
+
+// Parse the response received so far, returning `None` until its end of message record arrives.
+fn parse_response(mut data: &[u8]) -> io::Result<Option<Response>> {
+    let mut protocols = None;
+    let mut algorithms = None;
+    let mut response = Response {
+        cookies: Vec::new(),
+        server: None,
+        port: None,
+    };
+    loop {
+        if data.len() < 4 {
+            return Ok(None);
+        }
+        let record_type = u16::from_be_bytes([data[0], data[1]]);
+        let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+        data = &data[4..];
+        if data.len() < len {
+            return Ok(None);
+        }
+        let (body, rest) = data.split_at(len);
+        data = rest;
+        match record_type & !CRITICAL {
+            RECORD_END_OF_MESSAGE => break,
+            RECORD_NEXT_PROTOCOL => protocols = Some(read_ids(body)?),
+            RECORD_ERROR => {
+                let err_msg = format!("nts-ke server reported error {}", read_id(body)?);
+                return Err(io::Error::other(err_msg));
+            }
+            RECORD_WARNING => warn!("nts-ke server reported warning {}", read_id(body)?),
+            RECORD_AEAD_ALGORITHM => algorithms = Some(read_ids(body)?),
+            RECORD_NEW_COOKIE => response.cookies.push(body.to_vec()),
+            RECORD_SERVER => {
+                let server = String::from_utf8(body.to_vec())
+                    .map_err(|_| invalid_data("nts-ke server name is not valid utf-8"))?;
+                response.server = Some(server);
+            }
+            RECORD_PORT => response.port = Some(read_id(body)?),
+            unknown if record_type & CRITICAL != 0 => {
+                let err_msg = format!("unknown critical nts-ke record type {}", unknown);
+                return Err(invalid_data(&err_msg));
+            }
+            unknown => debug!("ignoring nts-ke record type {}", unknown),
+        }
+    }
+
+    if protocols.as_deref() != Some(&[PROTOCOL_NTPV4][..]) {
+        return Err(invalid_data("nts-ke server declined ntpv4"));
+    }
+    if algorithms.as_deref() != Some(&[AEAD_AES_SIV_CMAC_256][..]) {
+        return Err(invalid_data("nts-ke server declined aead_aes_siv_cmac_256"));
+    }
+    if response.cookies.is_empty() {
+        return Err(invalid_data("nts-ke server supplied no cookies"));
+    }
+    Ok(Some(response))
+}
+
+// The 16-bit IDs making up the body of a record.
+fn read_ids(body: &[u8]) -> io::Result<Vec<u16>> {
+    if !body.len().is_multiple_of(2) {
+        return Err(invalid_data("invalid nts-ke record length"));
+    }
+    Ok(body.chunks_exact(2).map(|id| u16::from_be_bytes([id[0], id[1]])).collect())
+}
+
+// The single 16-bit value making up the body of a record.
+fn read_id(body: &[u8]) -> io::Result<u16> {
+    match read_ids(body)?[..] {
+        [id] => Ok(id),
+        _ => Err(invalid_data("invalid nts-ke record length")),
+    }
+}
+
+// The exporter context of the key for `direction`, 0 for client to server and 1 for the reverse,
+// as described in section 5.1 of RFC 8915.
+fn key_context(direction: u8) -> [u8; 5] {
+    let protocol = PROTOCOL_NTPV4.to_be_bytes();
+    let algorithm = AEAD_AES_SIV_CMAC_256.to_be_bytes();
+    [protocol[0], protocol[1], algorithm[0], algorithm[1], direction]
+}
+
+fn invalid_data(err_msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err_msg)
+}
@@ -0,0 +1,87 @@
+//! The rustls TLS backend for NTS key establishment, selected by the `nts-rustls` feature.
+
+use super::ke::{TlsSession, NTS_KE_ALPN};
+
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+// A rustls client session, restricted to TLS 1.3 as RFC 8915 requires.
+pub(crate) struct RustlsSession {
+    conn: ClientConnection,
+}
+
+impl TlsSession for RustlsSession {
+    fn connect(host: &str, roots: &[Vec<u8>]) -> io::Result<Self> {
+        let mut root_store = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        for der in roots {
+            root_store.add(CertificateDer::from(der.clone())).map_err(tls_error)?;
+        }
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(tls_error)?
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![NTS_KE_ALPN.to_vec()];
+        let name = ServerName::try_from(host.to_string())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let conn = ClientConnection::new(Arc::new(config), name).map_err(tls_error)?;
+        Ok(RustlsSession { conn })
+    }
+
+    fn is_handshaking(&self) -> bool {
+        self.conn.is_handshaking()
+    }
+
+    fn write_tls(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        while self.conn.wants_write() {
+            self.conn.write_tls(out)?;
+        }
+        Ok(())
+    }
+
+    fn read_tls(&mut self, mut data: &[u8]) -> io::Result<()> {
+        // The connection may accept only part of the data until it has processed what it holds.
+        while !data.is_empty() {
+            self.conn.read_tls(&mut data)?;
+            self.conn.process_new_packets().map_err(tls_error)?;
+        }
+        Ok(())
+    }
+
+    fn write_plaintext(&mut self, data: &[u8]) -> io::Result<()> {
+        self.conn.writer().write_all(data)
+    }
+
+    fn read_plaintext(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        // The reader would block once it has returned everything received so far.
+        match self.conn.reader().read_to_end(out) {
+            Err(err) if err.kind() != io::ErrorKind::WouldBlock => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.conn.alpn_protocol()
+    }
+
+    fn export_keying_material(
+        &self,
+        label: &[u8],
+        context: &[u8],
+        len: usize,
+    ) -> io::Result<Vec<u8>> {
+        self.conn
+            .export_keying_material(vec![0; len], label, Some(context))
+            .map_err(tls_error)
+    }
+}
+
+fn tls_error(err: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
@@ -6,13 +6,43 @@
 //!
 //! Documentation is largely derived (and often copied directly) from IETF RFC 5905.
 
+// Packets arrive from the network, so malformed input must be rejected with an error rather than
+// unwrapped.
+#![deny(clippy::unwrap_used)]
+
+use crate::{Error, Instant};
+
 use byteorder::{ReadBytesExt, WriteBytesExt, BE};
 use conv::TryFrom;
-use std::{fmt, io};
+use std::time::Duration;
+use std::{error, fmt, io, str};
+
+#[cfg(feature = "ntpv5")]
+mod v5;
+
+#[cfg(feature = "ntpv5")]
+pub use self::v5::{PacketV5, Timescale};
 
 /// NTP port number.
+pub const NTP_PORT: u16 = 123;
+
+/// NTP port number.
+#[deprecated(note = "use `NTP_PORT` instead, which has the `u16` type of port numbers")]
 pub const PORT: u8 = 123;
 
+/// The largest NTP packet, including extension fields and a MAC, that fits in a single UDP
+/// datagram over IPv4.
+pub const MAX_PACKET_SIZE: usize = 65_507;
+
+/// Size of the key identifier with which a MAC begins.
+pub const MAC_KEY_ID_SIZE_BYTES: usize = 4;
+
+/// Size of a MAC carrying a 128-bit MD5 digest (RFC 5905).
+pub const MAC_MD5_SIZE_BYTES: usize = MAC_KEY_ID_SIZE_BYTES + 16;
+
+/// Size of a MAC carrying a 160-bit SHA-1 digest (RFC 8573).
+pub const MAC_SHA1_SIZE_BYTES: usize = MAC_KEY_ID_SIZE_BYTES + 20;
+
 /// Frequency tolerance PHI (s/s).
 pub const TOLERANCE: f64 = 15e-6;
 
@@ -34,6 +64,19 @@ pub const MAXDIST: u8 = 1;
 /// Maximum stratum number.
 pub const MAXSTRAT: u8 = 16;
 
+/// Field type of the Unique Identifier extension field (RFC 8915).
+pub const UNIQUE_IDENTIFIER_FIELD_TYPE: u16 = 0x0104;
+
+/// Field type of the NTS Cookie extension field (RFC 8915).
+pub const NTS_COOKIE_FIELD_TYPE: u16 = 0x0204;
+
+/// Field type of the NTS Cookie Placeholder extension field (RFC 8915).
+pub const NTS_COOKIE_PLACEHOLDER_FIELD_TYPE: u16 = 0x0304;
+
+/// Field type of the NTS Authenticator and Encrypted Extension Fields extension field
+/// (RFC 8915).
+pub const NTS_AUTHENTICATOR_FIELD_TYPE: u16 = 0x0404;
+
 /// A trait for writing any of the Network Time Protocol types to network-endian bytes.
 ///
 /// A blanket implementation is provided for all types that implement `byteorder::WriteBytesExt`.
@@ -146,9 +189,10 @@ custom_derive! {
     /// As the only constructors are via associated constants, it should be impossible to create an
     /// invalid `LeapIndicator`.
     #[repr(u8)]
-    #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, TryFrom(u8))]
+    #[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq, TryFrom(u8))]
     pub enum LeapIndicator {
         /// No leap required.
+        #[default]
         NoWarning = 0,
         /// Last minute of the day has 61 seconds.
         AddOne = 1,
@@ -159,6 +203,45 @@ custom_derive! {
     }
 }
 
+/// The direction of a leap second announced by a server's `LeapIndicator`.
+///
+/// A pending leap second takes effect at the end of the last minute of the current UTC month.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum LeapDirection {
+    /// A second will be inserted, giving the last minute 61 seconds.
+    Insert,
+    /// A second will be deleted, giving the last minute 59 seconds.
+    Delete,
+}
+
+/// Which of the server's timestamps to read from a packet, as by `Packet::timestamp` and
+/// `parse_timestamp`.
+///
+/// A client in client/server mode wants the transmit timestamp, which is the default. Where only
+/// the server's timestamps are usable, as in a one-way or broadcast context, another may be of
+/// interest without the full offset computation of `NtpMeasurement`.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub enum TimestampSelector {
+    /// The transmit timestamp, at which the server sent the packet (T3).
+    #[default]
+    Transmit,
+    /// The receive timestamp, at which the server received the request it answers (T2).
+    Receive,
+    /// The reference timestamp, at which the server last synchronized its clock.
+    Reference,
+}
+
+impl TimestampSelector {
+    // The offset of the selected timestamp within the packet header.
+    fn offset(&self) -> usize {
+        match self {
+            TimestampSelector::Reference => 16,
+            TimestampSelector::Receive => 32,
+            TimestampSelector::Transmit => 40,
+        }
+    }
+}
+
 /// A 3-bit integer representing the NTP version number, currently 4.
 ///
 /// Note that while this struct is 8-bits, this field is packed to 3 in the actual header.
@@ -210,6 +293,13 @@ custom_derive! {
 #[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Stratum(pub u8);
 
+/// An 8-bit signed integer representing an interval as a power of two seconds, as used by the
+/// poll and precision fields.
+///
+/// The exponent is signed, so the byte `0xEC` is -20, or about a microsecond, rather than 236.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct LogSeconds(pub i8);
+
 /// A 32-bit code identifying the particular server or reference clock.
 ///
 /// The interpretation depends on the value in the stratum field:
@@ -236,7 +326,32 @@ pub enum ReferenceIdentifier {
     /// client, the Reference Identifier field appears to be a random value and a timing loop might
     /// not be detected.
     SecondaryOrClient([u8; 4]),
+    /// A reference identifier of a secondary server that is a known sentinel for a state of the
+    /// server rather than the address of its own server, which it would otherwise be mistaken
+    /// for. See `SpecialReference` for those recognized.
+    Special(SpecialReference),
     KissOfDeath(KissOfDeath),
+    /// A reference identifier of a stratum 0 or 1 packet that is neither a known primary source
+    /// nor a kiss code, such as a vendor-specific identifier, as its raw four octets. Also used,
+    /// zeroed, for NTPv5 responses, whose header carries none.
+    Unknown([u8; 4]),
+}
+
+/// A reference identifier sent by a secondary server in place of the address of a server.
+///
+/// Only the identifiers of these popular implementations are recognized, as any other is
+/// indistinguishable from an IPv4 address or the hash of an IPv6 address. Interleaved mode,
+/// which chrony enables with `xleave`, is signalled by the timestamps of the response rather than
+/// its reference identifier; see `NtpClient::interleaved`.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum SpecialReference {
+    /// `127.127.1.1`, sent by chrony when its own clock is the reference, as configured by its
+    /// `local` directive, including in orphan mode.
+    ChronyLocal,
+    /// The ASCII string `LOCL`, sent by ntpd when synchronized to its undisciplined local clock
+    /// driver, which is typically fudged to stratum 10. It would otherwise be read as the
+    /// address 76.79.67.76.
+    NtpdLocalClock,
 }
 
 // Convert an ascii string to a big-endian u32.
@@ -258,6 +373,7 @@ custom_derive! {
     /// development.
     #[repr(u32)]
     #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, TryFrom(u32))]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub enum PrimarySource {
         Goes = code_to_u32!(b"GOES"),
         Gps = code_to_u32!(b"GPS\0"),
@@ -413,15 +529,14 @@ pub struct Packet {
     pub version: Version,
     pub mode: Mode,
     pub stratum: Stratum,
-    /// 8-bit signed integer representing the maximum interval between successive messages, in log2
-    /// seconds. Suggested default limits for minimum and maximum poll intervals are 6 and 10,
-    /// respectively.
-    pub poll: i8,
-    /// 8-bit signed integer representing the precision of the system clock, in log2 seconds. For
-    /// instance, a value of -18 corresponds to a precision of about one microsecond. The precision
-    /// can be determined when the service first starts up as the minimum time of several
-    /// iterations to read the system clock.
-    pub precision: i8,
+    /// The maximum interval between successive messages, in log2 seconds. Suggested default
+    /// limits for minimum and maximum poll intervals are 6 and 10, respectively.
+    pub poll: LogSeconds,
+    /// The precision of the system clock, in log2 seconds. For instance, a value of -18
+    /// corresponds to a precision of about one microsecond. The precision can be determined when
+    /// the service first starts up as the minimum time of several iterations to read the system
+    /// clock.
+    pub precision: LogSeconds,
     /// Total round-trip delay to the reference clock, in NTP short format.
     pub root_delay: ShortFormat,
     /// Total dispersion to the reference clock, in NTP short format.
@@ -440,8 +555,218 @@ pub struct Packet {
 /// The consecutive types within the first packed byte in the NTP packet.
 pub type PacketByte1 = (LeapIndicator, Version, Mode);
 
+/// The fields of the first word of a `Packet`, decoded, as returned by `Packet::header`.
+///
+/// ```ignore
+///  0                   1                   2                   3
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |LI | VN  |Mode |    Stratum     |     Poll      |  Precision   |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct NtpHeader {
+    pub leap_indicator: LeapIndicator,
+    pub version: Version,
+    pub mode: Mode,
+    pub stratum: Stratum,
+    pub poll: LogSeconds,
+    pub precision: LogSeconds,
+}
+
+/// An error returned when parsing a `ShortFormat` or `TimestampFormat` from decimal seconds.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseFormatError {
+    msg: &'static str,
+}
+
+/// The outcome of each of the RFC 5905 packet sanity tests that can be applied to a response on
+/// its own, as returned by `Packet::run_sanity_tests`. Each field is `true` if the test passed.
+///
+/// TEST1 (duplicate packet) requires the client's state and is not included, nor are TEST4 and
+/// TEST5 which concern access control and authentication.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SanityReport {
+    /// TEST2: the origin timestamp matches the transmit timestamp of our request, i.e. the
+    /// packet is not bogus.
+    pub origin_matches: bool,
+    /// TEST3: none of the origin, receive and transmit timestamps are zero.
+    pub timestamps_valid: bool,
+    /// TEST6: the server's clock is synchronized: the leap indicator is not `Unknown` and the
+    /// reference timestamp does not follow the transmit timestamp.
+    pub synchronized: bool,
+    /// TEST7: the stratum is that of a primary or secondary server.
+    pub stratum_valid: bool,
+    /// TEST8: half the root delay plus the root dispersion is below `MAXDISP`.
+    pub root_distance_valid: bool,
+}
+
+/// An extension field following the packet header, as described by RFC 7822.
+///
+/// ### Layout
+///
+/// ```ignore
+///  0                   1                   2                   3
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |          Field Type           |            Length             |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// .                                                               .
+/// .                            Value                              .
+/// .                                                               .
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                       Padding (as needed)                     |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+///
+/// The length covers the whole field, which is zero padded to a multiple of four bytes and at
+/// least `MIN_PACKED_SIZE_BYTES`. As the padding cannot be told apart from the value, a field
+/// read from bytes has a `value` including any padding.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RawExtensionField {
+    /// The 16-bit type of the field, e.g. `UNIQUE_IDENTIFIER_FIELD_TYPE`.
+    pub field_type: u16,
+    /// The value of the field, excluding its header.
+    pub value: Vec<u8>,
+}
+
+/// An extension field decoded according to its type.
+///
+/// As with **RawExtensionField**, the value of a field whose length does not itself describe,
+/// such as a cookie, includes any padding it was read with.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ExtensionField {
+    /// A Unique Identifier, the random value a client sends and a server echoes.
+    UniqueIdentifier(Vec<u8>),
+    /// An NTS cookie, sent by the client and returned, encrypted, by the server.
+    NtsCookie(Vec<u8>),
+    /// A request for an additional cookie, whose value is as long as the cookie it requests and
+    /// otherwise meaningless.
+    NtsCookiePlaceholder,
+    /// The NTS authenticator, holding the AEAD nonce and the ciphertext of any encrypted
+    /// extension fields.
+    NtsAuthenticator {
+        nonce: Vec<u8>,
+        ciphertext: Vec<u8>,
+    },
+    /// A field of a type not decoded by this crate.
+    Unknown { field_type: u16, value: Vec<u8> },
+}
+
+/// An iterator over the packets in a stream of concatenated 48 byte NTP payloads, such as
+/// payloads extracted from a packet capture.
+///
+/// Each record is parsed independently, so a malformed record yields an error without ending
+/// the iteration. A trailing record shorter than a packet yields an `UnexpectedEof` error and
+/// ends the iteration, as does any error reading from the underlying stream.
+#[derive(Debug)]
+pub struct PacketReader<R> {
+    reader: R,
+    done: bool,
+}
+
 // Inherent implementations.
 
+impl ShortFormat {
+    /// The interval in fractional seconds.
+    pub fn as_secs_f64(&self) -> f64 {
+        self.seconds as f64 + self.fraction as f64 / 65_536.0
+    }
+}
+
+impl RawExtensionField {
+    /// The smallest size of an extension field in bytes.
+    pub const MIN_PACKED_SIZE_BYTES: usize = 16;
+
+    /// The size of the field in bytes once written, including its header and padding.
+    pub fn packed_size_bytes(&self) -> usize {
+        let len = (4 + self.value.len()).div_ceil(4) * 4;
+        len.max(RawExtensionField::MIN_PACKED_SIZE_BYTES)
+    }
+
+    /// Read the extension fields filling `bytes`, such as those following the header of a
+    /// packet.
+    ///
+    /// Returns an `InvalidData` error if a field's length is not a multiple of four or runs past
+    /// the end of `bytes`.
+    pub fn parse_all(mut bytes: &[u8]) -> io::Result<Vec<RawExtensionField>> {
+        let mut fields = Vec::new();
+        while !bytes.is_empty() {
+            let field: RawExtensionField = (&mut bytes).read_bytes()?;
+            fields.push(field);
+        }
+        Ok(fields)
+    }
+}
+
+impl ExtensionField {
+    /// Decode a raw extension field according to its type.
+    ///
+    /// Returns an `InvalidData` error if an NTS authenticator's nonce and ciphertext lengths
+    /// exceed the field.
+    pub fn decode(raw: &RawExtensionField) -> io::Result<ExtensionField> {
+        let value = raw.value.clone();
+        let field = match raw.field_type {
+            UNIQUE_IDENTIFIER_FIELD_TYPE => ExtensionField::UniqueIdentifier(value),
+            NTS_COOKIE_FIELD_TYPE => ExtensionField::NtsCookie(value),
+            NTS_COOKIE_PLACEHOLDER_FIELD_TYPE => ExtensionField::NtsCookiePlaceholder,
+            NTS_AUTHENTICATOR_FIELD_TYPE => {
+                let mut reader = &raw.value[..];
+                let nonce_len = reader.read_u16::<BE>()? as usize;
+                let ciphertext_len = reader.read_u16::<BE>()? as usize;
+                // The nonce is zero padded to a multiple of four bytes.
+                let nonce_end = 4 + nonce_len.div_ceil(4) * 4;
+                if raw.value.len() < nonce_end + ciphertext_len {
+                    let err_msg = "nts authenticator lengths exceed the field";
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+                }
+                ExtensionField::NtsAuthenticator {
+                    nonce: raw.value[4..4 + nonce_len].to_vec(),
+                    ciphertext: raw.value[nonce_end..nonce_end + ciphertext_len].to_vec(),
+                }
+            }
+            field_type => ExtensionField::Unknown { field_type, value },
+        };
+        Ok(field)
+    }
+
+    /// Read and decode the extension fields filling `bytes`, such as those following the header
+    /// of a packet.
+    pub fn parse_all(bytes: &[u8]) -> io::Result<Vec<ExtensionField>> {
+        RawExtensionField::parse_all(bytes)?.iter().map(ExtensionField::decode).collect()
+    }
+
+    /// The first of `fields` of the given type, e.g. `NTS_COOKIE_FIELD_TYPE`.
+    ///
+    /// Extension fields follow the packet header rather than being part of **Packet**, so they
+    /// are looked up among the fields parsed from the bytes following it.
+    pub fn find(fields: &[ExtensionField], field_type: u16) -> Option<&ExtensionField> {
+        fields.iter().find(|field| field.field_type() == field_type)
+    }
+
+    /// The 16-bit type of the field.
+    pub fn field_type(&self) -> u16 {
+        match *self {
+            ExtensionField::UniqueIdentifier(_) => UNIQUE_IDENTIFIER_FIELD_TYPE,
+            ExtensionField::NtsCookie(_) => NTS_COOKIE_FIELD_TYPE,
+            ExtensionField::NtsCookiePlaceholder => NTS_COOKIE_PLACEHOLDER_FIELD_TYPE,
+            ExtensionField::NtsAuthenticator { .. } => NTS_AUTHENTICATOR_FIELD_TYPE,
+            ExtensionField::Unknown { field_type, .. } => field_type,
+        }
+    }
+}
+
+impl SanityReport {
+    /// Whether or not the packet passed every test.
+    pub fn passed(&self) -> bool {
+        self.origin_matches
+            && self.timestamps_valid
+            && self.synchronized
+            && self.stratum_valid
+            && self.root_distance_valid
+    }
+}
+
 impl PrimarySource {
     /// The bytestring representation of the primary source.
     pub fn bytes(&self) -> [u8; 4] {
@@ -449,15 +774,82 @@ impl PrimarySource {
     }
 }
 
+impl SpecialReference {
+    /// The four octets of the reference identifier as they appear on the wire.
+    pub fn bytes(&self) -> [u8; 4] {
+        match *self {
+            SpecialReference::ChronyLocal => [127, 127, 1, 1],
+            SpecialReference::NtpdLocalClock => *b"LOCL",
+        }
+    }
+
+    /// The special reference identifier of the given octets, if they are one.
+    pub fn from_bytes(bytes: [u8; 4]) -> Option<Self> {
+        match &bytes {
+            [127, 127, 1, 1] => Some(SpecialReference::ChronyLocal),
+            b"LOCL" => Some(SpecialReference::NtpdLocalClock),
+            _ => None,
+        }
+    }
+}
+
+impl ReferenceIdentifier {
+    /// The four octets of the reference identifier exactly as they appear on the wire.
+    pub fn bytes(&self) -> [u8; 4] {
+        match *self {
+            ReferenceIdentifier::PrimarySource(src) => src.bytes(),
+            ReferenceIdentifier::Special(special) => special.bytes(),
+            ReferenceIdentifier::KissOfDeath(kod) => be_u32_to_bytes(kod as u32),
+            ReferenceIdentifier::SecondaryOrClient(arr) | ReferenceIdentifier::Unknown(arr) => arr,
+        }
+    }
+}
+
 impl Version {
     pub const V1: Self = Version(1);
     pub const V2: Self = Version(2);
     pub const V3: Self = Version(3);
     pub const V4: Self = Version(4);
+    /// The NTPv5 draft, available with the experimental `ntpv5` feature.
+    #[cfg(feature = "ntpv5")]
+    pub const V5: Self = Version(5);
+
+    /// The version number.
+    pub fn number(&self) -> u8 {
+        self.0
+    }
 
     /// Whether or not the version is a known, valid version.
     pub fn is_known(&self) -> bool {
-        self.0 >= 1 && self.0 <= 4
+        let latest = if cfg!(feature = "ntpv5") { 5 } else { 4 };
+        self.0 >= 1 && self.0 <= latest
+    }
+}
+
+impl<R: io::Read> PacketReader<R> {
+    /// Create a **PacketReader** reading records from the given stream.
+    pub fn new(reader: R) -> Self {
+        PacketReader { reader, done: false }
+    }
+
+    /// Unwrap the underlying stream.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    // Fill the buffer with the next record, returning the number of bytes read which is less than
+    // a packet only at the end of the stream.
+    fn read_record(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut len = 0;
+        while len < buf.len() {
+            match self.reader.read(&mut buf[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(len)
     }
 }
 
@@ -486,6 +878,269 @@ impl Stratum {
     }
 }
 
+impl LogSeconds {
+    /// The interval in fractional seconds, `2^self.0`.
+    pub fn as_secs_f64(&self) -> f64 {
+        2f64.powi(self.0 as i32)
+    }
+
+    /// The interval as a `Duration`, rounded to the nanosecond, so that exponents below -30 are
+    /// zero.
+    pub fn as_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.as_secs_f64())
+    }
+
+    /// The exponent of the power of two seconds nearest to `duration`, saturating at the bounds
+    /// of the field, such that a zero duration is `i8::MIN`.
+    pub fn from_duration(duration: Duration) -> Self {
+        let log2 = duration.as_secs_f64().log2().round();
+        LogSeconds(log2.clamp(i8::MIN as f64, i8::MAX as f64) as i8)
+    }
+}
+
+impl NtpHeader {
+    /// Whether the mode is one in which a packet solicits a response, i.e. client or symmetric
+    /// active.
+    pub fn is_request(&self) -> bool {
+        matches!(self.mode, Mode::Client | Mode::SymmetricActive)
+    }
+
+    /// Whether the mode is one in which a packet carries the sender's time to a peer, i.e.
+    /// server, broadcast or symmetric passive.
+    pub fn is_response(&self) -> bool {
+        matches!(self.mode, Mode::Server | Mode::Broadcast | Mode::SymmetricPassive)
+    }
+}
+
+impl Packet {
+    /// The leap second announced by the server, if any.
+    ///
+    /// This crate never applies the leap second itself; doing so is the responsibility of the
+    /// operating system. An unsynchronized server (`LeapIndicator::Unknown`) announces no leap.
+    pub fn pending_leap(&self) -> Option<LeapDirection> {
+        match self.leap_indicator {
+            LeapIndicator::AddOne => Some(LeapDirection::Insert),
+            LeapIndicator::SubOne => Some(LeapDirection::Delete),
+            LeapIndicator::NoWarning | LeapIndicator::Unknown => None,
+        }
+    }
+
+    /// The start of the last second of the UTC month containing `now`, at which the leap second
+    /// announced by the server occurs, or `None` if no leap is pending.
+    ///
+    /// Leap seconds occur only at the end of a month, so an announcement applies to the month in
+    /// progress. An inserted second follows the returned instant as 23:59:60, and a deleted
+    /// second is the returned instant itself, 23:59:59. Also `None` should the month end beyond
+    /// the range of `Instant`.
+    pub fn leap_second_date(&self, now: Instant) -> Option<Instant> {
+        self.pending_leap()?;
+        let days = now.as_nanos().div_euclid(86_400 * 1_000_000_000) as i64;
+        let (year, month, _) = crate::civil_from_days(days);
+        let (year, month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+        let next_month = crate::days_from_civil(year, month, 1).checked_mul(86_400)?;
+        Some(Instant::new(next_month.checked_sub(1)?, 0))
+    }
+
+    /// The server's timestamp chosen by `selector`.
+    pub fn timestamp(&self, selector: TimestampSelector) -> TimestampFormat {
+        match selector {
+            TimestampSelector::Transmit => self.transmit_timestamp,
+            TimestampSelector::Receive => self.receive_timestamp,
+            TimestampSelector::Reference => self.reference_timestamp,
+        }
+    }
+
+    /// The time at which the server last synchronized its clock, or `None` if the reference
+    /// timestamp is zero (the server has never synchronized). The timestamp is read in NTP era
+    /// 0; `reference_age` places it in the era of the time it is compared with.
+    pub fn reference_instant(&self) -> Option<Instant> {
+        if self.reference_timestamp == TimestampFormat::default() {
+            None
+        } else {
+            Some(self.reference_timestamp.into())
+        }
+    }
+
+    /// The reference identifier exactly as it appears on the wire, however it was decoded.
+    pub fn reference_id_raw(&self) -> [u8; 4] {
+        self.reference_id.bytes()
+    }
+
+    /// The decoded fields of the first word of the packet.
+    pub fn header(&self) -> NtpHeader {
+        NtpHeader {
+            leap_indicator: self.leap_indicator,
+            version: self.version,
+            mode: self.mode,
+            stratum: self.stratum,
+            poll: self.poll,
+            precision: self.precision,
+        }
+    }
+
+    /// The poll exponent, the log2 of the maximum interval in seconds between successive
+    /// messages.
+    pub fn poll(&self) -> LogSeconds {
+        self.poll
+    }
+
+    /// The precision exponent, the log2 of the precision in seconds of the sender's clock.
+    pub fn precision(&self) -> LogSeconds {
+        self.precision
+    }
+
+    /// The precision of the server's clock in seconds, decoded from the log2 `precision` field.
+    ///
+    /// For example, a precision of -20 (typical of a GPS disciplined clock) is about a
+    /// microsecond, while -6 (a coarse software clock) is over 15 milliseconds.
+    pub fn precision_secs(&self) -> f64 {
+        self.precision.as_secs_f64()
+    }
+
+    /// Apply the RFC 5905 packet sanity tests to this response to a request sent with the transmit
+    /// timestamp `our_transmit`.
+    pub fn run_sanity_tests(&self, our_transmit: TimestampFormat) -> SanityReport {
+        let zero = TimestampFormat::default();
+        let root_distance =
+            self.root_delay.as_secs_f64() / 2.0 + self.root_dispersion.as_secs_f64();
+        SanityReport {
+            origin_matches: self.origin_timestamp == our_transmit,
+            timestamps_valid: self.origin_timestamp != zero
+                && self.receive_timestamp != zero
+                && self.transmit_timestamp != zero,
+            synchronized: self.leap_indicator != LeapIndicator::Unknown
+                && self.reference_timestamp <= self.transmit_timestamp,
+            stratum_valid: Stratum::PRIMARY <= self.stratum
+                && self.stratum <= Stratum::SECONDARY_MAX,
+            root_distance_valid: root_distance < MAXDISP,
+        }
+    }
+
+    /// The number of servers between this one and its reference clock, i.e. the stratum less
+    /// one: 0 for a primary server and 2 for one at stratum 3. Zero for the unspecified stratum.
+    ///
+    /// This counts hops only; the distance to the reference clock in time, which tells a nearby
+    /// server from a far one of the same stratum, is the root distance given by
+    /// `NtpMeasurement::quality_score`. The unsynchronized stratum 16 yields 15, which is
+    /// meaningless.
+    pub fn hops(&self) -> u8 {
+        self.stratum.0.saturating_sub(1)
+    }
+
+    /// Whether or not the packet originates from a server in orphan mode, given the orphan
+    /// stratum configured for the subnet (`tos orphan` in ntpd, commonly 10).
+    ///
+    /// Servers that lose all of their upstream sources may fall back to orphan mode, in which they
+    /// advertise the orphan stratum and agree among themselves on a common time that is not
+    /// derived from any reference clock. Servers synchronized to an orphan advertise a stratum up
+    /// to `SECONDARY_MAX` above it, so any stratum from `orphan_stratum` up to `SECONDARY_MAX` is
+    /// considered orphaned.
+    pub fn is_orphan(&self, orphan_stratum: u8) -> bool {
+        Stratum(orphan_stratum) <= self.stratum && self.stratum <= Stratum::SECONDARY_MAX
+    }
+
+    /// Build a server reply to this client request.
+    ///
+    /// The request's transmit timestamp is copied to the reply's origin timestamp so that the
+    /// client can match the reply to its request, and the reply is stamped with the time the
+    /// server last synchronized its clock (`reference_time`), the time the request was received
+    /// (`recv_time`) and the time the reply is sent (`xmit_time`). The version and poll interval
+    /// are echoed from the request.
+    ///
+    /// The precision, root delay and root dispersion of the reply are zero and may be set on the
+    /// returned packet to describe the server's clock.
+    ///
+    /// Returns `Error::BeforePrimeEpoch` if any of the times precedes 1 January 1900, which a
+    /// timestamp cannot represent.
+    pub fn make_reply(
+        &self,
+        stratum: Stratum,
+        reference_id: ReferenceIdentifier,
+        reference_time: Instant,
+        recv_time: Instant,
+        xmit_time: Instant,
+    ) -> Result<Packet, Error> {
+        Ok(Packet {
+            leap_indicator: LeapIndicator::NoWarning,
+            version: self.version,
+            mode: Mode::Server,
+            stratum,
+            poll: self.poll,
+            precision: LogSeconds(0),
+            root_delay: ShortFormat::default(),
+            root_dispersion: ShortFormat::default(),
+            reference_id,
+            reference_timestamp: TimestampFormat::from_instant_checked(reference_time)?,
+            origin_timestamp: self.transmit_timestamp,
+            receive_timestamp: TimestampFormat::from_instant_checked(recv_time)?,
+            transmit_timestamp: TimestampFormat::from_instant_checked(xmit_time)?,
+        })
+    }
+
+    /// How long before `now` the server last synchronized its clock.
+    ///
+    /// The reference timestamp is placed in the NTP era nearest `now`, so that the age remains
+    /// correct beyond the 2036 rollover. Returns `None` if the reference timestamp is zero. A
+    /// reference timestamp later than `now` is reported as an age of zero.
+    pub fn reference_age(&self, now: Instant) -> Option<Duration> {
+        self.reference_instant()?;
+        let reference = self.reference_timestamp.to_instant_near(now);
+        let nanos = |t: Instant| t.secs() as i128 * 1_000_000_000 + t.subsec_nanos() as i128;
+        let age = (nanos(now) - nanos(reference)).max(0);
+        Some(Duration::from_nanos(age as u64))
+    }
+}
+
+/// Read only the transmit timestamp of a packet, without parsing the rest of its header.
+///
+/// This is all an SNTP client needs of the response to set its clock. Fails with
+/// `UnexpectedEof` if `bytes` is shorter than a packet header. No other field is checked.
+pub fn parse_transmit_timestamp(bytes: &[u8]) -> io::Result<TimestampFormat> {
+    parse_timestamp(bytes, TimestampSelector::Transmit)
+}
+
+/// Read only the server's timestamp chosen by `selector`, as `parse_transmit_timestamp` does the
+/// transmit timestamp.
+pub fn parse_timestamp(bytes: &[u8], selector: TimestampSelector) -> io::Result<TimestampFormat> {
+    if bytes.len() < Packet::PACKED_SIZE_BYTES {
+        let err_msg = "packet is shorter than an ntp header";
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, err_msg));
+    }
+    let offset = selector.offset();
+    (&bytes[offset..offset + TimestampFormat::PACKED_SIZE_BYTES]).read_bytes()
+}
+
+/// Build a minimal NTPv4 client request, as recommended for SNTP clients by RFC 4330: every
+/// field is zero other than the version and mode.
+///
+/// The server echoes the transmit timestamp (bytes 40 to 47) as the origin timestamp of its
+/// response, so a client wishing to match the response to its request may write one there.
+pub fn quick_build_client() -> [u8; Packet::PACKED_SIZE_BYTES] {
+    let mut bytes = [0u8; Packet::PACKED_SIZE_BYTES];
+    bytes[0] = (Version::V4.0 << 3) | Mode::Client as u8;
+    bytes
+}
+
+/// Build `count` distinct client requests as `quick_build_client` does, for load testing a
+/// server, each carrying a unique nonce as its transmit timestamp.
+///
+/// The nonces are consecutive from a random start, so that the packets of separate batches are
+/// unlikely to coincide, and the server's responses are matched to the requests by their origin
+/// timestamps. Requires the `loadtest` feature.
+#[cfg(feature = "loadtest")]
+pub fn build_client_batch(count: usize) -> Vec<[u8; Packet::PACKED_SIZE_BYTES]> {
+    let start: u64 = rand::random();
+    (0..count as u64).map(|i| build_client_with_nonce(start.wrapping_add(i))).collect()
+}
+
+// A client request as `quick_build_client` builds, carrying `nonce` as its transmit timestamp.
+#[cfg(feature = "loadtest")]
+pub(crate) fn build_client_with_nonce(nonce: u64) -> [u8; Packet::PACKED_SIZE_BYTES] {
+    let mut bytes = quick_build_client();
+    bytes[40..48].copy_from_slice(&nonce.to_be_bytes());
+    bytes
+}
+
 // Size implementations.
 
 impl ConstPackedSizeBytes for ShortFormat {
@@ -504,6 +1159,10 @@ impl ConstPackedSizeBytes for Stratum {
     const PACKED_SIZE_BYTES: usize = 1;
 }
 
+impl ConstPackedSizeBytes for LogSeconds {
+    const PACKED_SIZE_BYTES: usize = 1;
+}
+
 impl ConstPackedSizeBytes for ReferenceIdentifier {
     const PACKED_SIZE_BYTES: usize = 4;
 }
@@ -516,7 +1175,7 @@ impl ConstPackedSizeBytes for Packet {
     const PACKED_SIZE_BYTES: usize =
         PacketByte1::PACKED_SIZE_BYTES
         + Stratum::PACKED_SIZE_BYTES
-        + 2
+        + LogSeconds::PACKED_SIZE_BYTES * 2
         + ShortFormat::PACKED_SIZE_BYTES * 2
         + ReferenceIdentifier::PACKED_SIZE_BYTES
         + TimestampFormat::PACKED_SIZE_BYTES * 4;
@@ -533,7 +1192,7 @@ where
     }
 }
 
-impl<'a, P> WriteToBytes for &'a P
+impl<P> WriteToBytes for &P
 where
     P: WriteToBytes,
 {
@@ -574,19 +1233,31 @@ impl WriteToBytes for Stratum {
     }
 }
 
+impl WriteToBytes for LogSeconds {
+    fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_i8(self.0)?;
+        Ok(())
+    }
+}
+
 impl WriteToBytes for ReferenceIdentifier {
     fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
-        match *self {
-            ReferenceIdentifier::KissOfDeath(kod) => {
-                writer.write_u32::<BE>(kod as u32)?;
-            }
-            ReferenceIdentifier::PrimarySource(src) => {
-                writer.write_u32::<BE>(src as u32)?;
-            }
-            ReferenceIdentifier::SecondaryOrClient(arr) => {
-                writer.write_u32::<BE>(code_to_u32!(&arr))?;
-            }
+        writer.write_all(&self.bytes())?;
+        Ok(())
+    }
+}
+
+impl WriteToBytes for RawExtensionField {
+    fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
+        let len = self.packed_size_bytes();
+        if len > u16::MAX as usize {
+            let err_msg = "extension field value is too long";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg));
         }
+        writer.write_u16::<BE>(self.field_type)?;
+        writer.write_u16::<BE>(len as u16)?;
+        writer.write_all(&self.value)?;
+        writer.write_all(&vec![0; len - 4 - self.value.len()])?;
         Ok(())
     }
 }
@@ -608,8 +1279,8 @@ impl WriteToBytes for Packet {
         let li_vn_mode = (self.leap_indicator, self.version, self.mode);
         writer.write_bytes(li_vn_mode)?;
         writer.write_bytes(self.stratum)?;
-        writer.write_i8(self.poll)?;
-        writer.write_i8(self.precision)?;
+        writer.write_bytes(self.poll)?;
+        writer.write_bytes(self.precision)?;
         writer.write_bytes(self.root_delay)?;
         writer.write_bytes(self.root_dispersion)?;
         writer.write_bytes(self.reference_id)?;
@@ -667,6 +1338,36 @@ impl ReadFromBytes for Stratum {
     }
 }
 
+impl ReadFromBytes for LogSeconds {
+    fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
+        let log_seconds = LogSeconds(reader.read_i8()?);
+        Ok(log_seconds)
+    }
+}
+
+impl ReadFromBytes for RawExtensionField {
+    fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
+        let field_type = reader.read_u16::<BE>()?;
+        let len = reader.read_u16::<BE>()? as usize;
+        if len < 4 || !len.is_multiple_of(4) {
+            let err_msg = "extension field length is not a positive multiple of four";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        }
+        let mut value = vec![0; len - 4];
+        reader.read_exact(&mut value).map_err(|_| {
+            let err_msg = "extension field runs past the end of the packet";
+            io::Error::new(io::ErrorKind::InvalidData, err_msg)
+        })?;
+        Ok(RawExtensionField { field_type, value })
+    }
+}
+
+impl ReadFromBytes for ExtensionField {
+    fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
+        ExtensionField::decode(&reader.read_bytes()?)
+    }
+}
+
 impl ReadFromBytes for (LeapIndicator, Version, Mode) {
     fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
         let li_vn_mode = reader.read_u8()?;
@@ -676,7 +1377,7 @@ impl ReadFromBytes for (LeapIndicator, Version, Mode) {
         let li = match LeapIndicator::try_from(li_u8).ok() {
             Some(li) => li,
             None => {
-                let err_msg = "unknown leap indicator";
+                let err_msg = format!("unknown leap indicator {}", li_u8);
                 return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
             },
         };
@@ -684,7 +1385,7 @@ impl ReadFromBytes for (LeapIndicator, Version, Mode) {
         let mode = match Mode::try_from(mode_u8).ok() {
             Some(mode) => mode,
             None => {
-                let err_msg = "unknown association mode";
+                let err_msg = format!("unknown association mode {}", mode_u8);
                 return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
             },
         };
@@ -696,8 +1397,8 @@ impl ReadFromBytes for Packet {
     fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
         let (leap_indicator, version, mode) = reader.read_bytes()?;
         let stratum = reader.read_bytes::<Stratum>()?;
-        let poll = reader.read_i8()?;
-        let precision = reader.read_i8()?;
+        let poll = reader.read_bytes()?;
+        let precision = reader.read_bytes()?;
         let root_delay = reader.read_bytes()?;
         let root_dispersion = reader.read_bytes()?;
         let reference_id = {
@@ -707,17 +1408,27 @@ impl ReadFromBytes for Packet {
                     Ok(src) => ReferenceIdentifier::PrimarySource(src),
                     Err(_) => match KissOfDeath::try_from(u) {
                         Ok(kod) => ReferenceIdentifier::KissOfDeath(kod),
-                        Err(_) => {
-                            let err_msg = "unknown reference id";
-                            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
-                        }
+                        Err(_) => ReferenceIdentifier::Unknown(be_u32_to_bytes(u)),
                     },
                 }
             } else if stratum.is_secondary() {
                 let arr = be_u32_to_bytes(u);
-                ReferenceIdentifier::SecondaryOrClient(arr)
+                match SpecialReference::from_bytes(arr) {
+                    Some(special) => ReferenceIdentifier::Special(special),
+                    None => ReferenceIdentifier::SecondaryOrClient(arr),
+                }
+            } else if stratum == Stratum::UNSPECIFIED {
+                // Either a kiss-o'-death message or a client request, which usually carries a
+                // null reference id.
+                match KissOfDeath::try_from(u) {
+                    Ok(kod) => ReferenceIdentifier::KissOfDeath(kod),
+                    Err(_) => match PrimarySource::try_from(u) {
+                        Ok(src) => ReferenceIdentifier::PrimarySource(src),
+                        Err(_) => ReferenceIdentifier::Unknown(be_u32_to_bytes(u)),
+                    },
+                }
             } else {
-                let err_msg = "unsupported stratum";
+                let err_msg = format!("unsupported stratum {}", stratum.0);
                 return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
             }
         };
@@ -743,11 +1454,51 @@ impl ReadFromBytes for Packet {
     }
 }
 
-// Manual default implementations.
+// Iterator implementations.
+
+impl<R: io::Read> Iterator for PacketReader<R> {
+    type Item = io::Result<Packet>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut buf = [0u8; Packet::PACKED_SIZE_BYTES];
+        match self.read_record(&mut buf) {
+            Ok(0) => {
+                self.done = true;
+                None
+            }
+            Ok(len) if len < buf.len() => {
+                self.done = true;
+                let err_msg = "trailing record is shorter than an ntp packet";
+                Some(Err(io::Error::new(io::ErrorKind::UnexpectedEof, err_msg)))
+            }
+            Ok(_) => Some((&buf[..]).read_bytes()),
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+// Array conversion implementations.
 
-impl Default for LeapIndicator {
-    fn default() -> Self {
-        LeapIndicator::NoWarning
+/// Parses a packet from exactly the 48 bytes of its header, returning `Error::InvalidPacket` if
+/// any field holds a value that cannot be decoded.
+impl std::convert::TryFrom<[u8; Packet::PACKED_SIZE_BYTES]> for Packet {
+    type Error = crate::Error;
+    fn try_from(bytes: [u8; Packet::PACKED_SIZE_BYTES]) -> Result<Self, Self::Error> {
+        (&bytes[..]).read_bytes().map_err(|err| crate::Error::InvalidPacket(err.to_string()))
+    }
+}
+
+impl From<&Packet> for [u8; Packet::PACKED_SIZE_BYTES] {
+    fn from(packet: &Packet) -> Self {
+        let mut bytes = [0u8; Packet::PACKED_SIZE_BYTES];
+        // Every field is of a fixed size, so writing to an array of the packed size cannot fail.
+        (&mut bytes[..]).write_bytes(packet).expect("packet is larger than its packed size");
+        bytes
     }
 }
 
@@ -761,8 +1512,111 @@ impl fmt::Display for PrimarySource {
     }
 }
 
+/// Displays the value in decimal seconds, e.g. `1.5`.
+///
+/// The shortest decimal fraction that parses back into the same fixed-point value is used.
+impl fmt::Display for ShortFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_fixed_point(f, self.seconds as u64, self.fraction as u64, 16)
+    }
+}
+
+/// Displays the value in decimal seconds since the prime epoch, e.g. `3619455089.5`.
+///
+/// The shortest decimal fraction that parses back into the same fixed-point value is used.
+impl fmt::Display for TimestampFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_fixed_point(f, self.seconds as u64, self.fraction as u64, 32)
+    }
+}
+
+impl fmt::Display for ParseFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl error::Error for ParseFormatError {}
+
+// FromStr implementations.
+
+/// Parses decimal seconds, e.g. `1.5`.
+///
+/// The fraction is rounded to the nearest representable value (1/65536 s), with ties rounding
+/// up. A fraction that rounds up to a whole second carries into the seconds.
+impl str::FromStr for ShortFormat {
+    type Err = ParseFormatError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (seconds, fraction) = parse_fixed_point(s, u16::MAX as u64, 16)?;
+        Ok(ShortFormat { seconds: seconds as u16, fraction: fraction as u16 })
+    }
+}
+
+/// Parses decimal seconds since the prime epoch, e.g. `3619455089.5`.
+///
+/// The fraction is rounded to the nearest representable value (2^-32 s), with ties rounding up.
+/// A fraction that rounds up to a whole second carries into the seconds.
+impl str::FromStr for TimestampFormat {
+    type Err = ParseFormatError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (seconds, fraction) = parse_fixed_point(s, u32::MAX as u64, 32)?;
+        Ok(TimestampFormat { seconds: seconds as u32, fraction: fraction as u32 })
+    }
+}
+
 // Utility functions.
 
+// Decimal digits beyond this are ignored when parsing, keeping the arithmetic within a u128.
+const MAX_PARSED_FRACTION_DIGITS: u32 = 28;
+
+// Round a decimal fraction of `digits` digits to the nearest `bits`-bit binary fraction.
+fn decimal_to_binary_fraction(decimal: u128, digits: u32, bits: u32) -> u128 {
+    let scale = 10u128.pow(digits);
+    ((decimal << bits) + scale / 2) / scale
+}
+
+fn fmt_fixed_point(f: &mut fmt::Formatter, seconds: u64, fraction: u64, bits: u32) -> fmt::Result {
+    let fraction = fraction as u128;
+    let half = 1u128 << (bits - 1);
+    // At this many digits every binary fraction has a distinct decimal representation.
+    let max_digits = if bits <= 16 { 5 } else { 10 };
+    for digits in 1..=max_digits {
+        let scale = 10u128.pow(digits);
+        let decimal = (fraction * scale + half) >> bits;
+        if decimal < scale && decimal_to_binary_fraction(decimal, digits, bits) == fraction {
+            return write!(f, "{}.{:0width$}", seconds, decimal, width = digits as usize);
+        }
+    }
+    unreachable!("binary fraction has no decimal representation")
+}
+
+fn parse_fixed_point(s: &str, max_seconds: u64, bits: u32) -> Result<(u64, u64), ParseFormatError> {
+    let invalid = |msg| ParseFormatError { msg };
+    let (int_part, frac_part) = match s.find('.') {
+        Some(dot) => (&s[..dot], &s[dot + 1..]),
+        None => (s, ""),
+    };
+    let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if int_part.is_empty() || !is_digits(int_part) || !is_digits(frac_part) {
+        return Err(invalid("invalid decimal seconds"));
+    }
+    let mut seconds: u64 = match int_part.parse() {
+        Ok(seconds) if seconds <= max_seconds => seconds,
+        _ => return Err(invalid("seconds out of range")),
+    };
+    let frac_part = &frac_part[..frac_part.len().min(MAX_PARSED_FRACTION_DIGITS as usize)];
+    let decimal = frac_part.parse::<u128>().unwrap_or(0);
+    let mut fraction = decimal_to_binary_fraction(decimal, frac_part.len() as u32, bits);
+    if fraction >> bits != 0 {
+        fraction = 0;
+        seconds += 1;
+        if seconds > max_seconds {
+            return Err(invalid("seconds out of range"));
+        }
+    }
+    Ok((seconds, fraction as u64))
+}
+
 fn be_u32_to_bytes(u: u32) -> [u8; 4] {
     [
         (u >> 24 & 0xff) as u8,
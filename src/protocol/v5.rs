@@ -0,0 +1,194 @@
+//! The NTPv5 packet header, as described by the IETF draft draft-ietf-ntp-ntpv5.
+//!
+//! The draft is not final and its layout may still change, so these types are only available
+//! with the experimental `ntpv5` feature.
+
+use super::{
+    ConstPackedSizeBytes, LeapIndicator, LogSeconds, Mode, PacketByte1, ReadBytes, ReadFromBytes,
+    Stratum, TimestampFormat, Version, WriteBytes, WriteToBytes,
+};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, BE};
+use conv::TryFrom;
+use std::io;
+
+custom_derive! {
+    /// An 8-bit integer identifying the timescale of the timestamps in an NTPv5 packet.
+    ///
+    /// As the only constructors are via associated constants, it should be impossible to create an
+    /// invalid `Timescale`.
+    #[repr(u8)]
+    #[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq, TryFrom(u8))]
+    pub enum Timescale {
+        /// Coordinated Universal Time.
+        #[default]
+        Utc = 0,
+        /// International Atomic Time.
+        Tai = 1,
+        /// Universal Time 1.
+        Ut1 = 2,
+        /// UTC with leap seconds smeared over the surrounding hours.
+        LeapSmearedUtc = 3,
+    }
+}
+
+/// **NTPv5 Packet Header** - The 48 byte header of the NTPv5 draft.
+///
+/// In place of the reference identifier and the origin timestamp of NTPv4, a request carries a
+/// random client cookie that the server echoes, and the reference timestamp is replaced by a
+/// server cookie distinguishing the instances of a server behind one address. The era resolves
+/// the ambiguity of the 32-bit timestamp seconds.
+///
+/// ```ignore
+///  0                   1                   2                   3
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |LI | VN  |Mode |    Stratum    |     Poll      |   Precision   |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |   Timescale   |      Era      |             Flags             |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                          Root Delay                           |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                        Root Dispersion                        |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// +                         Server Cookie                         +
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// +                         Client Cookie                         +
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// +                       Receive Timestamp                       +
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// +                       Transmit Timestamp                      +
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct PacketV5 {
+    pub leap_indicator: LeapIndicator,
+    pub version: Version,
+    pub mode: Mode,
+    pub stratum: Stratum,
+    /// The maximum interval between successive messages, in log2 seconds.
+    pub poll: LogSeconds,
+    /// The precision of the system clock, in log2 seconds.
+    pub precision: LogSeconds,
+    pub timescale: Timescale,
+    /// The number of the NTP era in which the transmit timestamp falls.
+    pub era: u8,
+    /// A combination of the `FLAG_*` bits.
+    pub flags: u16,
+    /// Total round-trip delay to the reference clock, in the unsigned 4.28 fixed-point format.
+    pub root_delay: u32,
+    /// Total dispersion to the reference clock, in the unsigned 4.28 fixed-point format.
+    pub root_dispersion: u32,
+    /// An identifier of the server instance that sent the packet, zero in requests.
+    pub server_cookie: u64,
+    /// A random value chosen by the client for each request and echoed by the server.
+    pub client_cookie: u64,
+    /// Time at the server when the request arrived from the client.
+    pub receive_timestamp: TimestampFormat,
+    /// Time at the server when the response left for the client.
+    pub transmit_timestamp: TimestampFormat,
+}
+
+impl PacketV5 {
+    /// The server's leap second status is unknown.
+    pub const FLAG_UNKNOWN_LEAP: u16 = 0x1;
+    /// The response is in interleaved mode.
+    pub const FLAG_INTERLEAVED: u16 = 0x2;
+    /// The server could not authenticate the request.
+    pub const FLAG_AUTH_NAK: u16 = 0x4;
+
+    /// A client request carrying the given cookie, with all other fields zero.
+    pub fn request(client_cookie: u64) -> Self {
+        PacketV5 {
+            leap_indicator: LeapIndicator::NoWarning,
+            version: Version::V5,
+            mode: Mode::Client,
+            stratum: Stratum::UNSPECIFIED,
+            poll: LogSeconds(0),
+            precision: LogSeconds(0),
+            timescale: Timescale::Utc,
+            era: 0,
+            flags: 0,
+            root_delay: 0,
+            root_dispersion: 0,
+            server_cookie: 0,
+            client_cookie,
+            receive_timestamp: TimestampFormat::default(),
+            transmit_timestamp: TimestampFormat::default(),
+        }
+    }
+
+    /// The root delay in fractional seconds.
+    pub fn root_delay_secs(&self) -> f64 {
+        self.root_delay as f64 / (1u64 << 28) as f64
+    }
+
+    /// The root dispersion in fractional seconds.
+    pub fn root_dispersion_secs(&self) -> f64 {
+        self.root_dispersion as f64 / (1u64 << 28) as f64
+    }
+}
+
+impl ConstPackedSizeBytes for PacketV5 {
+    const PACKED_SIZE_BYTES: usize = PacketByte1::PACKED_SIZE_BYTES
+        + Stratum::PACKED_SIZE_BYTES
+        + LogSeconds::PACKED_SIZE_BYTES * 2
+        + 2
+        + 2
+        + 4 * 2
+        + 8 * 2
+        + TimestampFormat::PACKED_SIZE_BYTES * 2;
+}
+
+impl WriteToBytes for PacketV5 {
+    fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_bytes((self.leap_indicator, self.version, self.mode))?;
+        writer.write_bytes(self.stratum)?;
+        writer.write_bytes(self.poll)?;
+        writer.write_bytes(self.precision)?;
+        writer.write_u8(self.timescale as u8)?;
+        writer.write_u8(self.era)?;
+        writer.write_u16::<BE>(self.flags)?;
+        writer.write_u32::<BE>(self.root_delay)?;
+        writer.write_u32::<BE>(self.root_dispersion)?;
+        writer.write_u64::<BE>(self.server_cookie)?;
+        writer.write_u64::<BE>(self.client_cookie)?;
+        writer.write_bytes(self.receive_timestamp)?;
+        writer.write_bytes(self.transmit_timestamp)?;
+        Ok(())
+    }
+}
+
+impl ReadFromBytes for PacketV5 {
+    fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
+        let (leap_indicator, version, mode) = reader.read_bytes()?;
+        let stratum = reader.read_bytes()?;
+        let poll = reader.read_bytes()?;
+        let precision = reader.read_bytes()?;
+        let timescale_u8 = reader.read_u8()?;
+        let timescale = match Timescale::try_from(timescale_u8) {
+            Ok(timescale) => timescale,
+            Err(_) => {
+                let err_msg = format!("unknown timescale {}", timescale_u8);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+            }
+        };
+        Ok(PacketV5 {
+            leap_indicator,
+            version,
+            mode,
+            stratum,
+            poll,
+            precision,
+            timescale,
+            era: reader.read_u8()?,
+            flags: reader.read_u16::<BE>()?,
+            root_delay: reader.read_u32::<BE>()?,
+            root_dispersion: reader.read_u32::<BE>()?,
+            server_cookie: reader.read_u64::<BE>()?,
+            client_cookie: reader.read_u64::<BE>()?,
+            receive_timestamp: reader.read_bytes()?,
+            transmit_timestamp: reader.read_bytes()?,
+        })
+    }
+}
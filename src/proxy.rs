@@ -0,0 +1,230 @@
+//! Tunnelling requests through a SOCKS5 proxy, as described in RFC 1928.
+//!
+//! A TCP connection is made to the proxy for each request, on which the client authenticates and
+//! asks the proxy to relay UDP with the UDP ASSOCIATE command. The request is then sent to the
+//! relay address the proxy replies with, preceded by a header naming the server, and the response
+//! arrives from the relay preceded by a header naming the server it came from. The proxy keeps
+//! the relay open only as long as the TCP connection, which is held until the response arrives.
+//! The association is set up before the request is timestamped, so that the round trips it
+//! takes are excluded from the exchange.
+
+use crate::config::Socks5ProxyConfig;
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+#[cfg(feature = "async-std")]
+use async_std::io::{ReadExt, WriteExt};
+#[cfg(feature = "async-std")]
+use async_std::net::{TcpStream, UdpSocket};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+#[cfg(feature = "tokio")]
+use tokio::net::{TcpStream, UdpSocket};
+
+const VERSION: u8 = 5;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+const USERNAME_PASSWORD_VERSION: u8 = 1;
+const COMMAND_UDP_ASSOCIATE: u8 = 3;
+const REPLY_SUCCEEDED: u8 = 0;
+const ADDRESS_IPV4: u8 = 1;
+const ADDRESS_DOMAIN_NAME: u8 = 3;
+const ADDRESS_IPV6: u8 = 4;
+
+// A UDP association with the proxy, through which requests are relayed to a server.
+pub(crate) struct Relay {
+    // The connection the proxy keeps the association open for.
+    _control: TcpStream,
+    // A socket connected to the relay address.
+    sock: UdpSocket,
+    server: SocketAddr,
+}
+
+// Set up a UDP association with the proxy to relay requests to `server`, marked with `dscp`.
+pub(crate) async fn associate(
+    proxy: &Socks5ProxyConfig,
+    server: SocketAddr,
+    dscp: Option<u8>,
+) -> io::Result<Relay> {
+    let mut control = TcpStream::connect(proxy.addr).await?;
+    control.set_nodelay(true)?;
+    authenticate(&mut control, proxy).await?;
+
+    // The address the datagrams will be sent from is not known until the socket is bound, and
+    // the proxy accepts them from any address when told the unspecified address.
+    let unspecified = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0);
+    let mut associate = vec![VERSION, COMMAND_UDP_ASSOCIATE, 0];
+    write_addr(&mut associate, unspecified);
+    control.write_all(&associate).await?;
+    let mut reply = [0u8; 3];
+    control.read_exact(&mut reply).await?;
+    if reply[0] != VERSION {
+        return Err(invalid_data("proxy replied with an unknown socks version"));
+    }
+    if reply[1] != REPLY_SUCCEEDED {
+        let err_msg = format!("proxy refused to relay udp with reply code {}", reply[1]);
+        return Err(io::Error::new(io::ErrorKind::ConnectionRefused, err_msg));
+    }
+    let mut relay = read_addr(&mut control).await?;
+    // A relay on the unspecified address is reached at the address of the proxy.
+    if relay.ip().is_unspecified() {
+        relay.set_ip(proxy.addr.ip());
+    }
+    debug!("relaying through {} via {}", proxy.addr, relay);
+
+    let local_addr = if relay.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let sock = UdpSocket::bind(local_addr).await?;
+    if let Some(dscp) = dscp {
+        crate::client::set_dscp(&sock, relay.is_ipv6(), dscp)?;
+    }
+    sock.connect(relay).await?;
+    Ok(Relay { _control: control, sock, server })
+}
+
+impl Relay {
+    // Relay the request held in `buf` to the server and receive its response into `buf`,
+    // returning the length of the response.
+    pub(crate) async fn send_and_receive(
+        &self,
+        buf: &mut Vec<u8>,
+        recv_buffer_size: usize,
+    ) -> io::Result<usize> {
+        let (sock, server) = (&self.sock, self.server);
+        let mut datagram = vec![0, 0, 0];
+        write_addr(&mut datagram, server);
+        datagram.extend_from_slice(buf);
+        sock.send(&datagram).await?;
+        debug!("sent: {}", buf.len());
+
+        // The response is preceded by a header of at most 22 bytes, for an IPv6 address, and is
+        // followed by a spare byte revealing whether it was truncated to fit the buffer.
+        let mut datagram = vec![0u8; 22 + recv_buffer_size + 1];
+        let len = sock.recv(&mut datagram).await?;
+        let (from, header_len) = parse_udp_header(&datagram[..len])?;
+        if from != server {
+            let err_msg =
+                format!("proxy relayed a response from {} rather than {}", from, server);
+            return Err(invalid_data(&err_msg));
+        }
+        let res = (len - header_len).min(recv_buffer_size + 1);
+        buf.clear();
+        buf.extend_from_slice(&datagram[header_len..header_len + res]);
+        Ok(res)
+    }
+}
+
+// Negotiate an authentication method with the proxy, and authenticate if it requires it.
+async fn authenticate(control: &mut TcpStream, proxy: &Socks5ProxyConfig) -> io::Result<()> {
+    let greeting: &[u8] = match proxy.auth {
+        Some(_) => &[VERSION, 2, METHOD_NO_AUTH, METHOD_USERNAME_PASSWORD],
+        None => &[VERSION, 1, METHOD_NO_AUTH],
+    };
+    control.write_all(greeting).await?;
+    let mut choice = [0u8; 2];
+    control.read_exact(&mut choice).await?;
+    if choice[0] != VERSION {
+        return Err(invalid_data("proxy replied with an unknown socks version"));
+    }
+    match (choice[1], &proxy.auth) {
+        (METHOD_NO_AUTH, _) => Ok(()),
+        (METHOD_USERNAME_PASSWORD, Some(auth)) => {
+            let (username, password) = (&auth.username, &auth.password);
+            if username.len() > 255 || password.len() > 255 {
+                let err_msg = "socks5 username and password must be at most 255 bytes";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg));
+            }
+            let mut request = vec![USERNAME_PASSWORD_VERSION, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            control.write_all(&request).await?;
+            let mut status = [0u8; 2];
+            control.read_exact(&mut status).await?;
+            if status[1] != 0 {
+                let err_msg = "proxy rejected the socks5 username and password";
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, err_msg));
+            }
+            Ok(())
+        }
+        (METHOD_NONE_ACCEPTABLE, _) => {
+            let err_msg = "proxy accepts none of the offered authentication methods";
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, err_msg))
+        }
+        (method, _) => {
+            let err_msg = format!("proxy chose unoffered authentication method {}", method);
+            Err(invalid_data(&err_msg))
+        }
+    }
+}
+
+// Append the address in its SOCKS5 encoding: a type, the address and a big-endian port.
+fn write_addr(bytes: &mut Vec<u8>, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            bytes.push(ADDRESS_IPV4);
+            bytes.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            bytes.push(ADDRESS_IPV6);
+            bytes.extend_from_slice(&ip.octets());
+        }
+    }
+    bytes.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+// Read an address in its SOCKS5 encoding from the control connection.
+async fn read_addr(control: &mut TcpStream) -> io::Result<SocketAddr> {
+    let mut kind = [0u8; 1];
+    control.read_exact(&mut kind).await?;
+    let mut bytes = vec![kind[0]];
+    let len = match kind[0] {
+        ADDRESS_IPV4 => 4 + 2,
+        ADDRESS_IPV6 => 16 + 2,
+        _ => return Err(invalid_data("proxy replied with an unsupported address type")),
+    };
+    bytes.resize(1 + len, 0);
+    control.read_exact(&mut bytes[1..]).await?;
+    parse_addr(&bytes).map(|(addr, _)| addr)
+}
+
+// Parse the header preceding a relayed datagram, returning the address the datagram came from
+// and the length of the header.
+fn parse_udp_header(datagram: &[u8]) -> io::Result<(SocketAddr, usize)> {
+    if datagram.len() < 3 {
+        return Err(invalid_data("relayed datagram is shorter than its header"));
+    }
+    if datagram[2] != 0 {
+        return Err(invalid_data("proxy relayed a fragment of a datagram"));
+    }
+    parse_addr(&datagram[3..]).map(|(addr, len)| (addr, 3 + len))
+}
+
+// Parse an address in its SOCKS5 encoding, returning it and the length of its encoding.
+fn parse_addr(bytes: &[u8]) -> io::Result<(SocketAddr, usize)> {
+    let short = || invalid_data("socks5 address is truncated");
+    let (ip, len): (IpAddr, usize) = match bytes.first() {
+        Some(&ADDRESS_IPV4) if bytes.len() >= 1 + 4 + 2 => {
+            let mut octets = [0u8; 4];
+            octets.copy_from_slice(&bytes[1..5]);
+            (Ipv4Addr::from(octets).into(), 4)
+        }
+        Some(&ADDRESS_IPV6) if bytes.len() >= 1 + 16 + 2 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&bytes[1..17]);
+            (Ipv6Addr::from(octets).into(), 16)
+        }
+        Some(&ADDRESS_IPV4) | Some(&ADDRESS_IPV6) | None => return Err(short()),
+        Some(&ADDRESS_DOMAIN_NAME) => {
+            return Err(invalid_data("proxy named a server by domain rather than address"));
+        }
+        Some(_) => return Err(invalid_data("unknown socks5 address type")),
+    };
+    let port = u16::from_be_bytes([bytes[1 + len], bytes[2 + len]]);
+    Ok((SocketAddr::new(ip, port), 1 + len + 2))
+}
+
+fn invalid_data(err_msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err_msg.to_string())
+}
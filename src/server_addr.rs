@@ -0,0 +1,75 @@
+//! Server addresses that may be resolved by the system resolver or by a client's own.
+
+#[cfg(feature = "async-std")]
+use async_std::net::ToSocketAddrs;
+#[cfg(feature = "tokio")]
+use tokio::net::ToSocketAddrs;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+/// The address of a server, in any form that `ToSocketAddrs` accepts, given to the methods of
+/// `NtpClient` that send requests.
+///
+/// A server given by name, e.g. `"pool.ntp.org:123"` or `("pool.ntp.org", 123)`, is resolved by
+/// the client's `resolver` should it have one, and by the system resolver otherwise. Socket
+/// addresses and literal IP addresses need no resolving and are used as given.
+pub trait ServerAddr: ToSocketAddrs {
+    /// The name of the server written as `host:port`, or `None` if the address is literal.
+    fn server_name(&self) -> Option<String>;
+}
+
+impl ServerAddr for str {
+    fn server_name(&self) -> Option<String> {
+        match self.parse::<SocketAddr>() {
+            Ok(_) => None,
+            Err(_) => Some(self.to_string()),
+        }
+    }
+}
+
+impl ServerAddr for String {
+    fn server_name(&self) -> Option<String> {
+        self.as_str().server_name()
+    }
+}
+
+impl ServerAddr for (&str, u16) {
+    fn server_name(&self) -> Option<String> {
+        host_port_name(self.0, self.1)
+    }
+}
+
+impl<T: ServerAddr + ?Sized> ServerAddr for &T {
+    fn server_name(&self) -> Option<String> {
+        (**self).server_name()
+    }
+}
+
+macro_rules! literal_server_addr {
+    ($($addr:ty),*) => {
+        $(
+            impl ServerAddr for $addr {
+                fn server_name(&self) -> Option<String> {
+                    None
+                }
+            }
+        )*
+    };
+}
+
+literal_server_addr!(
+    SocketAddr,
+    SocketAddrV4,
+    SocketAddrV6,
+    (IpAddr, u16),
+    (Ipv4Addr, u16),
+    (Ipv6Addr, u16)
+);
+
+// The name of a server given as a host and port, or `None` if the host is a literal IP address.
+fn host_port_name(host: &str, port: u16) -> Option<String> {
+    match host.parse::<IpAddr>() {
+        Ok(_) => None,
+        Err(_) => Some(format!("{}:{}", host, port)),
+    }
+}
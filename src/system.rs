@@ -0,0 +1,69 @@
+//! The synchronization status of the local clock, as maintained by the Linux kernel.
+//!
+//! A local NTP daemon (ntpd, chrony, systemd-timesyncd) disciplines the clock through the kernel,
+//! which records whether the clock is synchronized and the daemon's estimate of its error. Reading
+//! the status complements querying remote servers when monitoring a host, and requires no
+//! privileges.
+
+use crate::protocol::LeapDirection;
+use crate::SignedDuration;
+
+use std::io;
+use std::time::Duration;
+
+/// The state of the kernel clock, as returned by `adjtimex`.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ClockState {
+    /// The clock is synchronized and no leap second is pending.
+    Ok,
+    /// A leap second is pending, or in progress.
+    Leap(LeapDirection),
+    /// A leap second has just occurred.
+    LeapOccurred,
+    /// The clock is not synchronized.
+    Error,
+}
+
+/// The synchronization status of the local clock.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SyncStatus {
+    /// Whether the clock is disciplined by a synchronization daemon.
+    pub synchronized: bool,
+    /// The state of the clock at the time of reading.
+    pub state: ClockState,
+    /// The daemon's estimate of the error of the clock.
+    pub estimated_error: Duration,
+    /// The daemon's bound on the error of the clock, which grows while it is not updated.
+    pub max_error: Duration,
+    /// The offset the kernel is still slewing the clock by.
+    pub offset: SignedDuration,
+}
+
+/// Read the synchronization status of the local clock from the kernel using `adjtimex`.
+pub fn local_sync_status() -> io::Result<SyncStatus> {
+    // A `modes` of zero only reads the status, leaving the clock unchanged.
+    let mut timex: libc::timex = unsafe { std::mem::zeroed() };
+    let state = unsafe { libc::adjtimex(&mut timex) };
+    if state < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let state = match state {
+        libc::TIME_OK => ClockState::Ok,
+        libc::TIME_INS | libc::TIME_OOP => ClockState::Leap(LeapDirection::Insert),
+        libc::TIME_DEL => ClockState::Leap(LeapDirection::Delete),
+        libc::TIME_WAIT => ClockState::LeapOccurred,
+        _ => ClockState::Error,
+    };
+    let offset_nanos = if timex.status & libc::STA_NANO != 0 {
+        timex.offset as i128
+    } else {
+        timex.offset as i128 * 1000
+    };
+    Ok(SyncStatus {
+        synchronized: state != ClockState::Error && timex.status & libc::STA_UNSYNC == 0,
+        state,
+        estimated_error: Duration::from_micros(timex.esterror.max(0) as u64),
+        max_error: Duration::from_micros(timex.maxerror.max(0) as u64),
+        offset: SignedDuration::from_nanos(offset_nanos),
+    })
+}
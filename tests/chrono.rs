@@ -0,0 +1,90 @@
+#![cfg(feature = "chrono")]
+
+extern crate chrono;
+extern crate nippy;
+
+use chrono::{DateTime, TimeZone, Utc};
+use nippy::protocol::{
+    LeapIndicator, LogSeconds, Mode, Packet, ReferenceIdentifier, ShortFormat, Stratum,
+    TimestampFormat, Version,
+};
+use nippy::{Error, Instant, NtpMeasurement};
+use std::time::Duration;
+
+#[test]
+fn timestamp_to_date_time() {
+    let unix_epoch = TimestampFormat { seconds: 0x83AA_7E80, fraction: 0 };
+    assert_eq!(DateTime::<Utc>::from(unix_epoch), Utc.timestamp_opt(0, 0).unwrap());
+
+    let half_second = TimestampFormat { seconds: 0x83AA_7E80, fraction: 0x8000_0000 };
+    assert_eq!(DateTime::<Utc>::from(half_second), Utc.timestamp_opt(0, 500_000_000).unwrap());
+
+    // The largest fraction rounds up to the next second.
+    let almost = TimestampFormat { seconds: 0x83AA_7E80, fraction: u32::MAX };
+    assert_eq!(DateTime::<Utc>::from(almost), Utc.timestamp_opt(1, 0).unwrap());
+
+    // Timestamps with the most significant bit clear fall in era 1.
+    let era_1 = TimestampFormat { seconds: 0, fraction: 0 };
+    let expected = Utc.with_ymd_and_hms(2036, 2, 7, 6, 28, 16).unwrap();
+    assert_eq!(DateTime::<Utc>::from(era_1), expected);
+    let era_0_start = TimestampFormat { seconds: 0x8000_0000, fraction: 0 };
+    let expected = Utc.with_ymd_and_hms(1968, 1, 20, 3, 14, 8).unwrap();
+    assert_eq!(DateTime::<Utc>::from(era_0_start), expected);
+}
+
+#[test]
+fn date_time_to_timestamp() {
+    let unix_epoch = Utc.timestamp_opt(0, 0).unwrap();
+    let expected = TimestampFormat { seconds: 0x83AA_7E80, fraction: 0 };
+    assert_eq!(TimestampFormat::from_date_time_checked(unix_epoch).unwrap(), expected);
+
+    // Dates before the unix epoch carry a positive fraction from the preceding second.
+    let before_epoch = Utc.timestamp_opt(-1, 750_000_000).unwrap();
+    let expected = TimestampFormat { seconds: 0x83AA_7E7F, fraction: 0xC000_0000 };
+    assert_eq!(TimestampFormat::from_date_time_checked(before_epoch).unwrap(), expected);
+
+    let before_prime_epoch = Utc.with_ymd_and_hms(1899, 12, 31, 23, 59, 59).unwrap();
+    match TimestampFormat::from_date_time_checked(before_prime_epoch) {
+        Err(Error::BeforePrimeEpoch(_)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // Dates between 1968 and 2104 round trip to the nanosecond, across the era boundary.
+    for &(secs, nanos) in &[(-50_000_000, 1), (1_600_000_000, 123_456_789), (4_000_000_000, 7)] {
+        let date_time = Utc.timestamp_opt(secs, nanos).unwrap();
+        let timestamp = TimestampFormat::from_date_time_checked(date_time).unwrap();
+        assert_eq!(DateTime::<Utc>::from(timestamp), date_time);
+    }
+}
+
+#[test]
+fn measurement_offset_as_chrono_duration() {
+    // A server 15ms behind the local clock, with no delay.
+    let sent = Instant::new(1_600_000_000, 15_000_000);
+    let server_time = TimestampFormat::from_instant_checked(Instant::new(1_600_000_000, 0))
+        .unwrap();
+    let packet = Packet {
+        leap_indicator: LeapIndicator::NoWarning,
+        version: Version::V4,
+        mode: Mode::Server,
+        stratum: Stratum(2),
+        poll: LogSeconds(0),
+        precision: LogSeconds(-20),
+        root_delay: ShortFormat::default(),
+        root_dispersion: ShortFormat::default(),
+        reference_id: ReferenceIdentifier::SecondaryOrClient([127, 0, 0, 1]),
+        reference_timestamp: server_time,
+        origin_timestamp: TimestampFormat::from_instant_checked(sent).unwrap(),
+        receive_timestamp: server_time,
+        transmit_timestamp: server_time,
+    };
+    let measurement = NtpMeasurement {
+        packet,
+        sent,
+        received: sent,
+        round_trip: Duration::from_secs(0),
+        interleaved: false,
+        delay_asymmetry: 0.0,
+    };
+    assert_eq!(measurement.offset_chrono(), chrono::Duration::milliseconds(-15));
+}
@@ -0,0 +1,1454 @@
+#![cfg(feature = "async-std")]
+
+#[cfg(target_os = "linux")]
+extern crate libc;
+extern crate nippy;
+extern crate rand;
+
+mod common;
+
+use async_std::task;
+use common::MockServer;
+use nippy::protocol::{
+    ConstPackedSizeBytes, KissOfDeath, LeapIndicator, LogSeconds, Packet, PrimarySource,
+    RawExtensionField, ReadBytes, ReferenceIdentifier, Stratum, TimestampFormat,
+    TimestampSelector, Version, WriteBytes, UNIQUE_IDENTIFIER_FIELD_TYPE,
+};
+use nippy::{
+    Association, Clock, Error, Instant, NtpClient, NtpMeasurement, SelectionPolicy,
+    NtpClientConfig, SignedDuration, Verdict,
+};
+use rand::rngs::mock::StepRng;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[test]
+fn max_reference_age_rejects_stale_server() {
+    let server = MockServer::start(|origin| {
+        let mut response = common::reply(origin);
+        let hour_ago = Instant::new(Instant::now().secs() - 3600, 0);
+        response.reference_timestamp = TimestampFormat::from_instant_checked(hour_ago).unwrap();
+        response
+    });
+
+    let client = NtpClient::new().max_reference_age(Duration::from_secs(600));
+    match task::block_on(client.request(server.addr())) {
+        Err(Error::StaleReference(age)) => assert!(age >= Duration::from_secs(3600)),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let client = NtpClient::new().max_reference_age(Duration::from_secs(7200));
+    assert!(task::block_on(client.request(server.addr())).is_ok());
+}
+
+#[test]
+fn interleaved_mode_uses_previous_transmit_timestamp() {
+    // A server whose precise (hardware) transmit timestamps are only known after sending, and
+    // are therefore returned in the following interleaved response.
+    let mut last_receive = TimestampFormat::default();
+    let mut last_transmit = TimestampFormat::default();
+    let server = MockServer::start_raw(move |request| {
+        let origin: TimestampFormat = (&request[24..32]).read_bytes().unwrap();
+        let receive: TimestampFormat = (&request[32..40]).read_bytes().unwrap();
+        let transmit: TimestampFormat = (&request[40..48]).read_bytes().unwrap();
+        let mut response = common::reply(transmit);
+        if origin != TimestampFormat::default() && origin == last_receive {
+            response.origin_timestamp = receive;
+            response.transmit_timestamp = last_transmit;
+        }
+        last_receive = response.receive_timestamp;
+        last_transmit = TimestampFormat { fraction: last_receive.fraction ^ 1, ..last_receive };
+        Some(common::to_bytes(&response))
+    });
+
+    let client = NtpClient::new().interleaved(true);
+    let first = task::block_on(client.request(server.addr())).unwrap();
+    assert!(!first.interleaved);
+    let second = task::block_on(client.request(server.addr())).unwrap();
+    assert!(second.interleaved);
+
+    // The interleaved measurement describes the first exchange with its precise transmit time.
+    let precise = TimestampFormat {
+        fraction: first.packet.receive_timestamp.fraction ^ 1,
+        ..first.packet.receive_timestamp
+    };
+    assert_eq!(second.packet.receive_timestamp, first.packet.receive_timestamp);
+    assert_eq!(second.packet.transmit_timestamp, precise);
+    assert_eq!(second.sent.secs(), first.sent.secs());
+    assert_eq!(second.sent.subsec_nanos(), first.sent.subsec_nanos());
+    assert_eq!(second.received.subsec_nanos(), first.received.subsec_nanos());
+
+    // Without interleaved mode the same server always responds in basic mode.
+    let client = NtpClient::new();
+    task::block_on(client.request(server.addr())).unwrap();
+    assert!(!task::block_on(client.request(server.addr())).unwrap().interleaved);
+}
+
+#[test]
+fn interleaved_mode_ignores_rejected_exchanges() {
+    // The first response is unsynchronized and rejected, so the second request must not refer
+    // to it in its origin and receive timestamps.
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let seen = requests.clone();
+    let server = MockServer::start_raw(move |request| {
+        let mut seen = seen.lock().unwrap();
+        let mut response = common::reply(common::transmit_timestamp(request));
+        if seen.is_empty() {
+            response.stratum = Stratum::UNSPECIFIED;
+        }
+        seen.push(request.to_vec());
+        Some(common::to_bytes(&response))
+    });
+
+    let client = NtpClient::new().interleaved(true);
+    match task::block_on(client.request(server.addr())) {
+        Err(Error::Unsynchronized) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(!task::block_on(client.request(server.addr())).unwrap().interleaved);
+    let requests = requests.lock().unwrap();
+    let origin: TimestampFormat = (&requests[1][24..32]).read_bytes().unwrap();
+    let receive: TimestampFormat = (&requests[1][32..40]).read_bytes().unwrap();
+    assert_eq!(origin, TimestampFormat::default());
+    assert_eq!(receive, TimestampFormat::default());
+}
+
+#[test]
+fn resolver_replaces_system_resolution() {
+    let server = MockServer::start(common::reply);
+    let addr = server.addr();
+    let client = NtpClient::new().resolver(move |host| {
+        assert_eq!(host, "ntp.invalid:123");
+        async move { vec![addr] }
+    });
+    assert!(task::block_on(client.request("ntp.invalid:123")).is_ok());
+    assert!(task::block_on(client.request(("ntp.invalid", 123))).is_ok());
+    assert!(task::block_on(client.burst("ntp.invalid:123", 2, Duration::from_millis(5))).is_ok());
+    assert_eq!(client.association(addr).unwrap().reach(), 0b1111);
+
+    // Literal addresses are used as given, without consulting the resolver.
+    assert!(task::block_on(client.request(addr.to_string())).is_ok());
+    assert!(task::block_on(client.request(("127.0.0.1", addr.port()))).is_ok());
+    assert!(task::block_on(client.request((addr.ip(), addr.port()))).is_ok());
+}
+
+#[test]
+fn fallback_addrs_are_queried_when_resolution_fails() {
+    let unresponsive = MockServer::start_raw(|_| None);
+    let server = MockServer::start(common::reply);
+    let client = NtpClient::new()
+        .timeout(Duration::from_millis(200))
+        .resolver(|_| async { Vec::new() })
+        .fallback_addrs(vec![unresponsive.addr(), server.addr()]);
+    assert!(task::block_on(client.request("ntp.example:123")).is_ok());
+    assert_eq!(client.association(unresponsive.addr()).unwrap().reach(), 0);
+    assert_eq!(client.association(server.addr()).unwrap().reach(), 1);
+
+    // Every method taking a server name falls back.
+    let burst = client.burst("ntp.example:123", 2, Duration::from_millis(5));
+    assert!(task::block_on(burst).is_ok());
+    assert!(task::block_on(client.fast_sync("ntp.example:123")).is_ok());
+    assert!(task::block_on(client.sample_until_stable("ntp.example:123", 1, 1)).is_ok());
+    let report = task::block_on(client.compare("ntp.example:123", server.addr())).unwrap();
+    assert_eq!(report.a.0, server.addr());
+
+    // Without fallbacks the resolution error is returned.
+    let client = NtpClient::new().resolver(|_| async { Vec::new() });
+    match task::block_on(client.burst("ntp.example:123", 2, Duration::from_millis(5))) {
+        Err(Error::Resolution(host)) => assert_eq!(host, "ntp.example:123"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn unexpected_extensions_policy() {
+    // A response padded with a 16 byte extension field the client never asked for.
+    let server = MockServer::start_patched(|_, response| {
+        response.extend_from_slice(&[0x02, 0x04, 0x00, 0x10]);
+        response.extend_from_slice(&[0u8; 12]);
+    });
+
+    let client = NtpClient::new();
+    match task::block_on(client.request(server.addr())) {
+        Err(Error::InvalidPacket(_)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let client = NtpClient::new().allow_unexpected_extensions(true);
+    assert!(task::block_on(client.request(server.addr())).is_ok());
+}
+
+#[test]
+fn require_primary_rejects_secondary_server() {
+    let secondary = MockServer::start(common::reply);
+    let primary = MockServer::start(|origin| {
+        let mut response = common::reply(origin);
+        response.stratum = Stratum::PRIMARY;
+        response.reference_id = ReferenceIdentifier::PrimarySource(PrimarySource::Gps);
+        response
+    });
+
+    let client = NtpClient::new().require_primary(true);
+    match task::block_on(client.request(secondary.addr())) {
+        Err(Error::InvalidPacket(reason)) => assert!(reason.contains("stratum 2")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(task::block_on(client.request(primary.addr())).is_ok());
+
+    let client = NtpClient::new().require_reference(PrimarySource::Gps);
+    assert!(task::block_on(client.request(primary.addr())).is_ok());
+    let client = NtpClient::new().require_reference(PrimarySource::Pps);
+    match task::block_on(client.request(primary.addr())) {
+        Err(Error::InvalidPacket(_)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn make_reply_produces_valid_response() {
+    let server = MockServer::start_raw(|request| {
+        let request: Packet = (&request[..]).read_bytes().unwrap();
+        let reference_id = ReferenceIdentifier::SecondaryOrClient([127, 0, 0, 1]);
+        let now = Instant::now();
+        let synced = Instant::new(now.secs() - 300, now.subsec_nanos());
+        let reply = request.make_reply(Stratum(2), reference_id, synced, now, Instant::now());
+        Some(common::to_bytes(&reply.unwrap()))
+    });
+
+    let client = NtpClient::new().require_synchronized(true);
+    let measurement = task::block_on(client.request(server.addr())).unwrap();
+    let sent = TimestampFormat::from_instant_checked(measurement.sent).unwrap();
+    assert_eq!(measurement.packet.origin_timestamp, sent);
+    assert_eq!(measurement.packet.stratum, Stratum(2));
+    let age = measurement.packet.reference_age(measurement.received).unwrap();
+    assert!(age >= Duration::from_secs(300) && age < Duration::from_secs(301), "{:?}", age);
+
+    // Times preceding 1900 are rejected rather than wrapped into the timestamps.
+    let request = common::reply(TimestampFormat::default());
+    let reference_id = ReferenceIdentifier::SecondaryOrClient([127, 0, 0, 1]);
+    let (now, before_1900) = (Instant::now(), Instant::new(-nippy::EPOCH_DELTA - 1, 0));
+    let times = [(before_1900, now, now), (now, before_1900, now), (now, now, before_1900)];
+    for &(reference, recv, xmit) in &times {
+        match request.make_reply(Stratum(2), reference_id, reference, recv, xmit) {
+            Err(Error::BeforePrimeEpoch(instant)) => assert_eq!(instant, before_1900),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn require_synchronized_rejects_zero_reference() {
+    let server = MockServer::start(|origin| Packet {
+        reference_timestamp: TimestampFormat::default(),
+        ..common::reply(origin)
+    });
+
+    assert!(task::block_on(NtpClient::new().request(server.addr())).is_ok());
+    let client = NtpClient::new().require_synchronized(true);
+    match task::block_on(client.request(server.addr())) {
+        Err(Error::InvalidPacket(_)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn server_transmit_time_is_uncorrected() {
+    // A server whose clock runs 100s ahead and whose responses take 200ms to arrive.
+    let transmitted = Arc::new(Mutex::new(TimestampFormat::default()));
+    let server = {
+        let transmitted = transmitted.clone();
+        MockServer::start(move |origin| {
+            let ahead = Instant::new(Instant::now().secs() + 100, Instant::now().subsec_nanos());
+            let mut response = common::reply(origin);
+            response.receive_timestamp = TimestampFormat::from_instant_checked(ahead).unwrap();
+            response.transmit_timestamp = response.receive_timestamp;
+            *transmitted.lock().unwrap() = response.transmit_timestamp;
+            std::thread::sleep(Duration::from_millis(200));
+            response
+        })
+    };
+
+    let uncorrected = task::block_on(nippy::server_transmit_time(server.addr())).unwrap();
+    assert_eq!(uncorrected, Instant::from(*transmitted.lock().unwrap()));
+
+    let measurement = task::block_on(NtpClient::new().request(server.addr())).unwrap();
+    let corrected = measurement.correct(measurement.received);
+    let transmit = Instant::from(measurement.packet.transmit_timestamp);
+    // Half of the delay is attributed to the response.
+    assert!(corrected > transmit + SignedDuration::from_nanos(50_000_000));
+    assert!(corrected < transmit + SignedDuration::from_nanos(500_000_000));
+}
+
+#[test]
+fn burst_returns_lowest_delay_sample() {
+    // Each response is delayed by a different amount and tagged with its index in the poll field.
+    let delays = [120, 10, 60, 90];
+    let mut index = 0;
+    let server = MockServer::start_raw(move |request| {
+        let mut response = common::reply(common::transmit_timestamp(request));
+        response.poll = LogSeconds(index as i8);
+        std::thread::sleep(Duration::from_millis(delays[index % delays.len()]));
+        index += 1;
+        Some(common::to_bytes(&response))
+    });
+
+    let client = NtpClient::new().timeout(Duration::from_secs(5));
+    let measurement = task::block_on(client.burst(server.addr(), 4, Duration::from_millis(5)));
+    let measurement = measurement.unwrap();
+    assert_eq!(measurement.packet.poll, LogSeconds(1));
+    assert!(measurement.delay() >= SignedDuration::from_nanos(10_000_000));
+}
+
+#[test]
+fn burst_rejects_outlier_samples() {
+    // The second response is the fastest but its timestamps are a second ahead of the others.
+    let mut index = 0;
+    let server = MockServer::start_raw(move |request| {
+        let mut response = common::reply(common::transmit_timestamp(request));
+        if index % 5 == 1 {
+            response.receive_timestamp.seconds += 1;
+            response.transmit_timestamp.seconds += 1;
+        } else {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        index += 1;
+        Some(common::to_bytes(&response))
+    });
+
+    let client = NtpClient::new().timeout(Duration::from_secs(5));
+    let report = task::block_on(client.burst_report(server.addr(), 5, Duration::from_millis(5)));
+    let report = report.unwrap();
+    // Scheduling noise may see further samples rejected, but never the majority.
+    assert_eq!(report.samples, 5);
+    assert!((1..=2).contains(&report.outliers), "{:?}", report);
+    assert!(report.best.offset().as_nanos().abs() < 500_000_000);
+
+    let client = client.outlier_sigma(f64::INFINITY);
+    let report = task::block_on(client.burst_report(server.addr(), 5, Duration::from_millis(5)));
+    let report = report.unwrap();
+    assert_eq!(report.outliers, 0);
+    assert!(report.best.offset() > SignedDuration::from_nanos(500_000_000));
+}
+
+#[test]
+fn burst_is_bounded_by_the_timeout() {
+    let server = MockServer::start(common::reply);
+    let client = NtpClient::new().timeout(Duration::from_millis(300));
+
+    // A burst that would take five seconds returns the best sample collected in the timeout.
+    let start = std::time::Instant::now();
+    let report = task::block_on(client.burst_report(server.addr(), 100, Duration::from_millis(50)));
+    let report = report.unwrap();
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert!((1..100).contains(&report.samples), "{:?}", report);
+
+    // Without any sample by then the burst times out.
+    let server = MockServer::start_raw(|_| None);
+    let start = std::time::Instant::now();
+    match task::block_on(client.burst(server.addr(), 10, Duration::from_millis(5))) {
+        Err(Error::Timeout) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn sample_until_stable_waits_for_offset_to_settle() {
+    // The first four responses are 200ms either side of the local clock, the rest agree with it.
+    // Each is tagged with its index in the poll field.
+    let mut index = 0;
+    let server = MockServer::start_raw(move |request| {
+        let noise = match index {
+            0..=3 if index % 2 == 0 => 200_000_000,
+            0..=3 => -200_000_000,
+            _ => 0,
+        };
+        let now = Instant::now() + SignedDuration::from_nanos(noise);
+        let mut response = common::reply(common::transmit_timestamp(request));
+        response.poll = LogSeconds(index as i8);
+        response.receive_timestamp = TimestampFormat::from_instant_checked(now).unwrap();
+        response.transmit_timestamp = response.receive_timestamp;
+        index += 1;
+        Some(common::to_bytes(&response))
+    });
+
+    let client = NtpClient::new().timeout(Duration::from_secs(5));
+    let measurement = task::block_on(client.sample_until_stable(server.addr(), 3, 10)).unwrap();
+    assert!((4..=6).contains(&measurement.packet.poll.0), "{:?}", measurement);
+    assert!(measurement.offset().as_nanos().abs() < 5_000_000);
+
+    // A server alternating between agreeing with the local clock and running a second ahead.
+    let mut ahead = 0;
+    let noisy = MockServer::start_raw(move |request| {
+        let mut response = common::reply(common::transmit_timestamp(request));
+        ahead = 1 - ahead;
+        response.receive_timestamp.seconds += ahead;
+        response.transmit_timestamp.seconds += ahead;
+        Some(common::to_bytes(&response))
+    });
+    let client = NtpClient::new().timeout(Duration::from_secs(5));
+    match task::block_on(client.sample_until_stable(noisy.addr(), 3, 6)) {
+        Err(Error::NotStable(3)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn association_tracks_reachability() {
+    // A server answering every other request.
+    let mut count = 0;
+    let server = MockServer::start_raw(move |request| {
+        count += 1;
+        if count % 2 == 0 {
+            return None;
+        }
+        let transmit: TimestampFormat = (&request[40..48]).read_bytes().unwrap();
+        Some(common::to_bytes(&common::reply(transmit)))
+    });
+
+    let client = NtpClient::new().timeout(Duration::from_millis(100));
+    assert_eq!(client.association(server.addr()), None);
+    let expected = [0b1, 0b10, 0b101, 0b1010];
+    for (i, &reach) in expected.iter().enumerate() {
+        let result = task::block_on(client.request(server.addr()));
+        assert_eq!(result.is_ok(), i % 2 == 0, "{:?}", result);
+        let association = client.association(server.addr()).unwrap();
+        assert_eq!(association.reach(), reach);
+    }
+    let association = client.association(server.addr()).unwrap();
+    assert!(association.reachable());
+    assert_eq!(association.reach_count(), 2);
+
+    // The register remembers only the last eight requests.
+    let mut association = Association::new();
+    assert!(!association.reachable());
+    association.update(true);
+    for _ in 0..8 {
+        association.update(false);
+    }
+    assert_eq!(association.reach(), 0);
+    assert!(!association.reachable());
+}
+
+#[test]
+fn timeout_when_server_does_not_respond() {
+    let server = MockServer::start_raw(|_| None);
+    let client = NtpClient::new().timeout(Duration::from_millis(50));
+    match task::block_on(client.burst(server.addr(), 2, Duration::from_millis(5))) {
+        Err(Error::Timeout) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn shared_client_handles_concurrent_requests() {
+    fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+    assert_shareable::<NtpClient>();
+
+    let server = MockServer::start(common::reply);
+    let client = Arc::new(NtpClient::new().interleaved(true).timeout(Duration::from_secs(5)));
+    let tasks: Vec<_> = (0..8)
+        .map(|_| {
+            let client = client.clone();
+            let addr = server.addr();
+            task::spawn(async move { client.request(addr).await })
+        })
+        .collect();
+    for task in tasks {
+        let measurement = task::block_on(task).unwrap();
+        let sent = TimestampFormat::from_instant_checked(measurement.sent).unwrap();
+        assert_eq!(measurement.packet.origin_timestamp, sent);
+    }
+}
+
+#[test]
+fn recv_buffer_size_rejects_oversized_response() {
+    // A 300 byte response carrying 252 bytes of extension fields.
+    let server = MockServer::start_patched(|_, response| response.resize(300, 0));
+
+    let client = NtpClient::new().allow_unexpected_extensions(true);
+    assert!(task::block_on(client.request(server.addr())).is_ok());
+
+    let client = client.recv_buffer_size(200);
+    match task::block_on(client.request(server.addr())) {
+        Err(Error::InvalidPacket(reason)) => assert!(reason.contains("200 byte")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn recv_buffer_size_from_config_is_clamped() {
+    let server = MockServer::start(common::reply);
+
+    // A buffer a byte short of the header, which the builder method would have clamped, once
+    // panicked on receiving a bare header.
+    let config = NtpClientConfig { recv_buffer_size: Some(47), ..NtpClientConfig::default() };
+    let client = NtpClient::from_config(config);
+    assert!(task::block_on(client.request(server.addr())).is_ok());
+}
+
+#[test]
+fn from_config_normalizes_as_builder_methods() {
+    let config = NtpClientConfig {
+        delay_asymmetry: -5.0,
+        dscp: Some(0xff),
+        recv_buffer_size: Some(0),
+        max_concurrency: Some(0),
+        outlier_sigma: Some(-1.0),
+        history_capacity: usize::MAX,
+        ..NtpClientConfig::default()
+    };
+    let built = NtpClient::new()
+        .delay_asymmetry(-5.0)
+        .dscp(0xff)
+        .recv_buffer_size(0)
+        .max_concurrency(0)
+        .outlier_sigma(-1.0)
+        .history_capacity(usize::MAX);
+    let client = NtpClient::from_config(config);
+    assert_eq!(client.config(), built.config());
+    assert_eq!(client.config().dscp, Some(0x3f));
+    assert_eq!(client.config().recv_buffer_size, Some(Packet::PACKED_SIZE_BYTES));
+
+    let server = MockServer::start(common::reply);
+    let batch = task::block_on(client.request_many(vec![server.addr()]));
+    assert_eq!(batch.ok.len(), 1);
+
+    let config = NtpClientConfig {
+        require_reference: Some(PrimarySource::Gps),
+        ..NtpClientConfig::default()
+    };
+    assert!(NtpClient::from_config(config).config().require_primary);
+}
+
+#[test]
+fn unresolvable_server_is_resolution_error() {
+    // The failure of the system resolver is described by its error.
+    let client = NtpClient::new();
+    match task::block_on(client.request("ntp.invalid:123")) {
+        Err(Error::Resolution(reason)) => assert!(reason.contains("ntp.invalid:123"), "{}", reason),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // That of a custom resolver by the name it was given.
+    let client = NtpClient::new().resolver(|_| async { Vec::new() });
+    match task::block_on(client.request("pool.ntp.org:123")) {
+        Err(Error::Resolution(host)) => assert_eq!(host, "pool.ntp.org:123"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match task::block_on(client.resolve("pool.ntp.org:123")) {
+        Err(Error::Resolution(host)) => assert_eq!(host, "pool.ntp.org:123"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn reject_orphan_rejects_orphan_stratum() {
+    let orphan = MockServer::start(|origin| {
+        let mut response = common::reply(origin);
+        response.stratum = Stratum(10);
+        response
+    });
+    let secondary = MockServer::start(common::reply);
+
+    let client = NtpClient::new().reject_orphan(10);
+    match task::block_on(client.request(orphan.addr())) {
+        Err(Error::InvalidPacket(reason)) => assert!(reason.contains("orphan")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let measurement = task::block_on(client.request(secondary.addr())).unwrap();
+    assert!(!measurement.packet.is_orphan(10));
+    assert!(task::block_on(NtpClient::new().request(orphan.addr())).unwrap().packet.is_orphan(10));
+}
+
+#[test]
+fn quick_report_summarizes_measurement() {
+    // A server whose clock runs 2.5s ahead.
+    let server = MockServer::start(|origin| {
+        let ahead = Instant::now() + SignedDuration::from_nanos(2_500_000_000);
+        let mut response = common::reply(origin);
+        response.receive_timestamp = TimestampFormat::from_instant_checked(ahead).unwrap();
+        response.transmit_timestamp = response.receive_timestamp;
+        response
+    });
+
+    let report = task::block_on(nippy::quick_report(server.addr())).unwrap();
+    let prefix = format!("{}: stratum 2, offset ", server.addr());
+    assert!(report.starts_with(&prefix), "{}", report);
+    let mut fields = report[prefix.len()..].split("ms, delay ");
+    let offset: f64 = fields.next().unwrap().parse().unwrap();
+    let delay: f64 = fields.next().unwrap().trim_end_matches("ms").parse().unwrap();
+    assert!((offset - 2500.0).abs() < 50.0, "{}", report);
+    assert!((0.0..50.0).contains(&delay), "{}", report);
+}
+
+#[test]
+fn send_request_then_try_recv() {
+    let server = MockServer::start(common::reply);
+    let client = NtpClient::new();
+    assert!(client.try_recv().is_err());
+
+    let transmit = task::block_on(client.send_request(server.addr())).unwrap();
+    let mut response = None;
+    for _ in 0..500 {
+        response = client.try_recv().unwrap();
+        if response.is_some() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(2));
+    }
+    assert_eq!(response.unwrap().origin_timestamp, transmit);
+    assert!(client.try_recv().is_err());
+}
+
+#[test]
+fn try_recv_discards_malformed_datagrams() {
+    // The server precedes its reply with a datagram of an unsupported stratum.
+    let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let handle = std::thread::spawn(move || {
+        let mut buf = [0u8; 48];
+        let (_, client) = server.recv_from(&mut buf).unwrap();
+        let reply = common::to_bytes(&common::reply(common::transmit_timestamp(&buf)));
+        let mut malformed = reply.clone();
+        malformed[1] = 200;
+        server.send_to(&malformed, client).unwrap();
+        server.send_to(&reply, client).unwrap();
+    });
+
+    let client = NtpClient::new();
+    let transmit = task::block_on(client.send_request(server_addr)).unwrap();
+    let mut response = None;
+    for _ in 0..500 {
+        response = client.try_recv().unwrap();
+        if response.is_some() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(2));
+    }
+    handle.join().unwrap();
+    assert_eq!(response.unwrap().origin_timestamp, transmit);
+}
+
+// A clock stopped at a fixed instant.
+struct FakeClock(Instant);
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.0
+    }
+}
+
+#[test]
+fn fake_clock_gives_deterministic_offset() {
+    // A server 5s ahead of the fake clock.
+    let server = MockServer::start(|origin| {
+        let server_time = Instant::new(1_600_000_005, 0);
+        let mut response = common::reply(origin);
+        response.receive_timestamp = TimestampFormat::from_instant_checked(server_time).unwrap();
+        response.transmit_timestamp = response.receive_timestamp;
+        response.reference_timestamp.seconds = response.receive_timestamp.seconds - 60;
+        response
+    });
+
+    let client = NtpClient::new().clock(FakeClock(Instant::new(1_600_000_000, 0)));
+    let measurement = task::block_on(client.request(server.addr())).unwrap();
+    assert_eq!(measurement.sent, Instant::new(1_600_000_000, 0));
+    assert_eq!(measurement.offset(), SignedDuration::from_nanos(5_000_000_000));
+}
+
+#[test]
+fn max_acceptable_precision_rejects_coarse_server() {
+    let coarse = MockServer::start(|origin| {
+        let mut response = common::reply(origin);
+        response.precision = LogSeconds(-6);
+        response
+    });
+    let fine = MockServer::start(common::reply);
+
+    let client = NtpClient::new().max_acceptable_precision(1e-3);
+    match task::block_on(client.request(coarse.addr())) {
+        Err(Error::InvalidPacket(reason)) => assert!(reason.contains("precision")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let fine = task::block_on(client.request(fine.addr())).unwrap();
+    let coarse = task::block_on(NtpClient::new().request(coarse.addr())).unwrap();
+    assert!(fine.quality_score() < coarse.quality_score());
+    assert!(coarse.quality_score() >= coarse.packet.precision_secs());
+}
+
+#[test]
+fn request_many_splits_successes_and_failures() {
+    let first = MockServer::start(common::reply);
+    let second = MockServer::start(common::reply);
+    let unresponsive = MockServer::start_raw(|_| None);
+
+    let client = NtpClient::new().timeout(Duration::from_millis(200));
+    let servers = vec![first.addr(), unresponsive.addr(), second.addr()];
+    let batch = task::block_on(client.request_many(servers));
+
+    let ok: Vec<_> = batch.ok.iter().map(|&(addr, _)| addr).collect();
+    assert_eq!(ok, vec![first.addr(), second.addr()]);
+    assert_eq!(batch.failed.len(), 1);
+    match batch.failed[0] {
+        (addr, Error::Timeout) => assert_eq!(addr, unresponsive.addr()),
+        ref other => panic!("unexpected failure: {:?}", other),
+    }
+    assert!(batch.best().is_some());
+    let combined = batch.combined().unwrap();
+    assert!(combined.as_nanos().abs() < 50_000_000);
+}
+
+#[test]
+fn best_measurement_applies_selection_policy() {
+    let primary =
+        MockServer::start(|origin| Packet { stratum: Stratum(1), ..common::reply(origin) });
+    let tertiary =
+        MockServer::start(|origin| Packet { stratum: Stratum(3), ..common::reply(origin) });
+    let unresponsive = MockServer::start_raw(|_| None);
+    let servers = vec![tertiary.addr(), unresponsive.addr(), primary.addr()];
+
+    let max_delay = Duration::from_secs(1);
+    let client = NtpClient::new()
+        .timeout(Duration::from_millis(200))
+        .selection_policy(SelectionPolicy::LowestStratum { max_delay });
+    let (addr, measurement) = task::block_on(client.best_measurement(servers)).unwrap();
+    assert_eq!(addr, primary.addr());
+    assert_eq!(measurement.packet.stratum, Stratum(1));
+
+    let client = client.selection_policy(SelectionPolicy::LowestStratum {
+        max_delay: Duration::from_secs(0),
+    });
+    let servers = vec![tertiary.addr(), primary.addr()];
+    match task::block_on(client.best_measurement(servers)) {
+        Err(Error::InvalidPacket(_)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match task::block_on(client.best_measurement(vec![unresponsive.addr()])) {
+        Err(Error::Timeout) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn connected_client_ignores_other_sources() {
+    // Before answering, the server has an impostor on another port send a reply that would
+    // otherwise be accepted, as it echoes the request's transmit timestamp.
+    let impostor = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server = MockServer::start_from(move |bytes, src| {
+        let origin = common::transmit_timestamp(bytes);
+        let mut forged = common::reply(origin);
+        forged.stratum = Stratum(5);
+        impostor.send_to(&common::to_bytes(&forged), src).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        Some(common::to_bytes(&common::reply(origin)))
+    });
+
+    let measurement = task::block_on(NtpClient::new().request(server.addr())).unwrap();
+    assert_eq!(measurement.packet.stratum, Stratum(2));
+}
+
+#[test]
+fn unique_id_must_be_echoed() {
+    // Echo the extension fields of the request, flipping a bit of the unique identifier if
+    // `tamper` is set.
+    fn echoing_server(tamper: bool) -> MockServer {
+        MockServer::start_patched(move |bytes, response| {
+            let fields = RawExtensionField::parse_all(&bytes[Packet::PACKED_SIZE_BYTES..]).unwrap();
+            for mut field in fields {
+                assert_eq!(field.field_type, UNIQUE_IDENTIFIER_FIELD_TYPE);
+                assert_eq!(field.value.len(), 32);
+                if tamper {
+                    field.value[0] ^= 1;
+                }
+                response.write_bytes(field).unwrap();
+            }
+        })
+    }
+
+    let honest = echoing_server(false);
+    let tampering = echoing_server(true);
+    let client = NtpClient::new().unique_id(true);
+    assert!(task::block_on(client.request(honest.addr())).is_ok());
+    match task::block_on(client.request(tampering.addr())) {
+        Err(Error::Spoofed) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // A server ignoring the extension does not echo it.
+    let ignoring = MockServer::start(common::reply);
+    match task::block_on(client.request(ignoring.addr())) {
+        Err(Error::Spoofed) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(task::block_on(NtpClient::new().request(ignoring.addr())).is_ok());
+}
+
+#[test]
+fn min_query_interval_spaces_requests() {
+    let server = MockServer::start(common::reply);
+    let other = MockServer::start(common::reply);
+    let interval = Duration::from_millis(200);
+    let client = NtpClient::new().min_query_interval(interval);
+
+    let first = task::block_on(client.request(server.addr())).unwrap();
+    let second = task::block_on(client.clone().request(server.addr())).unwrap();
+    let nanos = |t: Instant| t.secs() as i128 * 1_000_000_000 + t.subsec_nanos() as i128;
+    let spacing = nanos(second.sent) - nanos(first.sent);
+    assert!(spacing >= interval.as_nanos() as i128, "{:?}", spacing);
+
+    // Other servers are not delayed.
+    let start = std::time::Instant::now();
+    task::block_on(client.request(other.addr())).unwrap();
+    assert!(start.elapsed() < interval);
+}
+
+#[test]
+fn zero_padding_is_allowed() {
+    // An appliance padding responses with 20 zero bytes following any extension fields it echoes,
+    // or with `padding` itself.
+    fn padding_server(padding: [u8; 20]) -> MockServer {
+        MockServer::start_patched(move |bytes, response| {
+            response.extend_from_slice(&bytes[Packet::PACKED_SIZE_BYTES..]);
+            response.extend_from_slice(&padding);
+        })
+    }
+
+    let padded = padding_server([0; 20]);
+    match task::block_on(NtpClient::new().request(padded.addr())) {
+        Err(Error::InvalidPacket(_)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let client = NtpClient::new().allow_zero_padding(true);
+    assert!(task::block_on(client.request(padded.addr())).is_ok());
+    let client = NtpClient::new().allow_zero_padding(true).unique_id(true);
+    assert!(task::block_on(client.request(padded.addr())).is_ok());
+
+    let mut garbage = [0; 20];
+    garbage[19] = 1;
+    let garbled = padding_server(garbage);
+    let client = NtpClient::new().allow_zero_padding(true);
+    match task::block_on(client.request(garbled.addr())) {
+        Err(Error::InvalidPacket(_)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn request_into_reuses_buffer() {
+    let server = MockServer::start(common::reply);
+    let client = NtpClient::new().recv_buffer_size(512);
+
+    let mut buf = Vec::new();
+    task::block_on(client.request_into(server.addr(), &mut buf)).unwrap();
+    assert_eq!(buf.len(), Packet::PACKED_SIZE_BYTES);
+    let (ptr, capacity) = (buf.as_ptr(), buf.capacity());
+    assert!(capacity > 512);
+
+    for _ in 0..3 {
+        let measurement = task::block_on(client.request_into(server.addr(), &mut buf)).unwrap();
+        assert_eq!(buf.as_ptr(), ptr);
+        assert_eq!(buf.capacity(), capacity);
+        assert_eq!((&buf[..]).read_bytes::<Packet>().unwrap(), measurement.packet);
+    }
+}
+
+#[test]
+fn unset_local_clock_is_rejected_before_sending() {
+    let requests = Arc::new(Mutex::new(0));
+    let counter = requests.clone();
+    let server = MockServer::start_patched(move |_, _| *counter.lock().unwrap() += 1);
+
+    // A device that booted without a real-time clock and has since drifted before the epoch.
+    let in_1969 = Instant::new(-30 * 24 * 60 * 60, 0);
+    let client = NtpClient::new().clock(FakeClock(in_1969));
+    match task::block_on(client.request(server.addr())) {
+        Err(Error::LocalClockUnset(now)) => assert_eq!(now, in_1969),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match task::block_on(client.send_request(server.addr())) {
+        Err(Error::LocalClockUnset(_)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(*requests.lock().unwrap(), 0);
+
+    // A date in early 1971 is plausibly set.
+    let client = NtpClient::new().clock(FakeClock(Instant::new(40_000_000, 0)));
+    match task::block_on(client.request(server.addr())) {
+        Err(Error::LocalClockUnset(_)) => panic!("clock in 1971 considered unset"),
+        _ => assert_eq!(*requests.lock().unwrap(), 1),
+    }
+}
+
+#[test]
+fn poll_and_precision_are_advertised() {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let received = requests.clone();
+    let server =
+        MockServer::start_patched(move |bytes, _| received.lock().unwrap().push(bytes.to_vec()));
+
+    let client = NtpClient::new().poll(6).precision(-20);
+    task::block_on(client.request(server.addr())).unwrap();
+    task::block_on(client.send_request(server.addr())).unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    for bytes in requests.iter() {
+        assert_eq!(bytes[2], 6);
+        assert_eq!(bytes[3] as i8, -20);
+        let packet = (&bytes[..]).read_bytes::<Packet>().unwrap();
+        assert_eq!((packet.poll(), packet.precision()), (LogSeconds(6), LogSeconds(-20)));
+    }
+}
+
+#[test]
+fn request_many_limits_requests_in_flight() {
+    // A server answering each request 50ms after receiving it, recording the largest number of
+    // requests awaiting an answer at once.
+    let sock = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    sock.set_read_timeout(Some(Duration::from_millis(5))).unwrap();
+    let addr = sock.local_addr().unwrap();
+    let most_pending = Arc::new(Mutex::new(0));
+    let recorded = most_pending.clone();
+    std::thread::spawn(move || {
+        let mut pending = Vec::new();
+        let mut buf = [0u8; 1024];
+        loop {
+            if let Ok((len, src)) = sock.recv_from(&mut buf) {
+                let origin = common::transmit_timestamp(&buf[..len]);
+                pending.push((std::time::Instant::now(), src, origin));
+                let mut most = recorded.lock().unwrap();
+                *most = (*most).max(pending.len());
+            }
+            while let Some(&(at, src, origin)) = pending.first() {
+                if at.elapsed() < Duration::from_millis(50) {
+                    break;
+                }
+                sock.send_to(&common::to_bytes(&common::reply(origin)), src).unwrap();
+                pending.remove(0);
+            }
+        }
+    });
+
+    let client = NtpClient::new().max_concurrency(2);
+    let batch = task::block_on(client.request_many(vec![addr; 6]));
+    assert_eq!(batch.ok.len(), 6);
+    assert_eq!(*most_pending.lock().unwrap(), 2);
+}
+
+#[test]
+fn delay_asymmetry_is_applied_to_measurements() {
+    let server = MockServer::start(common::reply);
+    let symmetric = task::block_on(NtpClient::new().request(server.addr())).unwrap();
+    assert_eq!(symmetric.delay_asymmetry, 0.0);
+
+    let client = NtpClient::new().delay_asymmetry(0.25);
+    let measurement = task::block_on(client.request(server.addr())).unwrap();
+    assert_eq!(measurement.delay_asymmetry, 0.25);
+    let expected = NtpMeasurement { delay_asymmetry: 0.0, ..measurement }.offset()
+        - SignedDuration::from_nanos((measurement.delay().as_nanos() as f64 / 18.0).round() as i128);
+    assert_eq!(measurement.offset(), expected);
+}
+
+#[test]
+fn tcp_exchange_is_length_prefixed() {
+    use async_std::io::{ReadExt, WriteExt};
+    use async_std::net::TcpListener;
+
+    let listener = task::block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+    let addr = listener.local_addr().unwrap();
+    task::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut len = [0u8; 4];
+        stream.read_exact(&mut len).await.unwrap();
+        assert_eq!(u32::from_be_bytes(len), Packet::PACKED_SIZE_BYTES as u32);
+        let mut request = vec![0u8; Packet::PACKED_SIZE_BYTES];
+        stream.read_exact(&mut request).await.unwrap();
+        let response = common::to_bytes(&common::reply(common::transmit_timestamp(&request)));
+        stream.write_all(&(response.len() as u32).to_be_bytes()).await.unwrap();
+        stream.write_all(&response).await.unwrap();
+    });
+
+    let client = NtpClient::new().use_tcp(true).timeout(Duration::from_secs(5));
+    let measurement = task::block_on(client.request(addr)).unwrap();
+    assert_eq!(measurement.packet.stratum, Stratum(2));
+}
+
+// A listener whose accept queue is held full stalls the handshake of the next connection until
+// the server accepts, so that setting up the connection takes far longer than the exchange.
+#[cfg(target_os = "linux")]
+#[test]
+fn tcp_connection_setup_is_excluded_from_delay() {
+    use std::io::{Read, Write};
+    use std::os::unix::io::AsRawFd;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    // Listening again changes the backlog, which a single pending connection then fills.
+    assert_eq!(unsafe { libc::listen(listener.as_raw_fd(), 0) }, 0);
+    let filler = std::net::TcpStream::connect(addr).unwrap();
+    let stall = Duration::from_millis(300);
+    std::thread::spawn(move || {
+        std::thread::sleep(stall);
+        let _filler = (filler, listener.accept().unwrap());
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut len = [0u8; 4];
+        stream.read_exact(&mut len).unwrap();
+        let mut request = vec![0u8; u32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut request).unwrap();
+        let response = common::to_bytes(&common::reply(common::transmit_timestamp(&request)));
+        stream.write_all(&(response.len() as u32).to_be_bytes()).unwrap();
+        stream.write_all(&response).unwrap();
+    });
+
+    let client = NtpClient::new().use_tcp(true).timeout(Duration::from_secs(5));
+    let started = std::time::Instant::now();
+    let measurement = task::block_on(client.request(addr)).unwrap();
+    assert!(started.elapsed() >= stall);
+    let delay = measurement.delay();
+    assert!(delay < SignedDuration::from(Duration::from_millis(100)), "{:?}", delay);
+}
+
+#[test]
+fn duplicate_response_is_rejected() {
+    let now = TimestampFormat::from_instant_checked(Instant::now()).unwrap();
+    let server = MockServer::start(move |origin| Packet {
+        receive_timestamp: now,
+        transmit_timestamp: now,
+        ..common::reply(origin)
+    });
+
+    let client = NtpClient::new();
+    task::block_on(client.request(server.addr())).unwrap();
+    match task::block_on(client.clone().request(server.addr())) {
+        Err(Error::Duplicate) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn later_response_version_is_unsupported() {
+    // Respond with the given version in place of that of the request.
+    fn server_responding_with(version: u8) -> MockServer {
+        MockServer::start_patched(move |_, response| {
+            response[0] = (response[0] & !0b0011_1000) | version << 3;
+        })
+    }
+
+    let client = NtpClient::new();
+    let server = server_responding_with(5);
+    match task::block_on(client.request(server.addr())) {
+        Err(Error::UnsupportedVersion(5)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let server = server_responding_with(3);
+    let measurement = task::block_on(client.request(server.addr())).unwrap();
+    assert_eq!(measurement.packet.version, Version::V3);
+}
+
+#[test]
+fn fast_sync_refines_large_offsets() {
+    // A server ten seconds ahead of the local clock, counting the requests it answers.
+    let requests = Arc::new(Mutex::new(0));
+    let counter = requests.clone();
+    let server = MockServer::start(move |origin| {
+        *counter.lock().unwrap() += 1;
+        let mut response = common::reply(origin);
+        response.receive_timestamp.seconds += 10;
+        response.transmit_timestamp.seconds += 10;
+        response
+    });
+
+    let client = NtpClient::new().timeout(Duration::from_secs(5));
+    let report = task::block_on(client.fast_sync(server.addr())).unwrap();
+    assert!(report.step && report.refined);
+    assert_eq!(*requests.lock().unwrap(), 1 + nippy::FAST_SYNC_BURST_COUNT);
+    assert!((report.measurement.offset().as_secs_f64() - 10.0).abs() < 0.1);
+
+    // A server in step with the local clock needs no refinement.
+    let synchronized = MockServer::start(common::reply);
+    let report = task::block_on(client.fast_sync(synchronized.addr())).unwrap();
+    assert!(!report.step && !report.refined);
+}
+
+#[test]
+fn stratum_0_is_kiss_of_death_only_with_a_kiss_code() {
+    // Respond with stratum 0 and the given reference id.
+    fn server_responding_with(reference_id: [u8; 4]) -> MockServer {
+        MockServer::start_patched(move |_, response| {
+            response[1] = 0;
+            response[12..16].copy_from_slice(&reference_id);
+        })
+    }
+
+    let client = NtpClient::new();
+    let server = server_responding_with(*b"RATE");
+    match task::block_on(client.request(server.addr())) {
+        Err(Error::KissOfDeath(KissOfDeath::Rate)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let server = server_responding_with([0x8f, 0x00, 0x3a, 0xff]);
+    match task::block_on(client.request(server.addr())) {
+        Err(Error::Unsynchronized) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn combine_with_report_classifies_servers() {
+    let good = MockServer::start(common::reply);
+    let also_good = MockServer::start(common::reply);
+    let falseticker = MockServer::start(|origin| {
+        let mut response = common::reply(origin);
+        response.receive_timestamp.seconds += 5;
+        response.transmit_timestamp.seconds += 5;
+        response
+    });
+    let high_stratum =
+        MockServer::start(|origin| Packet { stratum: Stratum(9), ..common::reply(origin) });
+    let kiss = MockServer::start_patched(|_, response| {
+        response[1] = 0;
+        response[12..16].copy_from_slice(b"DENY");
+    });
+
+    let servers =
+        vec![good.addr(), falseticker.addr(), high_stratum.addr(), also_good.addr(), kiss.addr()];
+    let batch = task::block_on(NtpClient::new().request_many(servers));
+    let report = batch.combine_with_report(4);
+    let verdict = |addr| report.servers.iter().find(|&&(a, _)| a == addr).unwrap().1.clone();
+    assert!(matches!(verdict(good.addr()), Verdict::Truechimer(_)));
+    assert!(matches!(verdict(also_good.addr()), Verdict::Truechimer(_)));
+    match verdict(falseticker.addr()) {
+        Verdict::Falseticker(offset) => assert!((offset.as_secs_f64() - 5.0).abs() < 0.1),
+        other => panic!("unexpected verdict: {:?}", other),
+    }
+    assert_eq!(verdict(high_stratum.addr()), Verdict::HighStratum(9));
+    assert_eq!(verdict(kiss.addr()), Verdict::KissOfDeath(KissOfDeath::Deny));
+    assert_eq!(report.servers.len(), 5);
+    assert!(report.offset.unwrap().as_nanos().abs() < 50_000_000);
+
+    let explanation = report.to_string();
+    assert!(explanation.contains(&format!("{}: falseticker", falseticker.addr())));
+    assert!(explanation.ends_with("s") && explanation.contains("combined offset"));
+}
+
+#[test]
+fn compare_reports_difference_between_servers() {
+    let server_running_ahead = |secs: u32| {
+        MockServer::start(move |origin| {
+            let mut response = common::reply(origin);
+            response.receive_timestamp.seconds += secs;
+            response.transmit_timestamp.seconds += secs;
+            response
+        })
+    };
+    let (near, far) = (server_running_ahead(1), server_running_ahead(4));
+
+    let report = task::block_on(NtpClient::new().compare(far.addr(), near.addr())).unwrap();
+    assert_eq!(report.a.0, far.addr());
+    assert_eq!(report.b.0, near.addr());
+    assert!((report.a.1.as_secs_f64() - 4.0).abs() < 0.05);
+    assert!((report.b.1.as_secs_f64() - 1.0).abs() < 0.05);
+    assert!((report.difference.as_secs_f64() - 3.0).abs() < 0.05);
+    assert_eq!(report.closer, near.addr());
+    let summary = report.to_string();
+    assert!(summary.ends_with(&format!("{} agrees better with the local clock", near.addr())));
+
+    let silent = MockServer::start_raw(|_| None);
+    let client = NtpClient::new().timeout(Duration::from_millis(100));
+    assert!(task::block_on(client.compare(near.addr(), silent.addr())).is_err());
+}
+
+#[test]
+fn reflected_request_is_rejected() {
+    let echo = MockServer::start_raw(|request| Some(request.to_vec()));
+    match task::block_on(NtpClient::new().request(echo.addr())) {
+        Err(Error::Reflected) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    // The echoed unique identifier does not make the echo acceptable.
+    match task::block_on(NtpClient::new().unique_id(true).request(echo.addr())) {
+        Err(Error::Reflected) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // A server-mode response carrying our transmit timestamp as its own.
+    let copycat = MockServer::start(|origin| Packet {
+        receive_timestamp: origin,
+        transmit_timestamp: origin,
+        ..common::reply(origin)
+    });
+    match task::block_on(NtpClient::new().request(copycat.addr())) {
+        Err(Error::Reflected) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn undecodable_response_is_invalid_packet() {
+    let reserved_stratum = MockServer::start_patched(|_, response| response[1] = 200);
+    match task::block_on(NtpClient::new().request(reserved_stratum.addr())) {
+        Err(Error::InvalidPacket(ref reason)) => assert!(reason.contains("stratum 200")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let short = MockServer::start_raw(|_| Some(vec![0x24; 20]));
+    match task::block_on(NtpClient::new().request(short.addr())) {
+        Err(Error::InvalidPacket(_)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn from_socket_sends_requests_from_given_socket() {
+    let sources = Arc::new(Mutex::new(Vec::new()));
+    let seen = sources.clone();
+    let server = MockServer::start_from(move |request, src| {
+        seen.lock().unwrap().push(src);
+        Some(common::to_bytes(&common::reply(common::transmit_timestamp(request))))
+    });
+    // A stray datagram, which the client must discard rather than take for the response.
+    let stray = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let sock = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let local_addr = sock.local_addr().unwrap();
+    stray.send_to(&[0u8; 48], local_addr).unwrap();
+    let client = NtpClient::from_socket(sock).timeout(Duration::from_secs(5));
+    assert_eq!(client.socket().unwrap().local_addr().unwrap(), local_addr);
+    for _ in 0..2 {
+        assert!(task::block_on(client.request(server.addr())).is_ok());
+    }
+    assert_eq!(*sources.lock().unwrap(), vec![local_addr, local_addr]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn dscp_is_set_on_requests() {
+    let server = MockServer::start(common::reply);
+    let client = NtpClient::new().dscp(48);
+    assert!(task::block_on(client.request(server.addr())).is_ok());
+    let client = client.use_tcp(true);
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        use std::io::{Read, Write};
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = [0u8; 4 + 48];
+        stream.read_exact(&mut request).unwrap();
+        let response = common::to_bytes(&common::reply(common::transmit_timestamp(&request[4..])));
+        stream.write_all(&(response.len() as u32).to_be_bytes()).unwrap();
+        stream.write_all(&response).unwrap();
+    });
+    assert!(task::block_on(client.request(addr)).is_ok());
+}
+
+#[test]
+fn sanity_check_local_rejects_clock_a_decade_off() {
+    let server = MockServer::start(common::reply);
+    let decade_ago = Instant::new(Instant::now().secs() - 10 * 365 * 24 * 60 * 60, 0);
+    let client = NtpClient::new().clock(FakeClock(decade_ago));
+    assert!(task::block_on(client.request(server.addr())).is_ok());
+
+    let client = client.sanity_check_local(true);
+    match task::block_on(client.request(server.addr())) {
+        Err(Error::LocalClockImplausible(offset)) => {
+            assert!(offset.secs() >= 10 * 365 * 24 * 60 * 60 - 1)
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let client = NtpClient::new().sanity_check_local(true);
+    assert!(task::block_on(client.request(server.addr())).is_ok());
+}
+
+#[test]
+fn injected_rng_generates_unique_id() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let ids = seen.clone();
+    let server = MockServer::start_patched(move |bytes, response| {
+        let fields = RawExtensionField::parse_all(&bytes[Packet::PACKED_SIZE_BYTES..]).unwrap();
+        for field in fields {
+            ids.lock().unwrap().push(field.value.clone());
+            response.write_bytes(field).unwrap();
+        }
+    });
+
+    // A generator counting up from one fills each identifier with four little-endian `u64`s.
+    let client = NtpClient::new().unique_id(true).rng(StepRng::new(1, 1));
+    task::block_on(client.request(server.addr())).unwrap();
+    task::block_on(client.request(server.addr())).unwrap();
+    let expected = |first: u64| -> Vec<u8> {
+        (first..first + 4).flat_map(|n| n.to_le_bytes().to_vec()).collect()
+    };
+    assert_eq!(*seen.lock().unwrap(), vec![expected(1), expected(5)]);
+}
+
+#[test]
+fn max_offset_rejects_distant_servers() {
+    // A server `offset_secs` ahead of the fake clock.
+    fn server_ahead_by(offset_secs: i64) -> MockServer {
+        MockServer::start_patched(move |_, response| {
+            let server_time = Instant::new(1_600_000_000 + offset_secs, 0);
+            let received = TimestampFormat::from_instant_checked(server_time).unwrap();
+            let reference = TimestampFormat { seconds: received.seconds - 60, ..received };
+            // The reference, receive and transmit timestamps.
+            (&mut response[16..24]).write_bytes(reference).unwrap();
+            (&mut response[32..40]).write_bytes(received).unwrap();
+            (&mut response[40..48]).write_bytes(received).unwrap();
+        })
+    }
+    let client = || {
+        NtpClient::new()
+            .clock(FakeClock(Instant::new(1_600_000_000, 0)))
+            .max_offset(Duration::from_secs(1000))
+    };
+
+    for &offset_secs in &[999, 1000, -1000] {
+        let server = server_ahead_by(offset_secs);
+        let measurement = task::block_on(client().request(server.addr())).unwrap();
+        assert_eq!(measurement.offset().secs(), offset_secs);
+    }
+    for &offset_secs in &[1001, -1001] {
+        let server = server_ahead_by(offset_secs);
+        match task::block_on(client().request(server.addr())) {
+            Err(Error::OffsetTooLarge(offset)) => assert_eq!(offset.secs(), offset_secs),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn request_cancellable_is_cancelled_promptly() {
+    let unresponsive = MockServer::start_raw(|_| None);
+    let client = NtpClient::new().timeout(Duration::from_secs(10));
+    let start = std::time::Instant::now();
+    let cancel = task::sleep(Duration::from_millis(100));
+    match task::block_on(client.request_cancellable(unresponsive.addr(), cancel)) {
+        Err(Error::Cancelled) => assert!(start.elapsed() < Duration::from_secs(2)),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // A request completing first is unaffected.
+    let server = MockServer::start(common::reply);
+    let never = std::future::pending();
+    assert!(task::block_on(client.request_cancellable(server.addr(), never)).is_ok());
+}
+
+#[test]
+fn history_stats_follow_recent_measurements() {
+    // A server one second further ahead of the fake clock with each response.
+    let responses = Arc::new(Mutex::new(0));
+    let counter = responses.clone();
+    let server = MockServer::start(move |origin| {
+        let mut count = counter.lock().unwrap();
+        *count += 1;
+        let server_time = Instant::new(1_600_000_000 + *count, 0);
+        let mut response = common::reply(origin);
+        response.receive_timestamp = TimestampFormat::from_instant_checked(server_time).unwrap();
+        response.transmit_timestamp = response.receive_timestamp;
+        response.reference_timestamp.seconds = response.receive_timestamp.seconds - 60;
+        response
+    });
+
+    let client = NtpClient::new().clock(FakeClock(Instant::new(1_600_000_000, 0)));
+    task::block_on(client.request(server.addr())).unwrap();
+    assert_eq!(client.stats().count, 0);
+
+    let client = client.history_capacity(2);
+    for _ in 0..3 {
+        task::block_on(client.request(server.addr())).unwrap();
+    }
+    // Only the offsets of 3s and 4s are remembered.
+    let stats = client.stats();
+    assert_eq!(stats.count, 2);
+    assert_eq!(stats.mean_offset, SignedDuration::from_nanos(3_500_000_000));
+    assert_eq!(stats.jitter, Duration::from_millis(500));
+    assert!(stats.max_delay > SignedDuration::ZERO);
+    assert!(stats.max_delay < SignedDuration::from_nanos(1_000_000_000));
+}
+
+#[test]
+fn current_time_beyond_2036_rollover() {
+    // A server at 1 January 2040, in NTP era 1.
+    let server_time = Instant::new(2_208_988_800, 0);
+    let server = MockServer::start(move |origin| {
+        let mut response = common::reply(origin);
+        response.receive_timestamp = TimestampFormat::from_instant_checked(server_time).unwrap();
+        response.transmit_timestamp = response.receive_timestamp;
+        response.reference_timestamp.seconds = response.receive_timestamp.seconds - 60;
+        response
+    });
+    let close = |t: Instant| (t.secs() - server_time.secs()).abs() <= 1;
+    // A fresh client for each request, as the server's responses are all alike.
+    let current_time = |local_secs, era| {
+        let client = NtpClient::new().clock(FakeClock(Instant::new(local_secs, 0)));
+        task::block_on(client.current_time(server.addr(), era)).unwrap()
+    };
+
+    // A local clock a few seconds off places the timestamps in the right era.
+    assert!(close(current_time(2_208_988_795, None)));
+    assert!(close(current_time(2_208_988_795, Some(1))));
+
+    // A local clock reading June 1971 is over 68 years off and misjudges the era, unless the
+    // era is given.
+    assert_eq!(current_time(44_582_400, None).secs(), server_time.secs() - (1 << 32));
+    assert!(close(current_time(44_582_400, Some(1))));
+
+    // The reference timestamp, a minute old, is read in the same era.
+    let client = NtpClient::new()
+        .clock(FakeClock(Instant::new(2_208_988_795, 0)))
+        .max_reference_age(Duration::from_secs(600));
+    let measurement = task::block_on(client.request(server.addr())).unwrap();
+    let age = measurement.packet.reference_age(measurement.received).unwrap();
+    assert!(age < Duration::from_secs(120), "{:?}", age);
+}
+
+#[test]
+fn server_time_beyond_2036_rollover() {
+    // A server at 1 January 2040, in NTP era 1, which has never synchronized.
+    let server_time = Instant::new(2_208_988_800, 0);
+    let server = MockServer::start(move |origin| {
+        let mut response = common::reply(origin);
+        response.receive_timestamp = TimestampFormat::from_instant_checked(server_time).unwrap();
+        response.transmit_timestamp = response.receive_timestamp;
+        response.reference_timestamp = TimestampFormat::default();
+        response
+    });
+
+    let transmitted = task::block_on(nippy::server_transmit_time(server.addr())).unwrap();
+    assert_eq!(transmitted, server_time);
+    let received = task::block_on(nippy::server_time(server.addr(), TimestampSelector::Receive));
+    assert_eq!(received.unwrap(), server_time);
+    match task::block_on(nippy::server_time(server.addr(), TimestampSelector::Reference)) {
+        Err(Error::InvalidPacket(_)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn report_populates_every_field() {
+    // A server whose clock runs 2.5s ahead.
+    let server = MockServer::start(|origin| {
+        let ahead = Instant::now() + SignedDuration::from_nanos(2_500_000_000);
+        let mut response = common::reply(origin);
+        response.leap_indicator = LeapIndicator::AddOne;
+        response.receive_timestamp = TimestampFormat::from_instant_checked(ahead).unwrap();
+        response.transmit_timestamp = response.receive_timestamp;
+        response
+    });
+
+    let report = task::block_on(nippy::report(server.addr())).unwrap();
+    assert_eq!(report.server_addr, server.addr());
+    assert_eq!(report.stratum, Stratum(2));
+    assert_eq!(report.hops, 1);
+    assert_eq!(report.leap, LeapIndicator::AddOne);
+    assert_eq!(report.reference, ReferenceIdentifier::SecondaryOrClient([127, 0, 0, 1]));
+    assert!((report.offset.as_secs_f64() - 2.5).abs() < 0.05, "{:?}", report);
+    assert!(report.delay >= SignedDuration::ZERO && report.delay.as_secs_f64() < 0.05);
+    assert!(report.root_distance > 0.0);
+    assert_eq!(report.ntp_time, report.local_time + report.offset);
+    assert!((report.local_time.secs() - Instant::now().secs()).abs() <= 1);
+}
@@ -0,0 +1,108 @@
+//! A local mock ntp server shared by the integration tests.
+
+#![allow(dead_code)]
+
+use async_std::net::UdpSocket;
+use async_std::task;
+use nippy::protocol::{
+    ConstPackedSizeBytes, LeapIndicator, LogSeconds, Mode, Packet, ReadBytes,
+    ReferenceIdentifier, ShortFormat, Stratum, TimestampFormat, Version, WriteBytes,
+};
+use nippy::Instant;
+use std::net::SocketAddr;
+
+/// A server bound to a loopback port that answers each request using a closure.
+pub struct MockServer {
+    addr: SocketAddr,
+}
+
+impl MockServer {
+    /// Start a server that answers each request with the packet returned by `respond`, which is
+    /// given the transmit timestamp of the request.
+    pub fn start<F>(respond: F) -> Self
+    where
+        F: Fn(TimestampFormat) -> Packet + Send + 'static,
+    {
+        MockServer::start_raw(move |bytes| Some(to_bytes(&respond(transmit_timestamp(bytes)))))
+    }
+
+    /// Start a server that answers each request datagram with the bytes returned by `respond`, or
+    /// drops the request if it returns `None`.
+    pub fn start_raw<F>(mut respond: F) -> Self
+    where
+        F: FnMut(&[u8]) -> Option<Vec<u8>> + Send + 'static,
+    {
+        MockServer::start_from(move |bytes, _| respond(bytes))
+    }
+
+    /// Start a server that answers each request datagram with the bytes of `reply`, after `patch`
+    /// has modified them. `patch` is given the request and the bytes of the response.
+    pub fn start_patched<F>(mut patch: F) -> Self
+    where
+        F: FnMut(&[u8], &mut Vec<u8>) + Send + 'static,
+    {
+        MockServer::start_raw(move |request| {
+            let mut response = to_bytes(&reply(transmit_timestamp(request)));
+            patch(request, &mut response);
+            Some(response)
+        })
+    }
+
+    /// As `start_raw`, but `respond` is also given the address the request came from.
+    pub fn start_from<F>(mut respond: F) -> Self
+    where
+        F: FnMut(&[u8], SocketAddr) -> Option<Vec<u8>> + Send + 'static,
+    {
+        let sock = task::block_on(UdpSocket::bind("127.0.0.1:0")).unwrap();
+        let addr = sock.local_addr().unwrap();
+        task::spawn(async move {
+            let mut buf = [0u8; 2048];
+            loop {
+                let (len, src) = sock.recv_from(&mut buf).await.unwrap();
+                if let Some(response) = respond(&buf[..len], src) {
+                    sock.send_to(&response, src).await.unwrap();
+                }
+            }
+        });
+        MockServer { addr }
+    }
+
+    /// The address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+/// The transmit timestamp of a request, read directly from its bytes.
+pub fn transmit_timestamp(request: &[u8]) -> TimestampFormat {
+    (&request[40..48]).read_bytes().unwrap()
+}
+
+/// A well-formed stratum 2 server reply to a request, stamped with the current time, from a
+/// server that last synchronized a minute ago.
+pub fn reply(origin: TimestampFormat) -> Packet {
+    let now = TimestampFormat::from_instant_checked(Instant::now()).unwrap();
+    let reference = TimestampFormat { seconds: now.seconds - 60, ..now };
+    Packet {
+        leap_indicator: LeapIndicator::NoWarning,
+        version: Version::V4,
+        mode: Mode::Server,
+        stratum: Stratum(2),
+        poll: LogSeconds(0),
+        precision: LogSeconds(-20),
+        root_delay: ShortFormat { seconds: 0, fraction: 0x0100 },
+        root_dispersion: ShortFormat { seconds: 0, fraction: 0x0200 },
+        reference_id: ReferenceIdentifier::SecondaryOrClient([127, 0, 0, 1]),
+        reference_timestamp: reference,
+        origin_timestamp: origin,
+        receive_timestamp: now,
+        transmit_timestamp: now,
+    }
+}
+
+/// Serialize a packet to its 48 byte wire representation.
+pub fn to_bytes(packet: &Packet) -> Vec<u8> {
+    let mut bytes = vec![0u8; Packet::PACKED_SIZE_BYTES];
+    (&mut bytes[..]).write_bytes(packet).unwrap();
+    bytes
+}
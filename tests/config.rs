@@ -0,0 +1,65 @@
+#![cfg(feature = "serde")]
+
+extern crate nippy;
+extern crate serde_json;
+
+use nippy::protocol::PrimarySource;
+use nippy::{NtpClient, NtpClientConfig, SelectionPolicy};
+use std::time::Duration;
+
+#[test]
+fn config_round_trip() {
+    let config = NtpClientConfig {
+        timeout: Some(Duration::from_millis(1500)),
+        require_primary: true,
+        require_reference: Some(PrimarySource::Gps),
+        max_acceptable_precision: Some(1e-6),
+        poll: 6,
+        delay_asymmetry: 0.25,
+        dscp: Some(46),
+        max_offset: Some(Duration::from_secs(1)),
+        selection_policy: SelectionPolicy::LowestStratum { max_delay: Duration::from_millis(50) },
+        fallback_addrs: vec!["127.0.0.1:123".parse().unwrap()],
+        history_capacity: 8,
+        ..NtpClientConfig::default()
+    };
+    let json = serde_json::to_string(&config).unwrap();
+    let decoded: NtpClientConfig = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded, config);
+
+    let client = NtpClient::from_config(decoded);
+    assert_eq!(client.config(), &config);
+    let client = NtpClient::new().timeout(Duration::from_millis(1500)).poll(6);
+    assert_eq!(client.config().timeout, config.timeout);
+    assert_eq!(client.config().poll, 6);
+
+    // Fields missing from the serialized form take their default values.
+    let partial: NtpClientConfig = serde_json::from_str(r#"{"poll": 4}"#).unwrap();
+    assert_eq!(partial, NtpClientConfig { poll: 4, ..NtpClientConfig::default() });
+}
+
+#[cfg(feature = "proxy")]
+#[test]
+fn proxy_password_is_not_serialized() {
+    use nippy::{Socks5Credentials, Socks5ProxyConfig};
+
+    let addr = "127.0.0.1:1080".parse().unwrap();
+    let client = NtpClient::new().socks5_proxy(addr, Some(("user", "hunter2")));
+    let config = client.config();
+    let expected = Socks5ProxyConfig {
+        addr,
+        auth: Some(Socks5Credentials {
+            username: "user".to_string(),
+            password: "hunter2".to_string(),
+        }),
+    };
+    assert_eq!(config.socks5_proxy, Some(expected));
+    assert!(!format!("{:?}", config).contains("hunter2"));
+
+    let json = serde_json::to_string(config).unwrap();
+    assert!(!json.contains("hunter2"));
+    let decoded: NtpClientConfig = serde_json::from_str(&json).unwrap();
+    let auth = decoded.socks5_proxy.unwrap().auth.unwrap();
+    assert_eq!(auth.username, "user");
+    assert_eq!(auth.password, "");
+}
@@ -0,0 +1,145 @@
+extern crate nippy;
+
+use nippy::control::{parse_variables, ControlMessage, OpCode, PeerStatus, Reassembly, Selection};
+use nippy::protocol::{ReadBytes, WriteBytes};
+#[cfg(feature = "async-std")]
+use std::net::UdpSocket;
+#[cfg(feature = "async-std")]
+use std::time::Duration;
+
+// Two fragments of a `readvar` response for the system association, as sent by ntpd.
+const FRAGMENT_1: &[u8] = b"version=\"ntpd 4.2.8p15@1.3728-o\", processor=\"x86_64\",\r\nstratum=2, ";
+const FRAGMENT_2: &[u8] = b"precision=-23, rootdelay=1.234,\r\nsys_peer=41211, offset=-0.312, sys_jitter=0.087";
+
+fn response_fragment(offset: usize, data: &[u8], more: bool) -> Vec<u8> {
+    sequenced_fragment(7, offset, data, more)
+}
+
+fn sequenced_fragment(sequence: u16, offset: usize, data: &[u8], more: bool) -> Vec<u8> {
+    let mut fragment = ControlMessage::request(OpCode::ReadVariables, sequence, 0);
+    fragment.response = true;
+    fragment.more = more;
+    fragment.offset = offset as u16;
+    fragment.data = data.to_vec();
+    let mut bytes = Vec::new();
+    bytes.write_bytes(&fragment).unwrap();
+    bytes
+}
+
+#[test]
+fn read_variables_response_from_fragments() {
+    let first = response_fragment(0, FRAGMENT_1, true);
+    let last = response_fragment(FRAGMENT_1.len(), FRAGMENT_2, false);
+    assert_eq!(first.len() % 4, 0);
+
+    // Fragments may arrive out of order.
+    let mut reassembly = Reassembly::new();
+    reassembly.insert((&last[..]).read_bytes::<ControlMessage>().unwrap()).unwrap();
+    assert!(!reassembly.is_complete());
+    reassembly.insert((&first[..]).read_bytes::<ControlMessage>().unwrap()).unwrap();
+    assert!(reassembly.is_complete());
+
+    let variables = parse_variables(&reassembly.into_data().unwrap()).unwrap();
+    assert_eq!(variables.len(), 8);
+    assert_eq!(variables["version"], "ntpd 4.2.8p15@1.3728-o");
+    assert_eq!(variables["stratum"], "2");
+    assert_eq!(variables["sys_peer"], "41211");
+    assert_eq!(variables["offset"], "-0.312");
+    assert_eq!(variables["sys_jitter"], "0.087");
+}
+
+#[test]
+fn reassembly_requires_contiguous_fragments() {
+    let fragment = |offset: usize, len: usize, more: bool| {
+        let bytes = response_fragment(offset, &FRAGMENT_1[offset..offset + len], more);
+        (&bytes[..]).read_bytes::<ControlMessage>().unwrap()
+    };
+
+    // The lengths sum to the end of the response, but leave a gap between 4 and 10 bytes in.
+    let mut reassembly = Reassembly::new();
+    reassembly.insert(fragment(0, 4, true)).unwrap();
+    reassembly.insert(fragment(14, 6, true)).unwrap();
+    assert!(reassembly.insert(fragment(10, 2, false)).is_err());
+    assert!(!reassembly.is_complete());
+
+    // A gap keeps the response incomplete until it is filled.
+    let mut reassembly = Reassembly::new();
+    reassembly.insert(fragment(0, 4, true)).unwrap();
+    reassembly.insert(fragment(8, 4, false)).unwrap();
+    assert!(!reassembly.is_complete());
+    assert!(reassembly.insert(fragment(12, 4, true)).is_err());
+    assert!(reassembly.insert(fragment(4, 0, true)).is_err());
+    reassembly.insert(fragment(4, 4, true)).unwrap();
+    assert!(reassembly.is_complete());
+    assert_eq!(reassembly.into_data().unwrap(), &FRAGMENT_1[..12]);
+}
+
+#[test]
+fn peer_status_word_decoding() {
+    // As shown by `ntpq -c associations` for a configured, reachable system peer.
+    let status = PeerStatus(0x961a);
+    assert!(status.is_configured());
+    assert!(!status.is_authentication_enabled());
+    assert!(!status.is_authentic());
+    assert!(status.is_reachable());
+    assert!(!status.is_broadcast());
+    assert_eq!(status.selection(), Selection::SystemPeer);
+    assert_eq!(status.event_count(), 1);
+    assert_eq!(status.event_code(), 0xa);
+
+    let status = PeerStatus(0x1314);
+    assert!(!status.is_configured());
+    assert!(status.is_reachable());
+    assert_eq!(status.selection(), Selection::Outlier);
+}
+
+#[cfg(feature = "async-std")]
+#[test]
+fn query_ignores_foreign_and_malformed_datagrams() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let handle = std::thread::spawn(move || {
+        let mut buf = [0u8; 512];
+        let (len, client) = server.recv_from(&mut buf).unwrap();
+        let request: ControlMessage = (&buf[..len]).read_bytes().unwrap();
+        let sequence = request.sequence;
+
+        // A well-formed response from somebody other than the server.
+        let intruder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let forged = sequenced_fragment(sequence, 0, b"stratum=16", false);
+        intruder.send_to(&forged, client).unwrap();
+        // A datagram too short to be a control message.
+        server.send_to(&[0x16, 0x82], client).unwrap();
+
+        let first = sequenced_fragment(sequence, 0, FRAGMENT_1, true);
+        let last = sequenced_fragment(sequence, FRAGMENT_1.len(), FRAGMENT_2, false);
+        server.send_to(&first, client).unwrap();
+        server.send_to(&last, client).unwrap();
+    });
+
+    let query = nippy::control::query(
+        server_addr,
+        OpCode::ReadVariables,
+        0,
+        &[],
+        Duration::from_secs(5),
+    );
+    let data = async_std::task::block_on(query).unwrap();
+    handle.join().unwrap();
+    assert_eq!(data, [FRAGMENT_1, FRAGMENT_2].concat());
+}
+
+#[cfg(feature = "async-std")]
+#[test]
+fn query_times_out_without_a_response() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let query = nippy::control::query(
+        server.local_addr().unwrap(),
+        OpCode::ReadVariables,
+        0,
+        &[],
+        Duration::from_millis(200),
+    );
+    let err = async_std::task::block_on(query).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+}
@@ -0,0 +1,88 @@
+extern crate nippy;
+
+use nippy::history::{MeasurementLog, MeasurementLogReader, MeasurementRecord};
+use nippy::protocol::{
+    LeapIndicator, LogSeconds, Mode, Packet, ReferenceIdentifier, ShortFormat, Stratum,
+    TimestampFormat, Version,
+};
+use nippy::{Instant, NtpMeasurement, SignedDuration};
+use std::io;
+use std::time::Duration;
+
+fn timestamp(secs: i64, subsec_nanos: i32) -> TimestampFormat {
+    TimestampFormat::from_instant_checked(Instant::new(secs, subsec_nanos)).unwrap()
+}
+
+// An exchange received at `secs` in which the local clock is 5ms ahead of the server and the
+// delay is 20ms.
+fn measurement(secs: i64) -> NtpMeasurement {
+    let packet = Packet {
+        leap_indicator: LeapIndicator::AddOne,
+        version: Version::V4,
+        mode: Mode::Server,
+        stratum: Stratum(2),
+        poll: LogSeconds(0),
+        precision: LogSeconds(-20),
+        root_delay: ShortFormat::default(),
+        root_dispersion: ShortFormat::default(),
+        reference_id: ReferenceIdentifier::SecondaryOrClient([127, 0, 0, 1]),
+        reference_timestamp: timestamp(secs - 100, 0),
+        origin_timestamp: timestamp(secs, 0),
+        receive_timestamp: timestamp(secs, 5_000_000),
+        transmit_timestamp: timestamp(secs, 6_000_000),
+    };
+    NtpMeasurement {
+        packet,
+        sent: Instant::new(secs, 0),
+        received: Instant::new(secs, 21_000_000),
+        round_trip: Duration::from_millis(21),
+        interleaved: true,
+        delay_asymmetry: 0.0,
+    }
+}
+
+#[test]
+fn measurement_record_round_trip() {
+    let measurement = measurement(1_600_000_100);
+    let bytes = measurement.to_bytes();
+    assert_eq!(bytes.len(), 32);
+    assert_eq!(&bytes[24..32], &[2, 1, 1, 0xEC, 127, 0, 0, 1]);
+
+    let record = MeasurementRecord::from_bytes(&bytes).unwrap();
+    assert_eq!(record, measurement.record());
+    assert_eq!(record.received, Instant::new(1_600_000_100, 21_000_000));
+    assert_eq!(record.offset, SignedDuration::from_nanos(-5_000_000));
+    assert_eq!(record.delay, SignedDuration::from_nanos(20_000_000));
+    assert_eq!(record.stratum, Stratum(2));
+    assert_eq!(record.leap_indicator, LeapIndicator::AddOne);
+    assert!(record.interleaved);
+    assert_eq!(record.precision, LogSeconds(-20));
+    assert_eq!(record.reference_id, [127, 0, 0, 1]);
+
+    let mut bad_leap = bytes;
+    bad_leap[25] = 4;
+    assert!(MeasurementRecord::from_bytes(&bad_leap).is_err());
+}
+
+#[test]
+fn measurement_log_reads_back_records() {
+    let mut log = MeasurementLog::new(Vec::new());
+    for i in 0..3 {
+        log.append(&measurement(1_600_000_000 + i * 60)).unwrap();
+    }
+    let mut bytes = log.into_inner();
+    assert_eq!(bytes.len(), 3 * 32);
+
+    // A write interrupted part way through a fourth record.
+    bytes.extend_from_slice(&[0u8; 10]);
+    let mut reader = MeasurementLogReader::new(&bytes[..]);
+    for i in 0..3 {
+        let record = reader.next().unwrap().unwrap();
+        assert_eq!(record, measurement(1_600_000_000 + i * 60).record());
+    }
+    match reader.next() {
+        Some(Err(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(reader.next().is_none());
+}
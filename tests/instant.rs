@@ -0,0 +1,294 @@
+extern crate nippy;
+
+use nippy::protocol::{ShortFormat, TimestampFormat};
+use nippy::{Error, Instant, SignedDuration, SubsecPrecision};
+use std::time::Duration;
+
+#[test]
+fn timestamp_from_instant_checked_rejects_pre_epoch() {
+    // 1950-01-01T00:00:00Z precedes the unix epoch but follows the ntp prime epoch.
+    let instant = Instant::new(-631_152_000, 0);
+    let timestamp = TimestampFormat::from_instant_checked(instant).unwrap();
+    assert_eq!(timestamp.seconds, 1_577_836_800);
+
+    // 1850-01-01T00:00:00Z precedes the ntp prime epoch.
+    let instant = Instant::new(-3_786_825_600, -500_000_000);
+    match TimestampFormat::from_instant_checked(instant) {
+        Err(Error::BeforePrimeEpoch(i)) => assert_eq!(i.secs(), instant.secs()),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn timestamp_from_instant_checked_2020() {
+    // 2020-01-01T00:00:00.5Z
+    let instant = Instant::new(1_577_836_800, 500_000_000);
+    let timestamp = TimestampFormat::from_instant_checked(instant).unwrap();
+    assert_eq!(timestamp, TimestampFormat { seconds: 3_786_825_600, fraction: 1 << 31 });
+    assert_eq!(TimestampFormat::from(instant), timestamp);
+}
+
+#[test]
+fn instant_ntp64_round_trip() {
+    assert_eq!(Instant::new(0, 0).to_ntp64(), 0x83AA_7E80_0000_0000);
+    assert_eq!(Instant::from_ntp64(0x83AA_7E80_0000_0000), Instant::new(0, 0));
+
+    // 2020-01-01T00:00:00.5Z
+    let instant = Instant::new(1_577_836_800, 500_000_000);
+    assert_eq!(instant.to_ntp64(), 0xE1B6_5F80_8000_0000);
+    assert_eq!(Instant::from_ntp64(0xE1B6_5F80_8000_0000), instant);
+
+    // Nanoseconds survive the round trip exactly, as the fraction is finer than a nanosecond.
+    for &nanos in &[1, 123_456_789, 999_999_999] {
+        let instant = Instant::new(1_577_836_800, nanos);
+        assert_eq!(Instant::from_ntp64(instant.to_ntp64()), instant);
+    }
+    // Half a second past the prime epoch, before the unix epoch.
+    assert_eq!(Instant::from_ntp64(1 << 31), Instant::new(-2_208_988_799, -500_000_000));
+}
+
+#[test]
+fn format_rfc3339_precision() {
+    // 2024-05-01T12:00:00.123456789Z
+    let instant = Instant::new(1_714_564_800, 123_456_789);
+    assert_eq!(instant.format_rfc3339(SubsecPrecision::Seconds), "2024-05-01T12:00:00Z");
+    assert_eq!(instant.format_rfc3339(SubsecPrecision::Millis), "2024-05-01T12:00:00.123Z");
+    assert_eq!(instant.format_rfc3339(SubsecPrecision::Micros), "2024-05-01T12:00:00.123456Z");
+    assert_eq!(instant.format_rfc3339(SubsecPrecision::Nanos), "2024-05-01T12:00:00.123456789Z");
+
+    // 2024-02-29T23:59:59.000000001Z
+    let instant = Instant::new(1_709_251_199, 1);
+    assert_eq!(instant.format_rfc3339(SubsecPrecision::Nanos), "2024-02-29T23:59:59.000000001Z");
+}
+
+#[test]
+fn format_rfc3339_pre_epoch() {
+    // Half a second before the unix epoch.
+    let instant = Instant::new(0, -500_000_000);
+    assert_eq!(instant.format_rfc3339(SubsecPrecision::Millis), "1969-12-31T23:59:59.500Z");
+
+    // 1900-01-01T00:00:00Z, the ntp prime epoch.
+    let instant = Instant::new(-nippy::EPOCH_DELTA, 0);
+    assert_eq!(instant.format_rfc3339(SubsecPrecision::Seconds), "1900-01-01T00:00:00Z");
+}
+
+// A small deterministic generator of pseudo-random durations of up to about 46 days.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_mul(6_364_136_223_846_793_005);
+        self.0 = self.0.wrapping_add(1_442_695_040_888_963_407);
+        self.0 >> 16
+    }
+
+    fn duration(&mut self) -> SignedDuration {
+        let range = 8_000_000_000_000_000;
+        SignedDuration::from_nanos((self.next() % range) as i128 - (range / 2) as i128)
+    }
+}
+
+#[test]
+fn instant_addition_is_associative() {
+    let mut rng = Lcg(0x5eed);
+    for _ in 0..10_000 {
+        let a = Instant::new(0, 0) + rng.duration();
+        let (d1, d2) = (rng.duration(), rng.duration());
+        let lhs = (a + d1) + d2;
+        assert_eq!(lhs, a + (d1 + d2));
+        assert!(lhs.subsec_nanos().abs() < 1_000_000_000);
+        assert!(lhs.secs() * lhs.subsec_nanos() as i64 >= 0);
+        assert_eq!(lhs - d2 - d1, a);
+    }
+}
+
+#[test]
+fn instant_from_pre_unix_epoch_timestamp() {
+    // Half a second past the ntp prime epoch.
+    let instant = Instant::from(TimestampFormat { seconds: 0, fraction: 1 << 31 });
+    assert_eq!(instant.secs(), -nippy::EPOCH_DELTA + 1);
+    assert_eq!(instant.subsec_nanos(), -500_000_000);
+
+    // The largest fraction rounds to a whole second, which is carried into the seconds.
+    let instant = Instant::from(TimestampFormat { seconds: 3_786_825_600, fraction: u32::MAX });
+    assert_eq!(instant, Instant::new(1_577_836_801, 0));
+
+    // Every nanosecond survives the round trip through a timestamp exactly.
+    for &nanos in &[0, 1, 2, 123_456_789, 500_000_000, 999_999_998, 999_999_999] {
+        for &secs in &[-2_208_988_799, -1, 0, 1_577_836_800] {
+            let instant = Instant::new(secs, if secs < 0 { -nanos } else { nanos });
+            let timestamp = TimestampFormat::from_instant_checked(instant).unwrap();
+            assert_eq!(Instant::from(timestamp), instant);
+        }
+    }
+}
+
+#[test]
+fn estimated_resolution_is_sane() {
+    let resolution = Instant::estimate_resolution();
+    assert!((-30..=-6).contains(&resolution), "resolution {}", resolution);
+}
+
+#[test]
+fn signed_duration_scaled_for_drift() {
+    let hour = SignedDuration::new(3600, 0);
+    assert_eq!(hour.scaled_by_ppm(50.0), SignedDuration::new(0, 180_000_000));
+    assert_eq!(hour.scaled_by_ppm(-50.0), SignedDuration::new(0, -180_000_000));
+    assert_eq!(-hour * 0.5, SignedDuration::new(-1800, 0));
+    assert_eq!(SignedDuration::new(0, 3) * 0.5, SignedDuration::new(0, 2));
+}
+
+#[test]
+fn instant_round_and_truncate_to_precision() {
+    let ten_millis = Duration::from_millis(10);
+    let instant = Instant::new(1_600_000_000, 12_600_000);
+    assert_eq!(instant.round_to(ten_millis), Instant::new(1_600_000_000, 10_000_000));
+    assert_eq!(instant.truncate_to(ten_millis), Instant::new(1_600_000_000, 10_000_000));
+    let instant = Instant::new(1_600_000_000, 15_000_000);
+    assert_eq!(instant.round_to(ten_millis), Instant::new(1_600_000_000, 20_000_000));
+
+    // Rounding up crosses into the next second.
+    let instant = Instant::new(1_600_000_000, 999_600_000);
+    let millis = Duration::from_millis(1);
+    assert_eq!(instant.round_to(millis), Instant::new(1_600_000_001, 0));
+    assert_eq!(instant.truncate_to(millis), Instant::new(1_600_000_000, 999_000_000));
+
+    // Before the epoch, rounding is in the same direction in time.
+    let instant = Instant::new(0, -12_600_000);
+    assert_eq!(instant.round_to(ten_millis), Instant::new(0, -10_000_000));
+    assert_eq!(instant.truncate_to(ten_millis), Instant::new(0, -20_000_000));
+    let instant = Instant::new(-1, -999_600_000);
+    assert_eq!(instant.round_to(millis), Instant::new(-2, 0));
+
+    assert_eq!(instant.round_to(Duration::from_secs(0)), instant);
+    assert_eq!(instant.truncate_to(Duration::from_secs(0)), instant);
+}
+
+#[test]
+fn timestamp_to_instant_in_era() {
+    // Half a second past 1 January 2040, 123_010_304 seconds into era 1.
+    let timestamp = TimestampFormat { seconds: 123_010_304, fraction: 1 << 31 };
+    let secs_2040 = 2_208_988_800;
+    let in_2040 = Instant::new(secs_2040, 500_000_000);
+    let in_1904 = Instant::new(secs_2040 - (1 << 32) + 1, -500_000_000);
+    assert_eq!(timestamp.to_instant_in_era(1), in_2040);
+    assert_eq!(timestamp.to_instant_in_era(0), in_1904);
+    assert_eq!(TimestampFormat::from_instant_checked(in_2040).unwrap(), timestamp);
+
+    // The era nearest the pivot is chosen, switching 68 years from the timestamp.
+    assert_eq!(timestamp.to_instant_near(Instant::new(2_000_000_000, 0)), in_2040);
+    let halfway = secs_2040 - (1 << 31);
+    assert_eq!(timestamp.to_instant_near(Instant::new(halfway + 1, 0)), in_2040);
+    assert_eq!(timestamp.to_instant_near(Instant::new(halfway - 1, 0)), in_1904);
+    assert_eq!(timestamp.to_instant_near(Instant::new(0, 0)), in_1904);
+}
+
+#[test]
+fn fraction_saturates_at_last_nanosecond() {
+    let instant = Instant::new(1_600_000_000, 999_999_999);
+    let timestamp = TimestampFormat::from_instant_checked(instant).unwrap();
+    assert_eq!(timestamp.seconds, 1_600_000_000 + 2_208_988_800);
+    // A nanosecond is over four units of a 32 bit fraction, so the nearest fraction falls short
+    // of the maximum but must not wrap to zero.
+    assert_eq!(timestamp.fraction, u32::MAX - 3);
+
+    // Rounding to 16 bits would carry into the seconds.
+    let short = ShortFormat::from(instant);
+    assert_eq!(short.seconds, (1_600_000_000u64 + 2_208_988_800) as u16);
+    assert_eq!(short.fraction, u16::MAX);
+    assert_eq!(ShortFormat::from(Instant::new(1_600_000_000, 500_000_000)).fraction, 1 << 15);
+    let before_epoch = ShortFormat::from(Instant::new(0, -1));
+    assert_eq!(before_epoch.seconds, (2_208_988_800u64 - 1) as u16);
+    assert_eq!(before_epoch.fraction, u16::MAX);
+}
+
+#[test]
+fn short_format_round_trip() {
+    let short = ShortFormat { seconds: 1, fraction: 0x8000 };
+    // 1.5s after the prime epoch, which precedes the unix epoch.
+    let instant = Instant::from(short);
+    assert_eq!(instant, Instant::new(1 - 2_208_988_800 + 1, -500_000_000));
+    assert_eq!(ShortFormat::from(instant), short);
+    for fraction in [0, 1, 0x1234, 0xfffe, u16::MAX] {
+        let short = ShortFormat { seconds: 1, fraction };
+        assert_eq!(ShortFormat::from(Instant::from(short)), short);
+    }
+}
+
+#[test]
+fn signed_duration_negative_values_and_arithmetic() {
+    let ahead = SignedDuration::new(-1, -250_000_000);
+    assert!(ahead.is_negative());
+    assert_eq!(ahead.as_secs_f64(), -1.25);
+    assert_eq!(ahead.abs(), SignedDuration::new(1, 250_000_000));
+    assert_eq!(ahead.unsigned_abs(), Duration::from_millis(1250));
+    assert!(!SignedDuration::ZERO.is_negative());
+    assert!(!SignedDuration::new(0, 1).is_negative());
+    assert!(SignedDuration::new(0, -1).is_negative());
+
+    let behind = SignedDuration::from(Duration::from_millis(500));
+    assert_eq!(behind, SignedDuration::new(0, 500_000_000));
+    assert_eq!(ahead + behind, SignedDuration::new(0, -750_000_000));
+    assert_eq!(behind - ahead, SignedDuration::new(1, 750_000_000));
+    assert_eq!(-ahead, ahead.abs());
+    let mut sum = ahead;
+    sum += behind;
+    sum -= behind;
+    assert_eq!(sum, ahead);
+    assert!(ahead < SignedDuration::ZERO && SignedDuration::ZERO < behind);
+
+    // The difference of two instants is a signed duration of either sign.
+    let a = Instant::new(1_600_000_000, 100_000_000);
+    let b = Instant::new(1_600_000_001, 350_000_000);
+    assert_eq!(b - a, SignedDuration::new(1, 250_000_000));
+    assert_eq!(a - b, ahead);
+    assert_eq!(a + (b - a), b);
+}
+
+#[test]
+fn invalid_components_are_errors() {
+    assert_eq!(Instant::try_new(-5, -1).unwrap(), Instant::new(-5, -1));
+    // Nanoseconds of a second or more would break the ordering of instants.
+    for &(secs, subsec_nanos) in &[(1, -1), (-1, 1), (0, 1_500_000_000), (-1, -1_000_000_000)] {
+        match Instant::try_new(secs, subsec_nanos) {
+            Err(Error::InvalidComponents(reason)) => assert!(reason.contains("invalid instant")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    assert_eq!(SignedDuration::try_new(0, -1).unwrap(), SignedDuration::new(0, -1));
+    // The most negative `subsec_nanos` once overflowed taking its absolute value.
+    for &(secs, subsec_nanos) in &[(1, -1), (-1, 1), (0, 1_000_000_000), (0, i32::MIN)] {
+        match SignedDuration::try_new(secs, subsec_nanos) {
+            Err(Error::InvalidComponents(reason)) => assert!(reason.contains("invalid duration")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn extreme_instants_convert_without_overflow() {
+    let latest = Instant::new(i64::MAX, 999_999_999);
+    let earliest = Instant::new(i64::MIN, -999_999_999);
+    // The seconds saturate rather than wrapping, and the fraction rather than carrying.
+    let (secs, fraction) = (u32::MAX as u64, u32::MAX as u64 - 3);
+    assert_eq!(TimestampFormat::from(latest).fraction, fraction as u32);
+    assert_eq!(ShortFormat::from(latest), ShortFormat { seconds: u16::MAX, fraction: u16::MAX });
+    assert_eq!(latest.to_ntp64(), secs << 32 | fraction);
+    let timestamp = TimestampFormat::from_instant_checked(latest).unwrap();
+    assert_eq!(timestamp, TimestampFormat { seconds: secs as u32, fraction: fraction as u32 });
+    assert_eq!(ShortFormat::from(earliest).fraction, 0);
+    match TimestampFormat::from_instant_checked(earliest) {
+        Err(Error::BeforePrimeEpoch(instant)) => assert_eq!(instant, earliest),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // Pivots this far from the present once overflowed choosing the era. The era nearest the
+    // latest instant ends beyond it, so that the result saturates.
+    let timestamp = TimestampFormat { seconds: 1, fraction: 0 };
+    assert_eq!(timestamp.to_instant_near(latest), latest);
+    let near_earliest = timestamp.to_instant_near(earliest);
+    assert!(near_earliest.secs() as i128 - (i64::MIN as i128) < 1 << 31);
+    assert_eq!(near_earliest.subsec_nanos(), 0);
+    assert_eq!(SignedDuration::from_nanos(i128::MAX), SignedDuration::new(i64::MAX, 999_999_999));
+}
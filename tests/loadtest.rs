@@ -0,0 +1,76 @@
+#![cfg(all(feature = "loadtest", feature = "async-std"))]
+
+extern crate nippy;
+extern crate rand;
+
+mod common;
+
+use async_std::task;
+use common::MockServer;
+use nippy::protocol::{build_client_batch, ReadBytes, TimestampFormat};
+use nippy::{Error, NtpClient};
+use rand::rngs::mock::StepRng;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[test]
+fn client_batch_packets_are_distinct() {
+    let batch = build_client_batch(100);
+    assert_eq!(batch.len(), 100);
+    let nonces: HashSet<&[u8]> = batch.iter().map(|packet| &packet[40..48]).collect();
+    assert_eq!(nonces.len(), 100);
+    for packet in &batch {
+        assert_eq!(packet[0], 0x23);
+        assert!(packet[1..40].iter().all(|&byte| byte == 0));
+    }
+}
+
+#[test]
+fn flood_tallies_responses() {
+    // A server answering every other request.
+    let mut count = 0;
+    let server = MockServer::start_raw(move |request| {
+        count += 1;
+        if count % 2 == 0 {
+            return None;
+        }
+        let transmit: TimestampFormat = (&request[40..48]).read_bytes().unwrap();
+        Some(common::to_bytes(&common::reply(transmit)))
+    });
+
+    let client = NtpClient::new().timeout(Duration::from_millis(200));
+    let report = task::block_on(client.flood(server.addr(), 200, Duration::from_millis(100)));
+    let report = report.unwrap();
+    assert_eq!(report.sent, 20);
+    assert_eq!(report.received, 10);
+    assert_eq!(report.unmatched, 0);
+    let (min, mean, max) = (report.min_latency, report.mean_latency, report.max_latency);
+    assert!(min <= mean && mean <= max, "{:?}", report);
+    assert!(max.unwrap() < Duration::from_millis(200));
+
+    match task::block_on(client.flood(server.addr(), 0, Duration::from_secs(1))) {
+        Err(Error::Io(_)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn flood_nonces_are_consecutive_from_the_rng() {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let seen = requests.clone();
+    let server = MockServer::start_raw(move |request| {
+        seen.lock().unwrap().push(request.to_vec());
+        let transmit: TimestampFormat = (&request[40..48]).read_bytes().unwrap();
+        Some(common::to_bytes(&common::reply(transmit)))
+    });
+
+    let client = NtpClient::new().timeout(Duration::from_millis(200)).rng(StepRng::new(1000, 0));
+    let report = task::block_on(client.flood(server.addr(), 200, Duration::from_millis(50)));
+    assert_eq!(report.unwrap().received, 10);
+    let requests = requests.lock().unwrap();
+    for (i, request) in requests.iter().enumerate() {
+        assert_eq!(request[..40], build_client_batch(1)[0][..40]);
+        assert_eq!(request[40..48], (1000 + i as u64).to_be_bytes());
+    }
+}
@@ -0,0 +1,275 @@
+extern crate nippy;
+
+use nippy::protocol::{
+    LeapIndicator, LogSeconds, Mode, Packet, ReferenceIdentifier, ShortFormat, Stratum,
+    TimestampFormat, Version,
+};
+use nippy::{
+    ntp_offset_delay, BatchResult, DisciplineAction, Error, Instant, NtpMeasurement,
+    SelectionPolicy, SignedDuration,
+};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+fn timestamp(secs: i64, subsec_nanos: i32) -> TimestampFormat {
+    TimestampFormat::from_instant_checked(Instant::new(secs, subsec_nanos)).unwrap()
+}
+
+// An exchange in which the local clock is 5ms ahead of the server and the delay is 20ms.
+fn measurement() -> NtpMeasurement {
+    let packet = Packet {
+        leap_indicator: LeapIndicator::NoWarning,
+        version: Version::V4,
+        mode: Mode::Server,
+        stratum: Stratum(2),
+        poll: LogSeconds(0),
+        precision: LogSeconds(-20),
+        root_delay: ShortFormat::default(),
+        root_dispersion: ShortFormat::default(),
+        reference_id: ReferenceIdentifier::SecondaryOrClient([127, 0, 0, 1]),
+        reference_timestamp: timestamp(1_600_000_000, 0),
+        origin_timestamp: timestamp(1_600_000_100, 0),
+        receive_timestamp: timestamp(1_600_000_100, 5_000_000),
+        transmit_timestamp: timestamp(1_600_000_100, 6_000_000),
+    };
+    NtpMeasurement {
+        packet,
+        sent: Instant::new(1_600_000_100, 0),
+        received: Instant::new(1_600_000_100, 21_000_000),
+        round_trip: Duration::from_millis(21),
+        interleaved: false,
+        delay_asymmetry: 0.0,
+    }
+}
+
+#[test]
+fn measurement_corrects_local_instants() {
+    let measurement = measurement();
+    let offset = measurement.offset();
+    assert_eq!((offset.secs(), offset.subsec_nanos()), (0, -5_000_000));
+
+    let corrected = measurement.correct(Instant::new(1_600_000_200, 2_000_000));
+    assert_eq!((corrected.secs(), corrected.subsec_nanos()), (1_600_000_199, 997_000_000));
+
+    let locals = [
+        Instant::new(1_600_000_000, 0),
+        Instant::new(1_600_000_000, 999_999_999),
+        Instant::new(0, 3_000_000),
+        Instant::new(-1, -500_000_000),
+    ];
+    let expected = [
+        (1_599_999_999, 995_000_000),
+        (1_600_000_000, 994_999_999),
+        (0, -2_000_000),
+        (-1, -505_000_000),
+    ];
+    let corrected = measurement.correct_all(&locals);
+    for (instant, &expected) in corrected.iter().zip(&expected) {
+        assert_eq!((instant.secs(), instant.subsec_nanos()), expected);
+    }
+}
+
+#[test]
+fn measurement_correction_of_server_transmit() {
+    // The server transmitted 1ms after receiving, and the response is taken to have spent half
+    // of the 20ms delay on its way back.
+    let measurement = measurement();
+    assert_eq!(measurement.server_transmit(), Instant::new(1_600_000_100, 6_000_000));
+    assert_eq!(measurement.correction(), SignedDuration::from_nanos(10_000_000));
+    let corrected = measurement.correct(measurement.received);
+    assert_eq!(measurement.server_transmit() + measurement.correction(), corrected);
+    assert_eq!(corrected, Instant::new(1_600_000_100, 16_000_000));
+
+    let asymmetric = NtpMeasurement { delay_asymmetry: 0.5, ..measurement };
+    let corrected = asymmetric.correct(asymmetric.received);
+    assert_eq!(asymmetric.server_transmit() + asymmetric.correction(), corrected);
+}
+
+#[test]
+fn measurement_delay_ignores_wall_clock_step() {
+    let measurement = measurement();
+    let delay = measurement.delay();
+    assert_eq!(delay, SignedDuration::from_nanos(20_000_000));
+
+    // The wall clock is stepped back 10s while the request is in flight.
+    let stepped = NtpMeasurement {
+        received: Instant::new(1_600_000_090, 21_000_000),
+        ..measurement
+    };
+    assert_eq!(stepped.delay(), delay);
+}
+
+// A measurement with no delay in which the local clock is behind the server by `nanos`.
+fn measurement_with_offset(nanos: i128) -> NtpMeasurement {
+    let mut packet = measurement().packet;
+    packet.receive_timestamp = timestamp(1_600_000_100, 0);
+    packet.transmit_timestamp = packet.receive_timestamp;
+    let local = Instant::new(1_600_000_100, 0) + -SignedDuration::from_nanos(nanos);
+    NtpMeasurement {
+        packet,
+        sent: local,
+        received: local,
+        round_trip: Duration::from_secs(0),
+        ..measurement()
+    }
+}
+
+#[test]
+fn measurement_discipline_action_thresholds() {
+    let step_threshold = Duration::from_millis(128);
+    let action = |nanos| measurement_with_offset(nanos).discipline_action(step_threshold);
+    let offset = SignedDuration::from_nanos;
+    assert_eq!(action(0), DisciplineAction::Slew(offset(0)));
+    assert_eq!(action(128_000_000), DisciplineAction::Slew(offset(128_000_000)));
+    assert_eq!(action(-128_000_000), DisciplineAction::Slew(offset(-128_000_000)));
+    assert_eq!(action(128_000_001), DisciplineAction::Step(offset(128_000_001)));
+    assert_eq!(action(-128_000_001), DisciplineAction::Step(offset(-128_000_001)));
+    assert_eq!(action(1_000_000_000_000), DisciplineAction::Step(offset(1_000_000_000_000)));
+    assert_eq!(action(1_000_000_000_001), DisciplineAction::Panic(offset(1_000_000_000_001)));
+    assert_eq!(action(-1_000_000_000_001), DisciplineAction::Panic(offset(-1_000_000_000_001)));
+}
+
+#[test]
+fn measurement_from_exchange_offset_and_delay() {
+    let mut packet = measurement().packet;
+    packet.receive_timestamp = timestamp(1_600_000_100, 500_000_000);
+    packet.transmit_timestamp = timestamp(1_600_000_100, 750_000_000);
+    let t1 = Instant::new(1_600_000_100, 0);
+    let t4 = Instant::new(1_600_000_101, 0);
+
+    // offset = ((T2 - T1) + (T3 - T4)) / 2 = (0.5 - 0.25) / 2, delay = (T4 - T1) - (T3 - T2).
+    let measurement = NtpMeasurement::from_exchange(t1, &packet, t4).unwrap();
+    assert_eq!(measurement.offset(), SignedDuration::from_nanos(125_000_000));
+    assert_eq!(measurement.delay(), SignedDuration::from_nanos(750_000_000));
+    assert_eq!(measurement.round_trip, Duration::from_secs(1));
+
+    match NtpMeasurement::from_exchange(t4, &packet, t1) {
+        Err(Error::InvalidPacket(_)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    packet.transmit_timestamp = timestamp(1_600_000_100, 250_000_000);
+    match NtpMeasurement::from_exchange(t1, &packet, t4) {
+        Err(Error::InvalidPacket(_)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn measurement_from_exchange_across_2036_rollover() {
+    // The server receives the request just before the era wraps and replies just after it.
+    let mut packet = measurement().packet;
+    packet.receive_timestamp = TimestampFormat { seconds: u32::MAX, fraction: 0x8000_0000 };
+    packet.transmit_timestamp = TimestampFormat { seconds: 0, fraction: 0x8000_0000 };
+    let t1 = Instant::new(i64::from(u32::MAX) - nippy::EPOCH_DELTA, 0);
+    let t4 = Instant::new(i64::from(u32::MAX) - nippy::EPOCH_DELTA + 2, 0);
+
+    let measurement = NtpMeasurement::from_exchange(t1, &packet, t4).unwrap();
+    assert_eq!(measurement.offset(), SignedDuration::ZERO);
+    assert_eq!(measurement.delay(), SignedDuration::from_nanos(1_000_000_000));
+}
+
+#[test]
+fn measurement_offset_is_exact_at_large_timestamps() {
+    // A server 3000 * 2^-32 s (698.49...ns) ahead of a local clock in 2093, with no delay.
+    let mut packet = measurement().packet;
+    packet.receive_timestamp = TimestampFormat { seconds: 3_900_000_000, fraction: 3000 };
+    packet.transmit_timestamp = packet.receive_timestamp;
+    let local = Instant::new(3_900_000_000 - nippy::EPOCH_DELTA, 0);
+    let measurement = NtpMeasurement::from_exchange(local, &packet, local).unwrap();
+    assert_eq!(measurement.offset(), SignedDuration::from_nanos(698));
+    assert_eq!(measurement.delay(), SignedDuration::ZERO);
+
+    // Converting every timestamp through `f64` seconds loses the sub-microsecond offset.
+    let secs_f64 = |t: TimestampFormat| t.seconds as f64 + t.fraction as f64 / 4_294_967_296.0;
+    let local_f64 = secs_f64(TimestampFormat { seconds: 3_900_000_000, fraction: 0 });
+    let t2 = secs_f64(packet.receive_timestamp);
+    let offset_f64 = ((t2 - local_f64) + (t2 - local_f64)) / 2.0;
+    assert_eq!((offset_f64 * 1e9).round() as i128, 477);
+
+    // T2 and T3 are 1 * 2^-32 s (0.23ns) and 3 * 2^-32 s (0.70ns) past the second, which round
+    // to nanoseconds in opposite directions: rounding them before the arithmetic gives an offset
+    // of (0 + 1) / 2 rounded up to 1ns rather than 0.47ns rounded down.
+    packet.receive_timestamp = TimestampFormat { seconds: 3_900_000_000, fraction: 1 };
+    packet.transmit_timestamp = TimestampFormat { seconds: 3_900_000_000, fraction: 3 };
+    let measurement = NtpMeasurement::from_exchange(local, &packet, local).unwrap();
+    assert_eq!(measurement.offset(), SignedDuration::ZERO);
+    let (t2, t3) = (packet.receive_timestamp, packet.transmit_timestamp);
+    let (t2, t3) = (t2.to_instant_near(local), t3.to_instant_near(local));
+    let (rounded, _) = ntp_offset_delay(local, t2, t3, local);
+    assert_eq!(rounded, SignedDuration::from_nanos(1));
+}
+
+#[test]
+fn measurement_offset_corrects_delay_asymmetry() {
+    let symmetric = measurement();
+    assert_eq!(symmetric.offset(), SignedDuration::from_nanos(-5_000_000));
+
+    // Of the 20ms delay, 12.5ms is spent on the way to the server and 7.5ms on the way back.
+    let asymmetric = NtpMeasurement { delay_asymmetry: 2.0 / 3.0, ..symmetric };
+    assert_eq!(asymmetric.offset(), SignedDuration::from_nanos(-7_500_000));
+    assert_eq!(asymmetric.delay(), symmetric.delay());
+
+    let reversed = NtpMeasurement { delay_asymmetry: -0.4, ..symmetric };
+    assert_eq!(reversed.offset(), SignedDuration::from_nanos(-2_500_000));
+}
+
+#[test]
+fn ntp_offset_delay_hand_computed() {
+    let at = |millis: i64| {
+        Instant::new(1_600_000_000 + millis / 1000, millis as i32 % 1000 * 1_000_000)
+    };
+    let millis = |millis: i128| SignedDuration::from_nanos(millis * 1_000_000);
+
+    // The local clock is 1s behind the server: T1 = 0, T2 = 1.010, T3 = 1.012, T4 = 0.030.
+    // offset = (1.010 + (1.012 - 0.030)) / 2 = 0.996, delay = 0.030 - 0.002 = 0.028.
+    let (offset, delay) = ntp_offset_delay(at(0), at(1010), at(1012), at(30));
+    assert_eq!(offset, millis(996));
+    assert_eq!(delay, Duration::from_millis(28));
+
+    // The local clock is 250ms ahead of the server: T1 = 1.000, T2 = 0.760, T3 = 0.770,
+    // T4 = 1.030. offset = (-0.240 + -0.260) / 2 = -0.250, delay = 0.030 - 0.010 = 0.020.
+    let (offset, delay) = ntp_offset_delay(at(1000), at(760), at(770), at(1030));
+    assert_eq!(offset, millis(-250));
+    assert_eq!(delay, Duration::from_millis(20));
+
+    // An odd number of nanoseconds rounds the half nanosecond up, and a server whose clock runs
+    // fast over the exchange has a delay of zero.
+    let t = Instant::new(1_600_000_000, 0);
+    let (offset, delay) = ntp_offset_delay(t, t, Instant::new(1_600_000_000, 3), t);
+    assert_eq!(offset, SignedDuration::from_nanos(2));
+    assert_eq!(delay, Duration::from_secs(0));
+    let (offset, delay) = ntp_offset_delay(t, Instant::new(1_599_999_999, 999_999_997), t, t);
+    assert_eq!(offset, SignedDuration::from_nanos(-1));
+    assert_eq!(delay, Duration::from_secs(0));
+}
+
+#[test]
+fn batch_select_follows_policy() {
+    // A stratum 1 server 80ms away, a stratum 3 server 10ms away and a stratum 2 server 30ms
+    // away whose root dispersion makes it the least accurate.
+    let server = |stratum: u8, delay_ms: u64, root_dispersion: u16| {
+        let mut measurement = measurement();
+        measurement.packet.stratum = Stratum(stratum);
+        measurement.packet.root_dispersion = ShortFormat { seconds: root_dispersion, fraction: 0 };
+        measurement.round_trip = Duration::from_millis(delay_ms + 1);
+        let addr: SocketAddr = ([192, 0, 2, stratum], 123).into();
+        (addr, measurement)
+    };
+    let batch = BatchResult {
+        ok: vec![server(1, 80, 0), server(3, 10, 0), server(2, 30, 1)],
+        failed: Vec::new(),
+    };
+    let winner = |policy| batch.select(policy).unwrap().1.packet.stratum;
+
+    assert_eq!(winner(SelectionPolicy::default()), Stratum(3));
+    assert_eq!(winner(SelectionPolicy::LowestDelay), Stratum(3));
+    let max_delay = Duration::from_millis(100);
+    assert_eq!(winner(SelectionPolicy::LowestStratum { max_delay }), Stratum(1));
+    let max_delay = Duration::from_millis(50);
+    assert_eq!(winner(SelectionPolicy::LowestStratum { max_delay }), Stratum(2));
+    let max_delay = Duration::from_millis(5);
+    assert!(batch.select(SelectionPolicy::LowestStratum { max_delay }).is_none());
+    assert_eq!(winner(SelectionPolicy::BestQualityScore), Stratum(3));
+    let addr = |selected: Option<&(SocketAddr, NtpMeasurement)>| selected.unwrap().0;
+    assert_eq!(addr(batch.select(SelectionPolicy::BestQualityScore)), addr(batch.best()));
+}
@@ -0,0 +1,51 @@
+#![cfg(feature = "async-std")]
+
+extern crate nippy;
+
+mod common;
+
+use async_std::task;
+use nippy::multicast::{MulticastListener, LINK_LOCAL_GROUP};
+use nippy::protocol::{Mode, Packet, TimestampFormat};
+use std::net::{Ipv6Addr, SocketAddr};
+
+#[test]
+fn multicast_listener_yields_broadcast_packets() {
+    let listener = match task::block_on(MulticastListener::bind(0, LINK_LOCAL_GROUP, 0)) {
+        Ok(listener) => listener,
+        // The host has no IPv6 multicast capable interface.
+        Err(err) => return eprintln!("skipping multicast test: {}", err),
+    };
+    let port = listener.local_addr().unwrap().port();
+    let dest = SocketAddr::from((Ipv6Addr::LOCALHOST, port));
+
+    // Datagrams sent directly to the port stand in for the server's multicast packets.
+    let server = std::net::UdpSocket::bind("[::1]:0").unwrap();
+    let server_reply = common::reply(TimestampFormat::default());
+    let broadcast = Packet { mode: Mode::Broadcast, ..server_reply };
+    server.send_to(&[0u8; 12], dest).unwrap();
+    server.send_to(&common::to_bytes(&server_reply), dest).unwrap();
+    server.send_to(&common::to_bytes(&broadcast), dest).unwrap();
+
+    let (packet, src) = task::block_on(listener.recv()).unwrap();
+    assert_eq!(packet, broadcast);
+    assert_eq!(src, server.local_addr().unwrap());
+}
+
+#[test]
+fn dropping_multicast_listener_releases_port() {
+    let listener = match task::block_on(MulticastListener::bind(0, LINK_LOCAL_GROUP, 0)) {
+        Ok(listener) => listener,
+        Err(err) => return eprintln!("skipping multicast test: {}", err),
+    };
+    let port = listener.local_addr().unwrap().port();
+    assert!(task::block_on(MulticastListener::bind(port, LINK_LOCAL_GROUP, 0)).is_err());
+
+    // A pending receive is cancelled by dropping its future.
+    let timeout = std::time::Duration::from_millis(50);
+    assert!(task::block_on(async_std::future::timeout(timeout, listener.recv())).is_err());
+
+    drop(listener);
+    let rebound = task::block_on(MulticastListener::bind(port, LINK_LOCAL_GROUP, 0)).unwrap();
+    assert_eq!(rebound.local_addr().unwrap().port(), port);
+}
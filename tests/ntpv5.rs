@@ -0,0 +1,88 @@
+#![cfg(all(feature = "ntpv5", feature = "async-std"))]
+
+extern crate nippy;
+
+mod common;
+
+use async_std::task;
+use common::MockServer;
+use nippy::protocol::{
+    ConstPackedSizeBytes, LeapIndicator, LogSeconds, Mode, PacketV5, ReadBytes, Stratum,
+    Timescale, TimestampFormat, Version, WriteBytes,
+};
+use nippy::{Instant, NtpClient};
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn packet_v5_field_offsets() {
+    let packet = PacketV5 {
+        leap_indicator: LeapIndicator::AddOne,
+        version: Version::V5,
+        mode: Mode::Server,
+        stratum: Stratum(2),
+        poll: LogSeconds(6),
+        precision: LogSeconds(-20),
+        timescale: Timescale::Tai,
+        era: 1,
+        flags: PacketV5::FLAG_INTERLEAVED,
+        root_delay: 0x0180_0000,
+        root_dispersion: 0x0040_0000,
+        server_cookie: 0x0102_0304_0506_0708,
+        client_cookie: 0x1112_1314_1516_1718,
+        receive_timestamp: TimestampFormat { seconds: 0x2122_2324, fraction: 0x2526_2728 },
+        transmit_timestamp: TimestampFormat { seconds: 0x3132_3334, fraction: 0x3536_3738 },
+    };
+    let expected = [
+        0b01_101_100, 2, 6, 236, 1, 1, 0, 2, 0x01, 0x80, 0, 0, 0, 0x40, 0, 0, 1, 2, 3, 4, 5, 6,
+        7, 8, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26,
+        0x27, 0x28, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38,
+    ];
+    assert_eq!(PacketV5::PACKED_SIZE_BYTES, 48);
+    let mut bytes = [0u8; PacketV5::PACKED_SIZE_BYTES];
+    (&mut bytes[..]).write_bytes(packet).unwrap();
+    assert_eq!(bytes, expected);
+    assert_eq!((&expected[..]).read_bytes::<PacketV5>().unwrap(), packet);
+    assert_eq!(packet.root_delay_secs(), 0.09375);
+    assert_eq!(packet.root_dispersion_secs(), 0.015625);
+
+    let mut unknown_timescale = expected;
+    unknown_timescale[4] = 200;
+    assert!((&unknown_timescale[..]).read_bytes::<PacketV5>().is_err());
+}
+
+#[test]
+fn ntpv5_server_is_measured() {
+    let server = MockServer::start_raw(|bytes| {
+        let request: PacketV5 = (&bytes[..]).read_bytes().unwrap();
+        assert_eq!(request.version, Version::V5);
+        let now = TimestampFormat::from_instant_checked(Instant::now()).unwrap();
+        let response = PacketV5 {
+            mode: Mode::Server,
+            stratum: Stratum(2),
+            precision: LogSeconds(-20),
+            server_cookie: 42,
+            receive_timestamp: now,
+            transmit_timestamp: now,
+            ..PacketV5::request(request.client_cookie)
+        };
+        let mut buf = Vec::new();
+        buf.write_bytes(response).unwrap();
+        Some(buf)
+    });
+
+    let measurement = task::block_on(NtpClient::new().ntpv5(true).request(server.addr())).unwrap();
+    assert_eq!(measurement.packet.version, Version::V5);
+    assert_eq!(measurement.packet.stratum, Stratum(2));
+}
+
+#[test]
+fn ntpv5_falls_back_to_ntpv4() {
+    let versions = Arc::new(Mutex::new(Vec::new()));
+    let seen = versions.clone();
+    let server =
+        MockServer::start_patched(move |bytes, _| seen.lock().unwrap().push(bytes[0] >> 3 & 0b111));
+
+    let measurement = task::block_on(NtpClient::new().ntpv5(true).request(server.addr())).unwrap();
+    assert_eq!(measurement.packet.version, Version::V4);
+    assert_eq!(*versions.lock().unwrap(), vec![5, 4]);
+}
@@ -0,0 +1,36 @@
+extern crate nippy;
+
+use nippy::nts::{CookieJar, DEFAULT_COOKIE_COUNT};
+
+fn key_exchange_cookies(generation: u8) -> Vec<Vec<u8>> {
+    (0..DEFAULT_COOKIE_COUNT as u8).map(|i| vec![generation, i]).collect()
+}
+
+#[test]
+fn cookie_jar_stays_populated_across_requests() {
+    let mut jar = CookieJar::new(key_exchange_cookies(0));
+    for request in 0..10u8 {
+        let cookie = jar.pop().expect("jar ran dry");
+        assert!(!cookie.is_empty());
+        // Each authenticated response carries exactly one replacement cookie.
+        jar.push(vec![1, request]);
+        assert_eq!(jar.len(), DEFAULT_COOKIE_COUNT);
+        assert!(!jar.needs_key_exchange());
+    }
+}
+
+#[test]
+fn cookie_jar_requests_key_exchange_when_low() {
+    let mut jar = CookieJar::new(key_exchange_cookies(0));
+    let mut key_exchanges = 0;
+    for _ in 0..10 {
+        if jar.needs_key_exchange() {
+            key_exchanges += 1;
+            jar.refill(key_exchange_cookies(key_exchanges));
+        }
+        // Every response is lost, so no replacement cookie is pushed.
+        assert!(jar.pop().is_some());
+    }
+    assert_eq!(key_exchanges, 1);
+    assert!(!jar.is_empty());
+}
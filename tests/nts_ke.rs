@@ -0,0 +1,173 @@
+#![cfg(all(feature = "nts-rustls", feature = "async-std"))]
+
+extern crate nippy;
+
+use async_std::task;
+use nippy::nts::{KeyExchange, AEAD_AES_SIV_CMAC_256, NTS_KE_ALPN, PROTOCOL_NTPV4};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+
+// A self-signed certificate for `localhost`, and its key.
+const CERTIFICATE: &[u8] = include_bytes!("data/nts-ke-localhost.crt.der");
+const PRIVATE_KEY: &[u8] = include_bytes!("data/nts-ke-localhost.key.der");
+
+const LABEL: &[u8] = b"EXPORTER-network-time-security";
+
+// The request for NTPv4 with AEAD_AES_SIV_CMAC_256 that every exchange sends.
+const REQUEST: [u8; 16] = [0x80, 1, 0, 2, 0, 0, 0, 4, 0, 2, 0, 15, 0x80, 0, 0, 0];
+
+// What the server of a single exchange received and derived.
+struct Exchange {
+    request: Vec<u8>,
+    c2s_key: Vec<u8>,
+    s2c_key: Vec<u8>,
+}
+
+// Serve a single NTS-KE exchange on a loopback port, offering the given ALPN protocols and
+// answering the request with `response`.
+fn start_server(alpn: &[&[u8]], response: Vec<u8>) -> (u16, thread::JoinHandle<Exchange>) {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![CertificateDer::from(CERTIFICATE.to_vec())],
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(PRIVATE_KEY.to_vec())),
+        )
+        .unwrap();
+    config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (sock, _) = listener.accept().unwrap();
+        let conn = ServerConnection::new(Arc::new(config)).unwrap();
+        let mut stream = StreamOwned::new(conn, sock);
+        let mut request = Vec::new();
+        let mut buf = [0u8; 256];
+        while !request.ends_with(&[0x80, 0, 0, 0]) {
+            match stream.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(len) => request.extend_from_slice(&buf[..len]),
+            }
+        }
+        let key = |direction| {
+            let context = [0, 0, 0, 15, direction];
+            stream.conn.export_keying_material(vec![0; 32], LABEL, Some(&context[..]))
+        };
+        let (c2s_key, s2c_key) = match (key(0), key(1)) {
+            (Ok(c2s_key), Ok(s2c_key)) => (c2s_key, s2c_key),
+            _ => (Vec::new(), Vec::new()),
+        };
+        // The client may hang up as soon as it has what it needs.
+        let _ = stream.write_all(&response);
+        let _ = stream.flush();
+        Exchange { request, c2s_key, s2c_key }
+    });
+    (port, server)
+}
+
+fn record(record_type: u16, body: &[u8]) -> Vec<u8> {
+    let mut record = record_type.to_be_bytes().to_vec();
+    record.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    record.extend_from_slice(body);
+    record
+}
+
+fn response(records: &[Vec<u8>]) -> Vec<u8> {
+    let mut response = records.concat();
+    response.extend(record(0x8000, &[]));
+    response
+}
+
+fn accepted() -> Vec<Vec<u8>> {
+    vec![
+        record(0x8001, &PROTOCOL_NTPV4.to_be_bytes()),
+        record(4, &AEAD_AES_SIV_CMAC_256.to_be_bytes()),
+    ]
+}
+
+fn key_exchange() -> KeyExchange {
+    KeyExchange::new().add_root_certificate(CERTIFICATE.to_vec())
+}
+
+#[test]
+fn key_exchange_derives_keys_and_collects_cookies() {
+    let mut records = accepted();
+    records.push(record(5, &[1; 16]));
+    records.push(record(5, &[2; 16]));
+    // A non-critical record of an unknown type is ignored.
+    records.push(record(0x4000, &[9, 9]));
+    records.push(record(0x8006, b"ntp.example"));
+    records.push(record(0x8007, &1234u16.to_be_bytes()));
+    let (port, server) = start_server(&[NTS_KE_ALPN], response(&records));
+
+    let established = task::block_on(key_exchange().establish("localhost", port)).unwrap();
+    let exchange = server.join().unwrap();
+    assert_eq!(exchange.request, REQUEST);
+    assert_eq!(established.c2s_key.len(), 32);
+    assert_eq!(established.c2s_key, exchange.c2s_key);
+    assert_eq!(established.s2c_key, exchange.s2c_key);
+    assert_ne!(established.c2s_key, established.s2c_key);
+    assert_eq!(established.cookies, vec![vec![1; 16], vec![2; 16]]);
+    assert_eq!(established.server.as_deref(), Some("ntp.example"));
+    assert_eq!(established.port, Some(1234));
+}
+
+#[test]
+fn key_exchange_requires_ntske_alpn() {
+    let mut records = accepted();
+    records.push(record(5, &[1; 16]));
+    let (port, server) = start_server(&[], response(&records));
+
+    let err = task::block_on(key_exchange().establish("localhost", port)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let _ = server.join();
+}
+
+#[test]
+fn key_exchange_verifies_the_server_certificate() {
+    let (port, server) = start_server(&[NTS_KE_ALPN], response(&accepted()));
+
+    let err = task::block_on(KeyExchange::new().establish("localhost", port)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let _ = server.join();
+}
+
+#[test]
+fn key_exchange_rejects_unusable_responses() {
+    let cookie = record(5, &[1; 16]);
+    let invalid = io::ErrorKind::InvalidData;
+    let cases = vec![
+        // An error record.
+        (vec![record(0x8002, &[0, 1])], io::ErrorKind::Other),
+        // No protocol, or no algorithm, acceptable to the server.
+        (vec![record(0x8001, &[]), record(4, &[0, 15]), cookie.clone()], invalid),
+        (vec![record(0x8001, &[0, 0]), record(4, &[]), cookie.clone()], invalid),
+        // No cookies.
+        (accepted(), invalid),
+        // A critical record of an unknown type.
+        (vec![accepted().concat(), cookie, record(0x8040, &[])], invalid),
+    ];
+    for (records, kind) in cases {
+        let response = response(&records);
+        let (port, server) = start_server(&[NTS_KE_ALPN], response);
+        let err = task::block_on(key_exchange().establish("localhost", port)).unwrap_err();
+        assert_eq!(err.kind(), kind, "{}", err);
+        server.join().unwrap();
+    }
+}
+
+#[test]
+fn key_exchange_fails_when_the_server_hangs_up() {
+    let records = accepted();
+    let (port, server) = start_server(&[NTS_KE_ALPN], records.concat());
+
+    let err = task::block_on(key_exchange().establish("localhost", port)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    server.join().unwrap();
+}
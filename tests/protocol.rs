@@ -1,9 +1,19 @@
 extern crate nippy;
+extern crate rand;
 
 use nippy::protocol::{
-    LeapIndicator, Mode, ShortFormat, PrimarySource, ReadBytes, Packet, ReferenceIdentifier,
-    ConstPackedSizeBytes, Stratum, TimestampFormat, Version, WriteBytes,
+    LeapDirection, LeapIndicator, Mode, ShortFormat, PrimarySource, ReadBytes, Packet,
+    PacketReader, ReferenceIdentifier, ConstPackedSizeBytes, Stratum, TimestampFormat, Version,
+    RawExtensionField, SanityReport, WriteBytes, LogSeconds, NtpHeader, parse_transmit_timestamp,
+    quick_build_client, ExtensionField, NTS_AUTHENTICATOR_FIELD_TYPE, NTS_COOKIE_FIELD_TYPE,
+    NTS_COOKIE_PLACEHOLDER_FIELD_TYPE, UNIQUE_IDENTIFIER_FIELD_TYPE, SpecialReference,
+    parse_timestamp, TimestampSelector,
 };
+use nippy::{Error, Instant, SubsecPrecision};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::convert::TryFrom;
+use std::time::Duration;
 
 #[test]
 fn packet_from_bytes() {
@@ -17,8 +27,8 @@ fn packet_from_bytes() {
         version: Version::V2,
         mode: Mode::Server,
         stratum: Stratum::PRIMARY,
-        poll: 3,
-        precision: -16,
+        poll: LogSeconds(3),
+        precision: LogSeconds(-16),
         root_delay: ShortFormat { seconds: 0, fraction: 0 },
         root_dispersion: ShortFormat { seconds: 0, fraction: 24 },
         reference_id: ReferenceIdentifier::PrimarySource(PrimarySource::Cdma),
@@ -56,8 +66,8 @@ fn packet_to_bytes() {
         version: Version::V2,
         mode: Mode::Server,
         stratum: Stratum::PRIMARY,
-        poll: 3,
-        precision: -16,
+        poll: LogSeconds(3),
+        precision: LogSeconds(-16),
         root_delay: ShortFormat { seconds: 0, fraction: 0 },
         root_dispersion: ShortFormat { seconds: 0, fraction: 24 },
         reference_id: ReferenceIdentifier::PrimarySource(PrimarySource::Cdma),
@@ -79,7 +89,7 @@ fn packet_to_bytes() {
         },
     };
     let mut bytes = [0u8; Packet::PACKED_SIZE_BYTES];
-    (&mut bytes[..]).write_bytes(&input).unwrap();
+    (&mut bytes[..]).write_bytes(input).unwrap();
     assert_eq!(&bytes[..], &expected_output[..]);
 }
 
@@ -92,6 +102,724 @@ fn packet_conversion_roundtrip() {
     ];
     let packet = (&input[..]).read_bytes::<Packet>().unwrap();
     let mut output = [0u8; Packet::PACKED_SIZE_BYTES];
-    (&mut output[..]).write_bytes(&packet).unwrap();
+    (&mut output[..]).write_bytes(packet).unwrap();
     assert_eq!(&input[..], &output[..]);
 }
+
+#[test]
+fn short_format_decimal_seconds_roundtrip() {
+    for s in &["0.001", "1.5", "65535.999"] {
+        let short_format = s.parse::<ShortFormat>().unwrap();
+        assert_eq!(&short_format.to_string(), s);
+    }
+    let short_format = "1.5".parse::<ShortFormat>().unwrap();
+    assert_eq!(short_format, ShortFormat { seconds: 1, fraction: 0x8000 });
+    // 0.001 s is not exactly representable and rounds to the nearest 1/65536 s.
+    let short_format = "0.001".parse::<ShortFormat>().unwrap();
+    assert_eq!(short_format, ShortFormat { seconds: 0, fraction: 66 });
+}
+
+#[test]
+fn timestamp_format_decimal_seconds_roundtrip() {
+    let timestamp_format = TimestampFormat { seconds: 3619455089, fraction: 774086252 };
+    let s = timestamp_format.to_string();
+    assert_eq!(s, "3619455089.1802310003");
+    assert_eq!(s.parse::<TimestampFormat>().unwrap(), timestamp_format);
+}
+
+#[test]
+fn decimal_seconds_rejects_malformed() {
+    for s in &["", ".5", "1.2.3", "-1.5", " 1.5", "1.5s", "0x10", "65536.0", "65535.999995"] {
+        assert!(s.parse::<ShortFormat>().is_err(), "{:?} should not parse", s);
+    }
+    assert!("4294967296".parse::<TimestampFormat>().is_err());
+}
+
+#[test]
+fn packet_reference_age() {
+    let now = nippy::Instant::new(1_600_000_000, 250_000_000);
+    let hour_ago = nippy::Instant::new(1_600_000_000 - 3600, 250_000_000);
+    let input = [
+        20u8, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+        46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+        188, 128, 113, 46, 35, 158, 108,
+    ];
+    let mut packet = (&input[..]).read_bytes::<Packet>().unwrap();
+    packet.reference_timestamp = TimestampFormat::from_instant_checked(hour_ago).unwrap();
+    let age = packet.reference_age(now).unwrap();
+    assert!((age.as_secs_f64() - 3600.0).abs() < 1e-6);
+
+    packet.reference_timestamp = TimestampFormat::default();
+    assert!(packet.reference_age(now).is_none());
+}
+
+#[test]
+fn packet_timestamp_selector() {
+    let input = [
+        20u8, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+        46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+        188, 128, 113, 46, 35, 158, 108,
+    ];
+    let packet = (&input[..]).read_bytes::<Packet>().unwrap();
+    let reference = TimestampFormat { seconds: 0xD7BC_8069, fraction: 0xC6A9_2E63 };
+    let receive = TimestampFormat { seconds: 0xD7BC_8071, fraction: 0x2DEC_E62D };
+    let transmit = TimestampFormat { seconds: 0xD7BC_8071, fraction: 0x2E23_9E6C };
+    let expected = [
+        (TimestampSelector::Reference, reference),
+        (TimestampSelector::Receive, receive),
+        (TimestampSelector::Transmit, transmit),
+    ];
+    for &(selector, timestamp) in &expected {
+        assert_eq!(packet.timestamp(selector), timestamp);
+        assert_eq!(parse_timestamp(&input, selector).unwrap(), timestamp);
+        assert!(parse_timestamp(&input[..47], selector).is_err());
+    }
+    assert_eq!(TimestampSelector::default(), TimestampSelector::Transmit);
+}
+
+#[test]
+fn malformed_packets_are_errors() {
+    let input = [
+        20u8, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+        46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+        188, 128, 113, 46, 35, 158, 108,
+    ];
+    for len in 0..input.len() {
+        assert!((&input[..len]).read_bytes::<Packet>().is_err(), "{} bytes", len);
+        assert!(parse_transmit_timestamp(&input[..len]).is_err(), "{} bytes", len);
+    }
+    for stratum in 17..=u8::MAX {
+        let mut bytes = input;
+        bytes[1] = stratum;
+        assert!(Packet::try_from(bytes).is_err(), "stratum {}", stratum);
+    }
+
+    // Extension fields whose lengths are zero, not a multiple of four, or run past the packet,
+    // and a header cut short.
+    let fields: [&[u8]; 5] = [
+        &[0, 1, 0, 0],
+        &[0, 1, 0, 6, 0, 0],
+        &[0, 1, 0, 16, 0, 0, 0, 0],
+        &[0, 1, 0xff, 0xfc],
+        &[0, 1, 0],
+    ];
+    for field in &fields {
+        assert!(RawExtensionField::parse_all(field).is_err(), "{:?}", field);
+        assert!(ExtensionField::parse_all(field).is_err(), "{:?}", field);
+    }
+
+    // NTS authenticators whose nonce or ciphertext lengths exceed the field, or that are too
+    // short to hold the lengths.
+    let authenticators: [&[u8]; 3] = [&[0xff, 0xff, 0, 0], &[0, 4, 0xff, 0xff], &[0, 0]];
+    for value in &authenticators {
+        let raw = RawExtensionField {
+            field_type: NTS_AUTHENTICATOR_FIELD_TYPE,
+            value: value.to_vec(),
+        };
+        assert!(ExtensionField::decode(&raw).is_err(), "{:?}", value);
+    }
+}
+
+// As the `parse_packet` fuzz target does for arbitrary bytes, for packets mutated at random from a
+// valid one so that most still parse and reach the code interpreting them.
+#[test]
+fn mutated_packets_never_panic() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let valid = [
+        20u8, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+        46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+        188, 128, 113, 46, 35, 158, 108,
+    ];
+    let pivots = [Instant::now(), Instant::new(i64::MIN, -999_999_999), Instant::new(i64::MAX, 0)];
+    let selectors =
+        [TimestampSelector::Transmit, TimestampSelector::Receive, TimestampSelector::Reference];
+    for _ in 0..10_000 {
+        let mut bytes = valid.to_vec();
+        for _ in 0..rng.gen_range(1..8) {
+            let i = rng.gen_range(0..bytes.len());
+            bytes[i] = rng.gen();
+        }
+        // Occasionally follow the header with extension fields of random content.
+        if rng.gen_bool(0.25) {
+            bytes.extend((0..rng.gen_range(0..64)).map(|_| rng.gen::<u8>()));
+        }
+        bytes.truncate(rng.gen_range(40..=bytes.len()));
+
+        for &selector in &selectors {
+            let _ = parse_timestamp(&bytes, selector);
+        }
+        let packet = match (&bytes[..]).read_bytes::<Packet>() {
+            Ok(packet) => packet,
+            Err(_) => continue,
+        };
+        if let Ok(fields) = RawExtensionField::parse_all(&bytes[Packet::PACKED_SIZE_BYTES..]) {
+            for field in &fields {
+                let _ = ExtensionField::decode(field);
+            }
+        }
+        for &pivot in &pivots {
+            for &selector in &selectors {
+                let _ = packet.timestamp(selector).to_instant_near(pivot);
+            }
+            let _ = packet.reference_age(pivot);
+            let _ = packet.leap_second_date(pivot);
+        }
+        let _ = Instant::from(packet.root_delay);
+        let _ = packet.run_sanity_tests(packet.origin_timestamp);
+    }
+}
+
+#[test]
+fn packet_hops() {
+    let input = [
+        20u8, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+        46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+        188, 128, 113, 46, 35, 158, 108,
+    ];
+    let mut packet = (&input[..]).read_bytes::<Packet>().unwrap();
+    assert_eq!(packet.hops(), 0);
+    packet.stratum = Stratum(3);
+    assert_eq!(packet.hops(), 2);
+    packet.stratum = Stratum::UNSPECIFIED;
+    assert_eq!(packet.hops(), 0);
+}
+
+#[test]
+fn packet_pending_leap() {
+    let input = [
+        20u8, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+        46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+        188, 128, 113, 46, 35, 158, 108,
+    ];
+    let mut packet = (&input[..]).read_bytes::<Packet>().unwrap();
+    let cases = [
+        (LeapIndicator::NoWarning, None),
+        (LeapIndicator::AddOne, Some(LeapDirection::Insert)),
+        (LeapIndicator::SubOne, Some(LeapDirection::Delete)),
+        (LeapIndicator::Unknown, None),
+    ];
+    for &(leap_indicator, expected) in &cases {
+        packet.leap_indicator = leap_indicator;
+        assert_eq!(packet.pending_leap(), expected);
+    }
+}
+
+#[test]
+fn packet_leap_second_date() {
+    let input = [
+        20u8, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+        46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+        188, 128, 113, 46, 35, 158, 108,
+    ];
+    let mut packet = (&input[..]).read_bytes::<Packet>().unwrap();
+    packet.leap_indicator = LeapIndicator::NoWarning;
+    assert_eq!(packet.leap_second_date(Instant::new(1_465_992_000, 0)), None);
+
+    // Announced 2016-06-15T12:00:00Z, occurring after 2016-06-30T23:59:59Z.
+    packet.leap_indicator = LeapIndicator::AddOne;
+    let june = packet.leap_second_date(Instant::new(1_465_992_000, 0));
+    assert_eq!(june, Some(Instant::new(1_467_331_199, 0)));
+    assert_eq!(june.unwrap().format_rfc3339(SubsecPrecision::Seconds), "2016-06-30T23:59:59Z");
+
+    // Announced 2016-12-15T12:00:00Z, occurring after 2016-12-31T23:59:59Z.
+    let december = packet.leap_second_date(Instant::new(1_481_803_200, 0));
+    assert_eq!(december, Some(Instant::new(1_483_228_799, 0)));
+    assert_eq!(packet.leap_second_date(Instant::new(1_483_228_799, 0)), december);
+
+    packet.leap_indicator = LeapIndicator::SubOne;
+    assert_eq!(packet.leap_second_date(Instant::new(1_481_803_200, 0)), december);
+
+    // The end of the month containing the latest instant cannot be represented, and once
+    // overflowed.
+    assert_eq!(packet.leap_second_date(Instant::new(i64::MAX, 0)), None);
+}
+
+#[test]
+fn packet_reader_parses_concatenated_records() {
+    let input = [
+        20u8, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+        46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+        188, 128, 113, 46, 35, 158, 108,
+    ];
+    let mut stream = Vec::new();
+    for _ in 0..3 {
+        stream.extend_from_slice(&input);
+    }
+    stream.extend_from_slice(&input[..20]);
+
+    let expected: Packet = (&input[..]).read_bytes().unwrap();
+    let records: Vec<_> = PacketReader::new(&stream[..]).collect();
+    assert_eq!(records.len(), 4);
+    for record in &records[..3] {
+        assert_eq!(*record.as_ref().unwrap(), expected);
+    }
+    match records[3] {
+        Err(ref err) => assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof),
+        Ok(ref packet) => panic!("unexpected packet: {:?}", packet),
+    }
+}
+
+#[test]
+fn packet_golden_byte_layout() {
+    let input = Packet {
+        leap_indicator: LeapIndicator::AddOne,
+        version: Version::V4,
+        mode: Mode::Server,
+        stratum: Stratum(2),
+        poll: LogSeconds(6),
+        precision: LogSeconds(-23),
+        root_delay: ShortFormat { seconds: 0x0102, fraction: 0x0304 },
+        root_dispersion: ShortFormat { seconds: 0x0506, fraction: 0x0708 },
+        reference_id: ReferenceIdentifier::SecondaryOrClient([192, 0, 2, 1]),
+        reference_timestamp: TimestampFormat { seconds: 0x1011_1213, fraction: 0x1415_1617 },
+        origin_timestamp: TimestampFormat { seconds: 0x2021_2223, fraction: 0x2425_2627 },
+        receive_timestamp: TimestampFormat { seconds: 0x3031_3233, fraction: 0x3435_3637 },
+        transmit_timestamp: TimestampFormat { seconds: 0x4041_4243, fraction: 0x4445_4647 },
+    };
+    let mut bytes = [0u8; Packet::PACKED_SIZE_BYTES];
+    (&mut bytes[..]).write_bytes(input).unwrap();
+
+    // LI = 1 (bits 0-1), VN = 4 (bits 2-4), Mode = 4 (bits 5-7).
+    assert_eq!(bytes[0], 0b01_100_100);
+    assert_eq!(bytes[1], 2);
+    assert_eq!(bytes[2], 6);
+    assert_eq!(bytes[3], 0xe9);
+    assert_eq!(&bytes[4..8], &[0x01, 0x02, 0x03, 0x04]);
+    assert_eq!(&bytes[8..12], &[0x05, 0x06, 0x07, 0x08]);
+    assert_eq!(&bytes[12..16], &[192, 0, 2, 1]);
+    assert_eq!(&bytes[16..24], &[0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17]);
+    assert_eq!(&bytes[24..32], &[0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27]);
+    assert_eq!(&bytes[32..40], &[0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37]);
+    assert_eq!(&bytes[40..48], &[0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47]);
+    assert_eq!((&bytes[..]).read_bytes::<Packet>().unwrap(), input);
+}
+
+#[test]
+fn secondary_special_reference_ids() {
+    let input = [
+        20u8, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+        46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+        188, 128, 113, 46, 35, 158, 108,
+    ];
+    let cases = [
+        // A chrony server whose reference is its own clock, by its `local` directive.
+        (10, [127, 127, 1, 1], ReferenceIdentifier::Special(SpecialReference::ChronyLocal)),
+        // An ntpd server synchronized to its local clock driver.
+        (10, *b"LOCL", ReferenceIdentifier::Special(SpecialReference::NtpdLocalClock)),
+        (3, [192, 0, 2, 1], ReferenceIdentifier::SecondaryOrClient([192, 0, 2, 1])),
+        // At stratum 1 the identifier names the reference clock.
+        (1, *b"LOCL", ReferenceIdentifier::PrimarySource(PrimarySource::Locl)),
+    ];
+    for &(stratum, reference_id, expected) in &cases {
+        let mut bytes = input;
+        bytes[1] = stratum;
+        bytes[12..16].copy_from_slice(&reference_id);
+        let packet = (&bytes[..]).read_bytes::<Packet>().unwrap();
+        assert_eq!(packet.reference_id, expected);
+        assert_eq!(packet.reference_id_raw(), reference_id);
+
+        let mut output = [0u8; Packet::PACKED_SIZE_BYTES];
+        (&mut output[..]).write_bytes(packet).unwrap();
+        assert_eq!(&output[..], &bytes[..]);
+    }
+}
+
+#[test]
+fn captured_packet_byte_layout() {
+    // A response captured from a stratum 1 CDMA referenced server.
+    let input = [
+        20u8, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+        46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+        188, 128, 113, 46, 35, 158, 108,
+    ];
+    let packet = (&input[..]).read_bytes::<Packet>().unwrap();
+    assert_eq!(input[0] >> 6, packet.leap_indicator as u8);
+    assert_eq!((input[0] >> 3) & 0b111, 2);
+    assert_eq!(input[0] & 0b111, packet.mode as u8);
+    assert_eq!(input[1], packet.stratum.0);
+    assert_eq!(LogSeconds(input[2] as i8), packet.poll);
+    assert_eq!(LogSeconds(input[3] as i8), packet.precision);
+    assert_eq!(&input[12..16], b"CDMA");
+    let word = |offset: usize| {
+        u32::from_be_bytes([input[offset], input[offset + 1], input[offset + 2], input[offset + 3]])
+    };
+    assert_eq!(word(16), packet.reference_timestamp.seconds);
+    assert_eq!(word(20), packet.reference_timestamp.fraction);
+    assert_eq!(word(24), packet.origin_timestamp.seconds);
+    assert_eq!(word(32), packet.receive_timestamp.seconds);
+    assert_eq!(word(40), packet.transmit_timestamp.seconds);
+    assert_eq!(word(44), packet.transmit_timestamp.fraction);
+}
+
+#[test]
+fn default_client_packet_roundtrip() {
+    // The client request as sent by this crate, with every field but the transmit timestamp at
+    // its default.
+    let transmit = TimestampFormat::from_instant_checked(Instant::new(1_600_000_000, 123_456_789));
+    let input = Packet {
+        leap_indicator: LeapIndicator::default(),
+        version: Version::V4,
+        mode: Mode::Client,
+        stratum: Stratum::UNSPECIFIED,
+        poll: LogSeconds(0),
+        precision: LogSeconds(0),
+        root_delay: ShortFormat::default(),
+        root_dispersion: ShortFormat::default(),
+        reference_id: ReferenceIdentifier::PrimarySource(PrimarySource::Null),
+        reference_timestamp: TimestampFormat::default(),
+        origin_timestamp: TimestampFormat::default(),
+        receive_timestamp: TimestampFormat::default(),
+        transmit_timestamp: transmit.unwrap(),
+    };
+    let mut bytes = [0u8; Packet::PACKED_SIZE_BYTES];
+    (&mut bytes[..]).write_bytes(input).unwrap();
+
+    // LI = 0, VN = 4, Mode = 3 and nothing else is set before the transmit timestamp.
+    assert_eq!(bytes[0], 0x23);
+    assert!(bytes[1..40].iter().all(|&b| b == 0));
+    assert_eq!(&bytes[40..44], &(1_600_000_000u32 + 2_208_988_800u32).to_be_bytes());
+    let output = (&bytes[..]).read_bytes::<Packet>().unwrap();
+    assert_eq!(output, input);
+
+    // The transmit timestamp survives the round trip exactly, as does the instant it was created
+    // from.
+    assert_eq!(output.transmit_timestamp, input.transmit_timestamp);
+    let instant = Instant::from(output.transmit_timestamp);
+    assert_eq!(instant, Instant::new(1_600_000_000, 123_456_789));
+}
+
+#[test]
+fn populated_packet_roundtrip() {
+    let input = Packet {
+        leap_indicator: LeapIndicator::SubOne,
+        version: Version::V3,
+        mode: Mode::Server,
+        stratum: Stratum::SECONDARY_MAX,
+        poll: LogSeconds(17),
+        precision: LogSeconds(-29),
+        root_delay: ShortFormat { seconds: 0xfffe, fraction: 0xabcd },
+        root_dispersion: ShortFormat { seconds: 1, fraction: 0xffff },
+        reference_id: ReferenceIdentifier::SecondaryOrClient([10, 20, 30, 40]),
+        reference_timestamp: TimestampFormat { seconds: u32::MAX, fraction: u32::MAX },
+        origin_timestamp: TimestampFormat { seconds: 1, fraction: 1 },
+        receive_timestamp: TimestampFormat { seconds: 0x8000_0000, fraction: 0x7fff_ffff },
+        transmit_timestamp: TimestampFormat { seconds: 0xdead_beef, fraction: 0xcafe_babe },
+    };
+    let mut bytes = [0u8; Packet::PACKED_SIZE_BYTES];
+    (&mut bytes[..]).write_bytes(input).unwrap();
+    assert_eq!((&bytes[..]).read_bytes::<Packet>().unwrap(), input);
+}
+
+#[test]
+fn packet_sanity_tests() {
+    let input = [
+        20u8, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+        46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+        188, 128, 113, 46, 35, 158, 108,
+    ];
+    let packet = (&input[..]).read_bytes::<Packet>().unwrap();
+    let our_transmit = packet.origin_timestamp;
+    let passed = SanityReport {
+        origin_matches: true,
+        timestamps_valid: true,
+        synchronized: true,
+        stratum_valid: true,
+        root_distance_valid: true,
+    };
+    assert_eq!(packet.run_sanity_tests(our_transmit), passed);
+    assert!(packet.run_sanity_tests(our_transmit).passed());
+
+    let other_transmit = TimestampFormat { fraction: 1, ..our_transmit };
+    let report = packet.run_sanity_tests(other_transmit);
+    assert_eq!(report, SanityReport { origin_matches: false, ..passed });
+    assert!(!report.passed());
+
+    let zero_receive = Packet { receive_timestamp: TimestampFormat::default(), ..packet };
+    let report = zero_receive.run_sanity_tests(our_transmit);
+    assert_eq!(report, SanityReport { timestamps_valid: false, ..passed });
+
+    let unknown_leap = Packet { leap_indicator: LeapIndicator::Unknown, ..packet };
+    let report = unknown_leap.run_sanity_tests(our_transmit);
+    assert_eq!(report, SanityReport { synchronized: false, ..passed });
+
+    let future_reference = Packet {
+        reference_timestamp: TimestampFormat { seconds: u32::MAX, fraction: 0 },
+        ..packet
+    };
+    let report = future_reference.run_sanity_tests(our_transmit);
+    assert_eq!(report, SanityReport { synchronized: false, ..passed });
+
+    let unspecified = Packet { stratum: Stratum::UNSPECIFIED, ..packet };
+    let report = unspecified.run_sanity_tests(our_transmit);
+    assert_eq!(report, SanityReport { stratum_valid: false, ..passed });
+
+    let dispersed = Packet { root_dispersion: ShortFormat { seconds: 16, fraction: 0 }, ..packet };
+    let report = dispersed.run_sanity_tests(our_transmit);
+    assert_eq!(report, SanityReport { root_distance_valid: false, ..passed });
+}
+
+#[test]
+fn raw_extension_field_round_trip() {
+    let field = RawExtensionField { field_type: 0x0104, value: vec![1, 2, 3, 4, 5] };
+    assert_eq!(field.packed_size_bytes(), 16);
+    let mut bytes = Vec::new();
+    bytes.write_bytes(&field).unwrap();
+    assert_eq!(bytes, [1, 4, 0, 16, 1, 2, 3, 4, 5, 0, 0, 0, 0, 0, 0, 0]);
+
+    let uid = RawExtensionField { field_type: 0x0104, value: vec![7; 32] };
+    bytes.write_bytes(&uid).unwrap();
+    let fields = RawExtensionField::parse_all(&bytes).unwrap();
+    assert_eq!(fields.len(), 2);
+    assert_eq!(fields[0].value, [1, 2, 3, 4, 5, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(fields[1], uid);
+
+    assert!(RawExtensionField::parse_all(&bytes[..bytes.len() - 4]).is_err());
+    assert!(RawExtensionField::parse_all(&[0, 1, 0, 6, 0, 0]).is_err());
+}
+
+#[test]
+fn unknown_reference_id_round_trip() {
+    let mut bytes = [0u8; Packet::PACKED_SIZE_BYTES];
+    bytes[0] = 0x24; // version 4, server mode
+    bytes[1] = 1;
+    bytes[12..16].copy_from_slice(b"XVND");
+    bytes[40] = 0xE0;
+
+    let packet = (&bytes[..]).read_bytes::<Packet>().unwrap();
+    assert_eq!(packet.reference_id, ReferenceIdentifier::Unknown(*b"XVND"));
+    assert_eq!(packet.reference_id_raw(), *b"XVND");
+    let mut written = [0u8; Packet::PACKED_SIZE_BYTES];
+    (&mut written[..]).write_bytes(packet).unwrap();
+    assert_eq!(written, bytes);
+
+    // Known identifiers are reproduced exactly too.
+    bytes[12..16].copy_from_slice(b"GPS\0");
+    let packet = (&bytes[..]).read_bytes::<Packet>().unwrap();
+    assert_eq!(packet.reference_id, ReferenceIdentifier::PrimarySource(PrimarySource::Gps));
+    assert_eq!(packet.reference_id_raw(), *b"GPS\0");
+
+    // As is an unrecognized code at stratum 0.
+    bytes[1] = 0;
+    bytes[12..16].copy_from_slice(b"ABCD");
+    let packet = (&bytes[..]).read_bytes::<Packet>().unwrap();
+    assert_eq!(packet.reference_id, ReferenceIdentifier::Unknown(*b"ABCD"));
+    (&mut written[..]).write_bytes(packet).unwrap();
+    assert_eq!(written, bytes);
+}
+
+#[test]
+fn packet_array_round_trip() {
+    let captured = [
+        20u8, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+        46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+        188, 128, 113, 46, 35, 158, 108,
+    ];
+    let packet = Packet::try_from(captured).unwrap();
+    assert_eq!(packet.reference_id, ReferenceIdentifier::PrimarySource(PrimarySource::Cdma));
+    assert_eq!(<[u8; 48]>::from(&packet), captured);
+
+    let mut reserved_stratum = captured;
+    reserved_stratum[1] = 200;
+    match Packet::try_from(reserved_stratum) {
+        Err(Error::InvalidPacket(_)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn protocol_size_constants() {
+    assert_eq!(nippy::protocol::NTP_PORT, 123);
+    assert_eq!(nippy::protocol::MAX_PACKET_SIZE, 65_507);
+    assert_eq!(nippy::protocol::MAC_KEY_ID_SIZE_BYTES, 4);
+    assert_eq!(nippy::protocol::MAC_MD5_SIZE_BYTES, 20);
+    assert_eq!(nippy::protocol::MAC_SHA1_SIZE_BYTES, 24);
+    assert_eq!(Packet::PACKED_SIZE_BYTES, 48);
+    assert_eq!(nippy::MAX_RECV_BUFFER_SIZE_BYTES, nippy::protocol::MAX_PACKET_SIZE);
+}
+
+#[test]
+fn log_seconds_are_signed() {
+    let mut bytes = [0u8; Packet::PACKED_SIZE_BYTES];
+    bytes[0] = 0b00_100_100;
+    bytes[1] = 2;
+    bytes[2] = 0x06;
+    bytes[3] = 0xEC;
+    let packet = (&bytes[..]).read_bytes::<Packet>().unwrap();
+    assert_eq!(packet.poll, LogSeconds(6));
+    assert_eq!(packet.precision, LogSeconds(-20));
+    assert_eq!(packet.poll.as_duration(), Duration::from_secs(64));
+    assert_eq!(packet.precision.as_duration(), Duration::from_nanos(954));
+
+    assert_eq!(LogSeconds::from_duration(Duration::from_micros(1)), LogSeconds(-20));
+    assert_eq!(LogSeconds::from_duration(Duration::from_secs(1024)), LogSeconds(10));
+    assert_eq!(LogSeconds::from_duration(Duration::from_secs(0)), LogSeconds(i8::MIN));
+    assert_eq!(LogSeconds(i8::MIN).as_duration(), Duration::from_secs(0));
+}
+
+#[test]
+fn packet_header_matches_fields() {
+    let captured = [
+        20u8, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+        46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+        188, 128, 113, 46, 35, 158, 108,
+    ];
+    let packet = (&captured[..]).read_bytes::<Packet>().unwrap();
+    let header = packet.header();
+    assert_eq!(
+        header,
+        NtpHeader {
+            leap_indicator: LeapIndicator::NoWarning,
+            version: Version::V2,
+            mode: Mode::Server,
+            stratum: Stratum::PRIMARY,
+            poll: LogSeconds(3),
+            precision: LogSeconds(-16),
+        }
+    );
+    assert!(header.is_response());
+    assert!(!header.is_request());
+
+    let request = NtpHeader { mode: Mode::Client, ..header };
+    assert!(request.is_request());
+    assert!(!request.is_response());
+    let control = NtpHeader { mode: Mode::NtpControlMessage, ..header };
+    assert!(!control.is_request() && !control.is_response());
+}
+
+#[test]
+fn quick_parse_and_build_match_packet() {
+    let input = [
+        20u8, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+        46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+        188, 128, 113, 46, 35, 158, 108,
+    ];
+    let packet = (&input[..]).read_bytes::<Packet>().unwrap();
+    assert_eq!(parse_transmit_timestamp(&input).unwrap(), packet.transmit_timestamp);
+    assert!(parse_transmit_timestamp(&input[..47]).is_err());
+
+    let client = Packet {
+        leap_indicator: LeapIndicator::NoWarning,
+        version: Version::V4,
+        mode: Mode::Client,
+        stratum: Stratum::UNSPECIFIED,
+        poll: LogSeconds(0),
+        precision: LogSeconds(0),
+        root_delay: ShortFormat::default(),
+        root_dispersion: ShortFormat::default(),
+        reference_id: ReferenceIdentifier::Unknown([0; 4]),
+        reference_timestamp: TimestampFormat::default(),
+        origin_timestamp: TimestampFormat::default(),
+        receive_timestamp: TimestampFormat::default(),
+        transmit_timestamp: TimestampFormat::default(),
+    };
+    assert_eq!(quick_build_client(), <[u8; 48]>::from(&client));
+}
+
+#[test]
+fn extension_fields_decode_by_type() {
+    let bytes = [
+        // Unique Identifier.
+        0x01, 0x04, 0x00, 0x14, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+        // NTS Cookie.
+        0x02, 0x04, 0x00, 0x10, 0xc0, 0x0c, 0x1e, 0x5a, 0xc0, 0x0c, 0x1e, 0x5a, 0xc0, 0x0c,
+        0x1e, 0x5a,
+        // NTS Cookie Placeholder, as long as the cookie.
+        0x03, 0x04, 0x00, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        // NTS Authenticator with a 3 byte nonce padded to 4 and an 8 byte ciphertext.
+        0x04, 0x04, 0x00, 0x14, 0x00, 0x03, 0x00, 0x08, 0xaa, 0xbb, 0xcc, 0, 1, 2, 3, 4, 5, 6,
+        7, 8,
+        // An experimental field.
+        0x7f, 0x00, 0x00, 0x10, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9,
+    ];
+    let fields = ExtensionField::parse_all(&bytes).unwrap();
+    assert_eq!(
+        fields,
+        vec![
+            ExtensionField::UniqueIdentifier((1..=16).collect()),
+            ExtensionField::NtsCookie([0xc0, 0x0c, 0x1e, 0x5a].repeat(3)),
+            ExtensionField::NtsCookiePlaceholder,
+            ExtensionField::NtsAuthenticator {
+                nonce: vec![0xaa, 0xbb, 0xcc],
+                ciphertext: (1..=8).collect(),
+            },
+            ExtensionField::Unknown { field_type: 0x7f00, value: vec![9; 12] },
+        ]
+    );
+    let types: Vec<_> = fields.iter().map(ExtensionField::field_type).collect();
+    assert_eq!(
+        types,
+        vec![
+            UNIQUE_IDENTIFIER_FIELD_TYPE,
+            NTS_COOKIE_FIELD_TYPE,
+            NTS_COOKIE_PLACEHOLDER_FIELD_TYPE,
+            NTS_AUTHENTICATOR_FIELD_TYPE,
+            0x7f00,
+        ]
+    );
+    assert_eq!(ExtensionField::find(&fields, NTS_COOKIE_FIELD_TYPE), Some(&fields[1]));
+    assert_eq!(ExtensionField::find(&fields, 0x0505), None);
+    assert_eq!((&bytes[..20]).read_bytes::<ExtensionField>().unwrap(), fields[0]);
+
+    // A ciphertext longer than the field.
+    let mut overlong = bytes[52..72].to_vec();
+    assert_eq!(ExtensionField::parse_all(&overlong).unwrap(), vec![fields[3].clone()]);
+    overlong[7] = 0x09;
+    assert!(ExtensionField::parse_all(&overlong).is_err());
+}
+
+#[test]
+fn short_format_bytes_are_big_endian() {
+    let short_format = ShortFormat { seconds: 1, fraction: 0x8000 };
+    let mut bytes = Vec::new();
+    bytes.write_bytes(short_format).unwrap();
+    assert_eq!(bytes, [0x00, 0x01, 0x80, 0x00]);
+    assert_eq!((&bytes[..]).read_bytes::<ShortFormat>().unwrap(), short_format);
+
+    // Each half is written whole and in order.
+    let short_format = ShortFormat { seconds: 0x0102, fraction: 0x0304 };
+    let mut bytes = Vec::new();
+    bytes.write_bytes(short_format).unwrap();
+    assert_eq!(bytes, [0x01, 0x02, 0x03, 0x04]);
+    assert_eq!((&bytes[..]).read_bytes::<ShortFormat>().unwrap(), short_format);
+    assert_eq!(ShortFormat::PACKED_SIZE_BYTES, 4);
+    assert!((&bytes[..3]).read_bytes::<ShortFormat>().is_err());
+}
+
+#[test]
+fn timestamp_format_bytes_are_big_endian() {
+    let timestamp = TimestampFormat { seconds: 1, fraction: 0x8000_0000 };
+    let mut bytes = Vec::new();
+    bytes.write_bytes(timestamp).unwrap();
+    assert_eq!(bytes, [0x00, 0x00, 0x00, 0x01, 0x80, 0x00, 0x00, 0x00]);
+    assert_eq!((&bytes[..]).read_bytes::<TimestampFormat>().unwrap(), timestamp);
+
+    let timestamp = TimestampFormat { seconds: 0x0102_0304, fraction: 0x0506_0708 };
+    let mut bytes = Vec::new();
+    bytes.write_bytes(timestamp).unwrap();
+    assert_eq!(bytes, [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]);
+    assert_eq!((&bytes[..]).read_bytes::<TimestampFormat>().unwrap(), timestamp);
+    assert_eq!(TimestampFormat::PACKED_SIZE_BYTES, 8);
+    assert!((&bytes[..7]).read_bytes::<TimestampFormat>().is_err());
+}
+
+#[test]
+fn undecodable_fields_are_named_without_panicking() {
+    // Every leap indicator, version and mode a header byte can hold decodes.
+    for first in 0..=255u8 {
+        let mut bytes = [0u8; Packet::PACKED_SIZE_BYTES];
+        bytes[0] = first;
+        bytes[1] = 2;
+        let packet = Packet::try_from(bytes).unwrap();
+        assert_eq!(packet.leap_indicator as u8, first >> 6);
+        assert_eq!(packet.mode as u8, first & 0b111);
+    }
+
+    // Strata beyond 16 are reserved.
+    let mut bytes = [0u8; Packet::PACKED_SIZE_BYTES];
+    bytes[0] = 0b00_100_100;
+    bytes[1] = 200;
+    match Packet::try_from(bytes) {
+        Err(Error::InvalidPacket(ref reason)) => assert!(reason.contains("stratum 200")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
@@ -0,0 +1,147 @@
+#![cfg(all(feature = "proxy", feature = "async-std"))]
+
+extern crate nippy;
+
+mod common;
+
+use async_std::task;
+use common::MockServer;
+use nippy::{Error, NtpClient};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A SOCKS5 proxy accepting one username and password and relaying a single datagram, and its
+// response, per UDP ASSOCIATE. Counts the datagrams relayed.
+struct Socks5Stub {
+    addr: SocketAddr,
+    relayed: Arc<AtomicUsize>,
+}
+
+impl Socks5Stub {
+    fn start(username: &'static str, password: &'static str) -> Self {
+        Socks5Stub::start_stalling(username, password, Duration::from_secs(0))
+    }
+
+    // As `start`, but pausing for `stall` before each step of the handshake.
+    fn start_stalling(username: &'static str, password: &'static str, stall: Duration) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let relayed = Arc::new(AtomicUsize::new(0));
+        let count = relayed.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let count = count.clone();
+                thread::spawn(move || {
+                    let _ = associate(stream.unwrap(), username, password, stall, &count);
+                });
+            }
+        });
+        Socks5Stub { addr, relayed }
+    }
+}
+
+fn associate(
+    mut control: TcpStream,
+    username: &str,
+    password: &str,
+    stall: Duration,
+    relayed: &AtomicUsize,
+) -> io::Result<()> {
+    let mut header = [0u8; 2];
+    control.read_exact(&mut header)?;
+    let mut methods = vec![0u8; header[1] as usize];
+    control.read_exact(&mut methods)?;
+    thread::sleep(stall);
+    if !methods.contains(&2) {
+        return control.write_all(&[5, 0xff]);
+    }
+    control.write_all(&[5, 2])?;
+    let mut credentials = [0u8; 2];
+    control.read_exact(&mut credentials)?;
+    let mut user = vec![0u8; credentials[1] as usize];
+    control.read_exact(&mut user)?;
+    let mut len = [0u8; 1];
+    control.read_exact(&mut len)?;
+    let mut pass = vec![0u8; len[0] as usize];
+    control.read_exact(&mut pass)?;
+    if user != username.as_bytes() || pass != password.as_bytes() {
+        return control.write_all(&[1, 1]);
+    }
+    thread::sleep(stall);
+    control.write_all(&[1, 0])?;
+
+    // UDP ASSOCIATE with an IPv4 address.
+    let mut request = [0u8; 10];
+    control.read_exact(&mut request)?;
+    assert_eq!(&request[..4], &[5, 3, 0, 1]);
+    let relay = UdpSocket::bind("127.0.0.1:0")?;
+    let port = relay.local_addr()?.port().to_be_bytes();
+    thread::sleep(stall);
+    control.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, port[0], port[1]])?;
+
+    let mut buf = [0u8; 2048];
+    let (len, client) = relay.recv_from(&mut buf)?;
+    assert_eq!(&buf[..4], &[0, 0, 0, 1]);
+    let target = SocketAddr::from((
+        [buf[4], buf[5], buf[6], buf[7]],
+        u16::from_be_bytes([buf[8], buf[9]]),
+    ));
+    let upstream = UdpSocket::bind("127.0.0.1:0")?;
+    upstream.send_to(&buf[10..len], target)?;
+    let mut response = buf[..10].to_vec();
+    let len = upstream.recv(&mut buf)?;
+    response.extend_from_slice(&buf[..len]);
+    relay.send_to(&response, client)?;
+    relayed.fetch_add(1, Ordering::SeqCst);
+
+    // Hold the association until the client closes the connection.
+    let _ = control.read(&mut buf);
+    Ok(())
+}
+
+#[test]
+fn request_is_relayed_through_socks5_proxy() {
+    let server = MockServer::start(common::reply);
+    let proxy = Socks5Stub::start("ntp", "secret");
+
+    let client = NtpClient::new().socks5_proxy(proxy.addr, Some(("ntp", "secret")));
+    let measurement = task::block_on(client.request(server.addr())).unwrap();
+    assert!(measurement.offset().as_secs_f64().abs() < 0.05);
+    assert_eq!(proxy.relayed.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn socks5_handshake_is_excluded_from_delay() {
+    let server = MockServer::start(common::reply);
+    let stall = Duration::from_millis(200);
+    let proxy = Socks5Stub::start_stalling("ntp", "secret", stall);
+
+    let client = NtpClient::new().socks5_proxy(proxy.addr, Some(("ntp", "secret")));
+    let started = Instant::now();
+    let measurement = task::block_on(client.request(server.addr())).unwrap();
+    assert!(started.elapsed() >= 3 * stall);
+    assert!(measurement.delay().as_secs_f64() < 0.1, "{:?}", measurement.delay());
+    assert!(measurement.offset().as_secs_f64().abs() < 0.05);
+}
+
+#[test]
+fn socks5_proxy_rejects_bad_credentials() {
+    let server = MockServer::start(common::reply);
+    let proxy = Socks5Stub::start("ntp", "secret");
+
+    let client = NtpClient::new().socks5_proxy(proxy.addr, Some(("ntp", "wrong")));
+    match task::block_on(client.request(server.addr())) {
+        Err(Error::Io(ref err)) if err.kind() == io::ErrorKind::PermissionDenied => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let client = NtpClient::new().socks5_proxy(proxy.addr, None);
+    match task::block_on(client.request(server.addr())) {
+        Err(Error::Io(ref err)) if err.kind() == io::ErrorKind::PermissionDenied => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(proxy.relayed.load(Ordering::SeqCst), 0);
+}
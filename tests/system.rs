@@ -0,0 +1,13 @@
+#![cfg(target_os = "linux")]
+
+extern crate nippy;
+
+use nippy::system::{self, ClockState};
+
+#[test]
+fn local_sync_status_is_readable() {
+    let status = system::local_sync_status().unwrap();
+    if status.synchronized {
+        assert_ne!(status.state, ClockState::Error);
+    }
+}
@@ -0,0 +1,63 @@
+#![cfg(feature = "async-std")]
+
+extern crate log;
+extern crate nippy;
+
+mod common;
+
+use async_std::task;
+use common::MockServer;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use nippy::protocol::{ReadBytes, TimestampFormat};
+use nippy::NtpClient;
+use std::sync::{Arc, Mutex};
+
+// A logger keeping the messages logged by the crate, installed once for the whole test binary.
+struct CaptureLogger(Mutex<Vec<String>>);
+
+impl Log for CaptureLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Trace
+    }
+
+    fn log(&self, record: &Record) {
+        if record.target().starts_with("nippy") {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CaptureLogger = CaptureLogger(Mutex::new(Vec::new()));
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[test]
+fn trace_logs_the_exchange() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+
+    let exchanged = Arc::new(Mutex::new((Vec::new(), Vec::new())));
+    let server = {
+        let exchanged = exchanged.clone();
+        MockServer::start_raw(move |request| {
+            let transmit: TimestampFormat = (&request[40..48]).read_bytes().unwrap();
+            let response = common::to_bytes(&common::reply(transmit));
+            *exchanged.lock().unwrap() = (request.to_vec(), response.clone());
+            Some(response)
+        })
+    };
+    task::block_on(NtpClient::new().request(server.addr())).unwrap();
+
+    let (request, response) = exchanged.lock().unwrap().clone();
+    let logs = LOGGER.0.lock().unwrap();
+    let logged = |expected: &str| logs.iter().any(|message| message == expected);
+    assert!(logged(&format!("sent 48 bytes: {}", hex(&request))), "{:#?}", logs);
+    assert!(logged(&format!("received 48 bytes: {}", hex(&response))), "{:#?}", logs);
+    assert!(logs.iter().any(|message| message.starts_with("request: version 4, mode Client")));
+    assert!(logs.iter().any(|message| message.starts_with("timestamps: t1 ")));
+    assert!(logs.iter().any(|message| message.starts_with("offset ")));
+}